@@ -1,5 +1,7 @@
 MEMORY
 {
-  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  /* last two 2K pages are kept for settings, see SETTINGS_FLASH_OFFSET */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 252K
+  SETTINGS : ORIGIN = 0x0803F000, LENGTH = 4K
   RAM : ORIGIN = 0x20000000, LENGTH = 64K
 }
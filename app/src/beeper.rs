@@ -0,0 +1,37 @@
+//! Piezo beeper on PA2, square wave from TIM2 channel 3 PWM
+
+use embedded_hal::Pwm as _;
+use stm32f1xx_hal::{prelude::*, pwm::Channel};
+
+use crate::types::BeeperPwm;
+
+pub struct Beeper {
+    pwm: BeeperPwm,
+    /// what's playing
+    freq_hz: Option<u16>,
+}
+
+impl Beeper {
+    pub fn new(mut pwm: BeeperPwm) -> Self {
+        pwm.disable(Channel::C3);
+        Beeper { pwm, freq_hz: None }
+    }
+
+    /// 50% duty square wave at `freq_hz`, `None` for silence.
+    /// Cheap when nothing changes, meant to be called every tick.
+    pub fn play(&mut self, freq_hz: Option<u16>) {
+        if freq_hz == self.freq_hz {
+            return;
+        }
+        match freq_hz {
+            Some(f) => {
+                self.pwm.set_period((f as u32).hz());
+                let duty = self.pwm.get_max_duty() / 2;
+                self.pwm.set_duty(Channel::C3, duty);
+                self.pwm.enable(Channel::C3);
+            }
+            None => self.pwm.disable(Channel::C3),
+        }
+        self.freq_hz = freq_hz;
+    }
+}
@@ -8,33 +8,86 @@ use panic_halt as _;
             dispatchers = [EXTI4, FSMC, TAMPER], // Full list in  stm32f1::stm32f103::Interrupt
             )]
 mod app {
-    use cardiac_monitor::board::Board;
-    use cardiac_monitor::model::{Max3012Sample, UIModel};
+    use cardiac_monitor::beeper::Beeper;
+    use cardiac_monitor::board::{configure_sensor, Board};
+    use cardiac_monitor::flash::SettingsStorage;
+    use cardiac_monitor::lcd::Rotation;
+    use cardiac_monitor::usb::{OtgFsBus, UsbBusType, UsbDeviceType, UsbSerial, UsbSerialType};
     use cardiac_monitor::{consts::*, lcdui::*, types::*};
-    use cardiac_monitor_shared::circ::Circ;
+    use cardiac_monitor_shared::{
+        alarms::{AlarmConfig, AlarmEngine},
+        calibration::Spo2,
+        circ::Circ,
+        console::{AlarmSetting, Command, LineBuffer, Query, HELP, MAX_LINE_LEN},
+        fifo::{FifoRead, FifoSample, FIFO_DEPTH},
+        led_control::{LedControlConfig, LedController},
+        link::{Transport, TxQueue},
+        model::{Max3012Sample, ModelConfig, UIModel},
+        ppg::PpgSensor,
+        presence::{FingerEvent, FingerState},
+        protocol::Message,
+        screens::{Action, Frame},
+        sensor::SensorSettings,
+        settings::Settings,
+        timing::SampleStamper,
+        tones::ToneSequencer,
+        touch::{CalibrationState, RawTouch, TouchCalibrator},
+        trends::{Trends, TREND_INTERVAL_MS, TREND_NUM_POINTS},
+    };
 
+    use core::convert::TryFrom;
+    use core::fmt::{self, Write};
+    use heapless::{Deque, String, Vec};
     use rtic::Monotonic;
+    use stm32f1::stm32f107::Interrupt;
+    use stm32f1xx_hal::{gpio::ExtiPin, i2c};
     use systick_monotonic::*;
+    use usb_device::{bus::UsbBusAllocator, prelude::*};
+    use usbd_serial::SerialPort;
 
     #[shared]
     struct Shared {
         max30102_samples: Circ<Max3012Sample, MAX30102_NUM_SAMPLES>,
+        serial_tx: TxQueue<SERIAL_TX_QUEUE_LEN>,
+        /// Binary frames on, console replies go out regardless
+        streaming: bool,
+        console_commands: Deque<Command, CONSOLE_QUEUE_LEN>,
+        sensor: Sensor,
+        max30102_stamper: SampleStamper,
+        /// Sensor die temperature, °C
+        temperature: Option<f32>,
+        tones: ToneSequencer,
+        /// Pressed here, `None` when not
+        touch: Option<RawTouch>,
+        touch_int: TouchIntPin,
     }
 
     #[local]
     struct Local {
         test_pin: TestPin,
-        _beeper: BeeperPin,
+        beeper: Beeper,
+        touch_panel: Touch,
         lcdui: LcdUI,
-        max30102_sensor: Max30102Sensor,
-        ui_model: UIModel,
+        max30102_int: Max30102IntPin,
+        settings: Settings,
+        settings_store: SettingsStorage,
+        ui_model: UIModel<MAX30102_NUM_SAMPLES>,
+        led_control: LedController,
+        alarms: AlarmEngine,
+        trends: Trends<TREND_NUM_POINTS>,
+        usb_dev: UsbDeviceType,
+        usb_serial: UsbSerialType,
+        console_line: LineBuffer<MAX_LINE_LEN>,
     }
 
     // https://github.com/rtic-rs/cortex-m-rtic/blob/master/examples/schedule.rs
     #[monotonic(binds = SysTick, default = true)]
     type MyMono = Systick<100>; // 100 Hz / 10 ms granularity
 
-    #[init]
+    #[init(local = [
+        ep_memory: [u32; 1024] = [0; 1024],
+        usb_bus: Option<UsbBusAllocator<UsbBusType>> = None,
+    ])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         let mut core = cx.core;
         let device = cx.device;
@@ -42,73 +95,592 @@ mod app {
         let Board {
             test_pin,
             beeper,
-            max30102_sensor,
+            sensor,
+            max30102_int,
+            touch,
+            touch_int,
             lcd,
+            usb,
+            settings,
+            settings_store,
         } = Board::init(&mut core, device);
 
+        let usb_bus: &'static _ = cx
+            .local
+            .usb_bus
+            .insert(OtgFsBus::new(usb, cx.local.ep_memory));
+        let usb_serial = UsbSerial(SerialPort::new(usb_bus));
+        let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(USB_VID_PID.0, USB_VID_PID.1))
+            .manufacturer("cardiac-monitor")
+            .product("MAX30102 cardiac monitor")
+            .serial_number("0001")
+            .device_class(usbd_serial::USB_CLASS_CDC)
+            .build();
+
         let mono = Systick::new(core.SYST, SYS_FREQ.0);
-        sample::spawn_after(500.millis()).unwrap();
+
+        temperature::spawn().unwrap();
+        tone::spawn().unwrap();
 
         (
             Shared {
                 max30102_samples: Circ::new(Max3012Sample::zero()),
+                serial_tx: TxQueue::new(),
+                streaming: true,
+                console_commands: Deque::new(),
+                sensor,
+                max30102_stamper: SampleStamper::new(settings.sensor.sample_rate_hz()),
+                temperature: None,
+                tones: ToneSequencer::new(),
+                touch: None,
+                touch_int,
             },
             Local {
                 test_pin,
-                _beeper: beeper,
-                lcdui: LcdUI::new(lcd),
-                max30102_sensor,
-                ui_model: UIModel::new(),
+                beeper,
+                touch_panel: touch,
+                lcdui: LcdUI::new(
+                    lcd,
+                    Rotation::try_from(settings.rotation as u32).unwrap_or(Rotation::R90),
+                    settings.theme,
+                ),
+                max30102_int,
+                settings,
+                settings_store,
+                ui_model: ui_model(&settings),
+                led_control: led_control(&settings.sensor),
+                alarms: AlarmEngine::new(AlarmConfig::default(), settings.alarms),
+                trends: Trends::new(TREND_INTERVAL_MS),
+                usb_dev,
+                usb_serial,
+                console_line: LineBuffer::new(),
             },
             init::Monotonics(mono),
         )
     }
 
-    #[idle(shared = [max30102_samples], local = [lcdui,ui_model,test_pin])]
+    #[idle(shared = [max30102_samples, serial_tx, streaming, console_commands, sensor, max30102_stamper, temperature, tones, touch],
+           local = [lcdui, ui_model, led_control, alarms, trends, settings, settings_store, test_pin])]
     fn idle(mut ctx: idle::Context) -> ! {
         let lcdui = ctx.local.lcdui;
         let ui_model = ctx.local.ui_model;
+        let led_control = ctx.local.led_control;
+        let alarms = ctx.local.alarms;
+        let trends = ctx.local.trends;
+        let settings = ctx.local.settings;
+        let settings_store = ctx.local.settings_store;
         lcdui.init().unwrap();
 
         let test_pin = ctx.local.test_pin;
 
-        let mut oxi_r_samples = [0.0; MAX30102_NUM_SAMPLES];
-        let mut oxi_ir_samples = [0.0; MAX30102_NUM_SAMPLES];
+        let mut samples = [Max3012Sample::zero(); MAX30102_NUM_SAMPLES];
+        let mut total_samples = 0;
+        let mut last_beat_ms: Option<u32> = None;
+        // calibration screen is up while there's one of these
+        let mut touch_calibrator: Option<TouchCalibrator> = None;
+        let mut calibration_state: Option<CalibrationState> = None;
 
         loop {
+            while let Some(cmd) = ctx.shared.console_commands.lock(|c| c.pop_front()) {
+                run_command(
+                    cmd,
+                    &mut ctx.shared,
+                    lcdui,
+                    ui_model,
+                    led_control,
+                    alarms,
+                    &mut touch_calibrator,
+                    settings,
+                    settings_store,
+                );
+            }
+
             test_pin.set_high();
-            ctx.shared.max30102_samples.lock(|ss| {
-                for (i, Max3012Sample { r, ir }) in ss.iter().enumerate() {
-                    oxi_r_samples[i] = r;
-                    oxi_ir_samples[i] = ir;
+            let num_new = ctx.shared.max30102_samples.lock(|ss| {
+                for (i, s) in ss.iter().enumerate() {
+                    samples[i] = s;
                 }
+                let num_new = ss.total_added().wrapping_sub(total_samples);
+                total_samples = ss.total_added();
+                num_new
             });
             test_pin.set_low();
 
-            ui_model.update_from_samples(&oxi_r_samples, &oxi_ir_samples);
-            lcdui.render(ui_model).unwrap();
+            ui_model.set_temperature(ctx.shared.temperature.lock(|t| *t));
+            let finger_event = ui_model.update_from_samples(&samples, num_new);
+
+            // don't keep stale samples from the previous finger around
+            if finger_event == Some(FingerEvent::Removed) {
+                ctx.shared
+                    .max30102_samples
+                    .lock(|ss| ss.clear(Max3012Sample::zero()));
+
+                // next finger starts from the configured current
+                let old = led_control.amplitudes();
+                led_control.reset(settings.sensor.led_amplitude);
+                if led_control.amplitudes() != old {
+                    ctx.shared
+                        .sensor
+                        .lock(|sensor| sensor.set_led_amplitudes(led_control.amplitudes()))
+                        .unwrap();
+                }
+            } else if ui_model.finger_state() != FingerState::Absent {
+                let new_start = MAX30102_NUM_SAMPLES - num_new.min(MAX30102_NUM_SAMPLES);
+                for s in samples[new_start..].iter().filter(|s| !s.is_placeholder()) {
+                    if let Some(amplitudes) = led_control.update(s.r, s.ir) {
+                        ctx.shared
+                            .sensor
+                            .lock(|sensor| sensor.set_led_amplitudes(amplitudes))
+                            .unwrap();
+                        ui_model.on_led_step();
+                    }
+                }
+            }
+
+            let now_ms = monotonics::now().duration_since_epoch().to_millis() as u32;
+
+            // beats stay in the window for a while, only the new ones count
+            let beats = &ui_model.ir.heartbeats;
+            let beat_ms = |i: usize| ui_model.ir.sample_time_ms(beats[i].low_idx);
+            let new_beats = (0..beats.len())
+                .find(|i| {
+                    last_beat_ms.map_or(true, |last| (beat_ms(*i).wrapping_sub(last) as i32) > 0)
+                })
+                .unwrap_or(beats.len())..beats.len();
+            if let Some(last) = new_beats.clone().last() {
+                last_beat_ms = Some(beat_ms(last));
+                // one tick for however many beats turned up, nothing while settling
+                if ui_model.finger_state() == FingerState::Present {
+                    let spo2 = ui_model.spo2().and_then(Spo2::value);
+                    ctx.shared.tones.lock(|t| t.beat(now_ms, spo2));
+                }
+            }
+
+            let spo2 = ui_model.spo2().and_then(Spo2::value);
+            alarms.update(now_ms, ui_model.heart_rate_bpm(), spo2);
+            trends.update(now_ms, ui_model.heart_rate_bpm(), spo2);
+            ctx.shared
+                .tones
+                .lock(|t| t.set_alarm(now_ms, alarms.tone(now_ms)));
+
+            (&mut ctx.shared.serial_tx, &mut ctx.shared.streaming).lock(|tx, streaming| {
+                if !*streaming {
+                    return;
+                }
+                for i in new_beats {
+                    tx.push(&Message::Beat {
+                        time_ms: beat_ms(i),
+                    });
+                }
+                tx.push(&Message::Vitals {
+                    time_ms: now_ms,
+                    heart_rate_bpm: ui_model.heart_rate_bpm(),
+                    spo2: ui_model.spo2(),
+                    quality: ui_model.quality(),
+                    finger: ui_model.finger_state(),
+                });
+                if let Some(stats) = ui_model.hrv_stats() {
+                    tx.push(&Message::Hrv {
+                        time_ms: now_ms,
+                        stats,
+                    });
+                }
+            });
+            rtic::pend(Interrupt::OTG_FS);
+
+            let calibrator = match touch_calibrator.as_mut() {
+                Some(calibrator) => calibrator,
+                None => {
+                    let frame = Frame {
+                        model: ui_model,
+                        trends,
+                        alarm: alarms.highest(now_ms),
+                        alarm_limits: settings.alarms,
+                        pulse_tone: ctx.shared.tones.lock(|t| t.pulse_tone()),
+                        rotation: lcdui.rotation() as u8,
+                    };
+                    let calibration = settings.touch_calibration.unwrap_or_default();
+                    let touch = ctx.shared.touch.lock(|t| *t).and_then(|raw| {
+                        calibration.to_screen(raw, TFT_SIZE, lcdui.rotation() as u8)
+                    });
+                    // run next time around, replies included
+                    if let Some(action) = lcdui.touch(touch, &frame) {
+                        ctx.shared.console_commands.lock(|c| {
+                            for cmd in action_commands(action) {
+                                let _ = c.push_back(cmd);
+                            }
+                        });
+                    }
+                    lcdui.render(&frame).unwrap();
+                    continue;
+                }
+            };
+            let touch = ctx.shared.touch.lock(|t| *t);
+            let state = calibrator.update(now_ms, touch);
+            if calibration_state == Some(state) {
+                continue;
+            }
+            calibration_state = Some(state);
+            match state {
+                CalibrationState::Target { point, step } => {
+                    lcdui.render_touch_target(point, step).unwrap()
+                }
+                CalibrationState::Done(calibration) => {
+                    settings.touch_calibration = Some(calibration);
+                    let saved = settings_store.save(settings);
+                    ctx.shared.serial_tx.lock(|tx| match saved {
+                        Ok(()) => reply(tx, format_args!("touch calibrated")),
+                        Err(e) => reply(tx, format_args!("error: flash {:?}", e)),
+                    });
+                    touch_calibrator = None;
+                    calibration_state = None;
+                    lcdui.redraw();
+                }
+                // same spot touched twice or so, again from the first target
+                CalibrationState::Failed => {
+                    *calibrator = TouchCalibrator::new(TFT_SIZE, lcdui.rotation() as u8);
+                    calibration_state = None;
+                }
+            }
         }
     }
 
-    #[task(shared = [max30102_samples], local = [max30102_sensor], priority = 1)]
+    /// MAX30102 INT pin, sensor has new samples
+    #[task(binds = EXTI3, shared = [max30102_samples, serial_tx, streaming, sensor, max30102_stamper], local = [max30102_int], priority = 1)]
     fn sample(mut ctx: sample::Context) {
-        sample::spawn_at(monotonics::now() + 40.millis()).unwrap();
-
-        let mut max3012_data = [0; 2];
-        let max30102_sensor = ctx.local.max30102_sensor;
-        let samples_read = max30102_sensor.read_fifo(&mut max3012_data).unwrap();
-
-        if samples_read > 0 {
-            ctx.shared.max30102_samples.lock(|ss| {
-                // TODO: docs indicate R,IR sequence
-                // but that gives nonsensical SPO2 values,
-                // something is flipped or off by 1 somewhere
-                let si = Max3012Sample {
-                    r: max3012_data[1] as f32,
-                    ir: max3012_data[0] as f32,
-                };
-                ss.add(si)
+        ctx.local.max30102_int.clear_interrupt_pending_bit();
+
+        // drain everything the sensor has accumulated since the last interrupt
+        let mut fifo_samples = [FifoSample::zero(); FIFO_DEPTH];
+        let FifoRead {
+            num_samples,
+            overflow,
+        } = ctx
+            .shared
+            .sensor
+            .lock(|sensor| sensor.on_interrupt(&mut fifo_samples))
+            .unwrap();
+
+        // lost samples still take up sequence numbers, so the gap shows up in the model
+        let now_ms = monotonics::now().duration_since_epoch().to_millis() as u32;
+        let stamps = ctx
+            .shared
+            .max30102_stamper
+            .lock(|st| st.stamp(now_ms, overflow, num_samples));
+
+        if num_samples > 0 {
+            let samples = &mut ctx.shared.max30102_samples;
+            let tx = &mut ctx.shared.serial_tx;
+            let streaming = &mut ctx.shared.streaming;
+            (samples, tx, streaming).lock(|ss, tx, streaming| {
+                for (s, stamp) in fifo_samples[..num_samples].iter().zip(stamps) {
+                    ss.add(Max3012Sample::new(*s, stamp));
+                    // nobody listening is fine, the queue just drops frames
+                    if *streaming {
+                        tx.push(&Message::Sample {
+                            seq: stamp.seq,
+                            time_ms: stamp.time_ms,
+                            red: s.red,
+                            ir: s.ir,
+                        });
+                    }
+                }
             });
+            rtic::pend(Interrupt::OTG_FS);
+        }
+    }
+
+    /// Reads the die temperature conversion started on the previous run,
+    /// starts the next one
+    #[task(shared = [sensor, temperature, serial_tx, streaming], priority = 1)]
+    fn temperature(mut ctx: temperature::Context) {
+        let celsius = ctx
+            .shared
+            .sensor
+            .lock(|sensor| {
+                let celsius = sensor.read_temperature()?;
+                sensor.start_temperature()?;
+                Ok::<_, i2c::Error>(celsius)
+            })
+            .unwrap();
+
+        if let Some(celsius) = celsius {
+            ctx.shared.temperature.lock(|t| *t = Some(celsius));
+            let time_ms = monotonics::now().duration_since_epoch().to_millis() as u32;
+            (&mut ctx.shared.serial_tx, &mut ctx.shared.streaming).lock(|tx, streaming| {
+                if *streaming {
+                    tx.push(&Message::Temperature { time_ms, celsius });
+                }
+            });
+            rtic::pend(Interrupt::OTG_FS);
+        }
+
+        temperature::spawn_after(TEMPERATURE_PERIOD_S.secs()).unwrap();
+    }
+
+    /// Beeper, plays whatever the sequencer says should be sounding
+    #[task(shared = [tones], local = [beeper], priority = 1)]
+    fn tone(mut ctx: tone::Context) {
+        let now_ms = monotonics::now().duration_since_epoch().to_millis() as u32;
+        let freq_hz = ctx.shared.tones.lock(|t| t.tone(now_ms));
+        ctx.local.beeper.play(freq_hz);
+
+        tone::spawn_after(BEEPER_TICK_MS.millis()).unwrap();
+    }
+
+    /// Touch panel pressed, reading starts
+    #[task(binds = EXTI9_5, shared = [touch_int], priority = 1)]
+    fn pen_down(mut ctx: pen_down::Context) {
+        ctx.shared
+            .touch_int
+            .lock(|int| int.clear_interrupt_pending_bit());
+        // already reading if it doesn't spawn
+        let _ = touch_poll::spawn();
+    }
+
+    /// Reads the touch panel every `TOUCH_POLL_MS` until released
+    #[task(shared = [touch, touch_int], local = [touch_panel], priority = 1)]
+    fn touch_poll(mut ctx: touch_poll::Context) {
+        let raw = ctx.local.touch_panel.read().unwrap();
+
+        // PENIRQ is only valid between conversions, edges from those don't count
+        let pressed = ctx.shared.touch_int.lock(|int| {
+            int.clear_interrupt_pending_bit();
+            int.is_low()
+        });
+        ctx.shared.touch.lock(|t| *t = raw.filter(|_| pressed));
+
+        // a light or noisy reading isn't a release
+        if pressed {
+            touch_poll::spawn_after(TOUCH_POLL_MS.millis()).unwrap();
+        }
+    }
+
+    /// USB OTG FS, host traffic and outgoing frames
+    #[task(binds = OTG_FS, shared = [serial_tx, console_commands], local = [usb_dev, usb_serial, console_line], priority = 2)]
+    fn usb_poll(mut ctx: usb_poll::Context) {
+        let usb_serial = ctx.local.usb_serial;
+        ctx.local.usb_dev.poll(&mut [&mut usb_serial.0]);
+
+        // commands are parsed here, but run from idle, they may take a while
+        let mut buf = [0; 64];
+        let n = usb_serial.read(&mut buf).unwrap_or(0);
+        for b in buf[..n].iter() {
+            match ctx
+                .local
+                .console_line
+                .push(*b)
+                .map(|l| l.and_then(Command::parse))
+            {
+                Some(Ok(cmd)) => {
+                    if ctx
+                        .shared
+                        .console_commands
+                        .lock(|c| c.push_back(cmd))
+                        .is_err()
+                    {
+                        ctx.shared
+                            .serial_tx
+                            .lock(|tx| reply(tx, format_args!("error: busy")));
+                    }
+                }
+                Some(Err(e)) => ctx
+                    .shared
+                    .serial_tx
+                    .lock(|tx| reply(tx, format_args!("error: {}", e))),
+                None => (),
+            }
         }
+
+        // errors mean the host went away, frames pile up until it's back
+        ctx.shared.serial_tx.lock(|tx| {
+            let _ = tx.flush(usb_serial);
+        });
+    }
+
+    /// Filter and beat detection are tuned to the sample rate
+    fn ui_model(settings: &Settings) -> UIModel<MAX30102_NUM_SAMPLES> {
+        UIModel::with_config(ModelConfig {
+            sample_rate_hz: settings.sensor.sample_rate_hz() as f32,
+            spo2_calibration: settings.spo2_calibration,
+            ..ModelConfig::default()
+        })
+    }
+
+    /// DC level averaged over a second
+    fn led_control(sensor: &SensorSettings) -> LedController {
+        let config = LedControlConfig {
+            average_samples: sensor.sample_rate_hz() as usize,
+            ..LedControlConfig::default()
+        };
+        LedController::new(config, sensor.led_amplitude)
+    }
+
+    /// On-screen controls do what the console commands do
+    fn action_commands(action: Action) -> Vec<Command, 2> {
+        let mut commands = Vec::new();
+        let _ = match action {
+            Action::Acknowledge => commands.push(Command::Acknowledge),
+            Action::PulseTone(on) => commands.push(Command::Beep(on)),
+            Action::AlarmLimits(limits) => commands
+                .push(Command::Alarm(AlarmSetting::HeartRate {
+                    low_bpm: limits.hr_low_bpm,
+                    high_bpm: limits.hr_high_bpm,
+                }))
+                .and_then(|_| {
+                    commands.push(Command::Alarm(AlarmSetting::Spo2Low(limits.spo2_low)))
+                }),
+            Action::Rotate(quarter_turns) => commands.push(Command::Rotate(quarter_turns as u32)),
+            Action::CalibrateTouch => commands.push(Command::CalibrateTouch),
+            Action::Save => commands.push(Command::Save),
+        };
+        commands
+    }
+
+    /// Console reply line, dropped if the queue is full
+    fn reply(tx: &mut TxQueue<SERIAL_TX_QUEUE_LEN>, args: fmt::Arguments) {
+        let mut text: String<CONSOLE_REPLY_LEN> = String::new();
+        if text
+            .write_fmt(args)
+            .and_then(|_| text.write_str("\r\n"))
+            .is_ok()
+        {
+            tx.push_text(&text);
+        }
+        rtic::pend(Interrupt::OTG_FS);
+    }
+
+    fn run_command(
+        cmd: Command,
+        shared: &mut idle::SharedResources<'_>,
+        lcdui: &mut LcdUI,
+        ui_model: &mut UIModel<MAX30102_NUM_SAMPLES>,
+        led_control: &mut LedController,
+        alarms: &mut AlarmEngine,
+        touch_calibrator: &mut Option<TouchCalibrator>,
+        settings: &mut Settings,
+        settings_store: &mut SettingsStorage,
+    ) {
+        let mut text: String<CONSOLE_REPLY_LEN> = String::new();
+        let _ = match cmd {
+            Command::Help => write!(text, "{}", HELP.trim_end()),
+            Command::Set(setting) => match setting.apply(&settings.sensor) {
+                Ok(new) => {
+                    let sensor = &mut shared.sensor;
+                    let stamper = &mut shared.max30102_stamper;
+                    let samples = &mut shared.max30102_samples;
+                    (sensor, stamper, samples).lock(|sensor, stamper, samples| {
+                        match configure_sensor(sensor, &new) {
+                            Ok(()) => {
+                                settings.sensor = new;
+
+                                // samples at the old rate are useless to the new filters
+                                stamper.set_sample_rate(new.sample_rate_hz());
+                                samples.clear(Max3012Sample::zero());
+                                *ui_model = self::ui_model(settings);
+                                *led_control = self::led_control(&new);
+                                write!(text, "ok")
+                            }
+                            Err(e) => write!(text, "error: sensor {:?}, reset", e),
+                        }
+                    })
+                }
+                Err(e) => write!(text, "error: {}", e),
+            },
+            Command::Get(Query::HeartRate) => match ui_model.heart_rate_bpm() {
+                Some(hr) => write!(text, "hr {:.1}", hr),
+                None => write!(text, "hr -"),
+            },
+            // R ratio too, for calibration
+            Command::Get(Query::Spo2) => match (ui_model.spo2(), ui_model.spo2_ratio()) {
+                (Some(Spo2::Valid(spo2)), Some(r)) => write!(text, "spo2 {:.1} r {:.4}", spo2, r),
+                (Some(Spo2::Invalid), Some(r)) => write!(text, "spo2 invalid r {:.4}", r),
+                _ => write!(text, "spo2 -"),
+            },
+            Command::Get(Query::Quality) => write!(text, "quality {:?}", ui_model.quality()),
+            Command::Get(Query::Finger) => write!(text, "finger {:?}", ui_model.finger_state()),
+            Command::Get(Query::Temperature) => match ui_model.temperature_c() {
+                Some(t) => write!(text, "temp {:.2}", t),
+                None => write!(text, "temp -"),
+            },
+            Command::Get(Query::Sensor) => {
+                let part = shared.sensor.lock(|sensor| sensor.part());
+                write!(text, "{} {}", part, settings.sensor)
+            }
+            Command::Get(Query::Touch) => match shared.touch.lock(|t| *t) {
+                Some(raw) => {
+                    let calibration = settings.touch_calibration.unwrap_or_default();
+                    let _ = write!(text, "touch raw {} {} z {}", raw.x, raw.y, raw.z);
+                    match calibration.to_screen(raw, TFT_SIZE, lcdui.rotation() as u8) {
+                        Some(p) => write!(text, " screen {} {}", p.x, p.y),
+                        None => write!(text, " off screen"),
+                    }
+                }
+                None => write!(text, "touch -"),
+            },
+            Command::Get(Query::Calibration) => write!(text, "cal {}", settings.spo2_calibration),
+            Command::Calibrate(calibration) => {
+                settings.spo2_calibration = calibration;
+                ui_model.set_spo2_calibration(calibration);
+                write!(text, "ok")
+            }
+            Command::Get(Query::Alarms) => {
+                let now_ms = monotonics::now().duration_since_epoch().to_millis() as u32;
+                write!(text, "alarms {}", settings.alarms).and_then(|_| {
+                    alarms.alarms(now_ms).try_for_each(|alarm| {
+                        let latched = if alarm.latched { " latched" } else { "" };
+                        let silenced = if alarm.silenced { " silenced" } else { "" };
+                        write!(text, ", {}{}{}", alarm.kind, latched, silenced)
+                    })
+                })
+            }
+            Command::Alarm(setting) => {
+                settings.alarms = setting.apply(&settings.alarms);
+                alarms.set_limits(settings.alarms);
+                write!(text, "ok")
+            }
+            Command::Acknowledge => {
+                let now_ms = monotonics::now().duration_since_epoch().to_millis() as u32;
+                alarms.acknowledge(now_ms);
+                let tone = alarms.tone(now_ms);
+                shared.tones.lock(|t| t.set_alarm(now_ms, tone));
+                write!(text, "ok")
+            }
+            Command::CalibrateTouch => {
+                *touch_calibrator = Some(TouchCalibrator::new(TFT_SIZE, lcdui.rotation() as u8));
+                write!(text, "touch the targets on the screen")
+            }
+            Command::Screen(id) => {
+                match id {
+                    Some(id) => lcdui.show(id),
+                    None => lcdui.next_screen(),
+                }
+                write!(text, "ok")
+            }
+            Command::Stream(on) => {
+                shared.streaming.lock(|s| *s = on);
+                write!(text, "ok")
+            }
+            Command::Beep(on) => {
+                shared.tones.lock(|t| t.set_pulse_tone(on));
+                write!(text, "ok")
+            }
+            Command::Rotate(quarter_turns) => {
+                match Rotation::try_from(quarter_turns).and_then(|r| lcdui.set_rotation(r)) {
+                    Ok(()) => {
+                        settings.rotation = quarter_turns as u8;
+                        write!(text, "ok")
+                    }
+                    Err(e) => write!(text, "error: lcd {:?}", e),
+                }
+            }
+            Command::Save => match settings_store.save(settings) {
+                Ok(()) => write!(text, "ok"),
+                Err(e) => write!(text, "error: flash {:?}", e),
+            },
+        };
+        shared
+            .serial_tx
+            .lock(|tx| reply(tx, format_args!("{}", text)));
     }
 }
@@ -1,18 +1,32 @@
 //! Board initialization
 
-use max3010x::Max3010x;
+use cardiac_monitor_shared::{
+    ppg::PpgSensor,
+    sensor::SensorSettings,
+    settings::{Settings, SettingsStore},
+};
+use stm32f1xx_hal::gpio::{Edge, ExtiPin};
 use stm32f1xx_hal::prelude::*;
+use stm32f1xx_hal::spi::{Mode, Phase, Polarity, Spi};
+use stm32f1xx_hal::timer::{Tim2NoRemap, Timer};
 
-use crate::{consts::*, delay::*, lcd::*, types::*};
+use crate::flash::{InternalFlash, SettingsStorage};
+use crate::{beeper::Beeper, consts::*, delay::*, lcd::*, types::*, usb::OtgFs};
 
 use stm32f1xx_hal::i2c;
 use stm32f1xx_hal::i2c::blocking::BlockingI2c;
 
 pub struct Board {
     pub test_pin: TestPin,
-    pub beeper: BeeperPin,
-    pub max30102_sensor: Max30102Sensor,
+    pub beeper: Beeper,
+    pub sensor: Sensor,
+    pub max30102_int: Max30102IntPin,
+    pub touch: Touch,
+    pub touch_int: TouchIntPin,
     pub lcd: Lcd<AsmDelay, 0>,
+    pub usb: OtgFs,
+    pub settings: Settings,
+    pub settings_store: SettingsStorage,
 }
 
 impl Board {
@@ -78,8 +92,24 @@ impl Board {
             .adcclk(12.mhz())
             .freeze(&mut flash.acr);
 
+        // OTG FS runs off PLLCLK * 2 / 3, needs PLLCLK at 72MHz
+        assert!(clocks.usbclk_valid());
+
+        // unreadable flash is as good as no saved settings
+        let mut settings_store = SettingsStore::new(InternalFlash::new(flash));
+        let settings = settings_store.load().unwrap_or_default();
+
         let test_pin = gpiob.pb5.into_push_pull_output(&mut gpiob.crl);
-        let beeper = gpioa.pa2.into_push_pull_output(&mut gpioa.crl);
+
+        // passive piezo, pitch is the PWM frequency
+        let beeper_pin = gpioa.pa2.into_alternate_push_pull(&mut gpioa.crl);
+        let beeper = Beeper::new(
+            Timer::tim2(device.TIM2, &clocks).pwm::<Tim2NoRemap, _, _, _>(
+                beeper_pin,
+                &mut afio.mapr,
+                1.khz(),
+            ),
+        );
 
         // Initialize (enable) the monotonic timer (CYCCNT)
         core.DCB.enable_trace();
@@ -106,44 +136,82 @@ impl Board {
             1000,
         );
 
-        //
-        // With this config:
-        // Fs = 25Hz
-        // Fs/2 = 12.5Hz
-        // buffer = 100samples
-        //
-        // Fhrmax = 260bpm = 4Hz
-        // Fhrmin = 40Hbpm = 0.65Hz
-        //
-        // Fstop_norm = 0.026
-        // Fmax_norm = 0.16
-        // Ftypical_norm = 0.04
-        //
-        let max30102_sensor = Max3010x::new_max30102(i2c);
-        let mut max30102_sensor = max30102_sensor.into_oximeter().unwrap();
-        max30102_sensor
-            .set_pulse_amplitude(max3010x::Led::All, 200)
-            .unwrap();
-        max30102_sensor
-            .set_pulse_width(max3010x::LedPulseWidth::Pw411)
-            .unwrap();
-        max30102_sensor
-            .set_sampling_rate(max3010x::SamplingRate::Sps400)
-            .unwrap();
-        max30102_sensor
-            .set_sample_averaging(max3010x::SampleAveraging::Sa16)
-            .unwrap();
-        max30102_sensor
-            .set_adc_range(max3010x::AdcRange::Fs16k)
-            .unwrap();
-        max30102_sensor.enable_fifo_rollover().unwrap();
-        max30102_sensor.clear_fifo().unwrap();
+        let mut sensor = Sensor::new(i2c, MAX3010X_PART, MAX30102_CHANNEL_ORDER).unwrap();
+        configure_sensor(&mut sensor, &settings.sensor).unwrap();
+
+        // Sensor pulls INT low when it has new data,
+        // acquisition follows the sensor's own sample clock.
+        let mut max30102_int = gpioa.pa3.into_pull_up_input(&mut gpioa.crl);
+        max30102_int.make_interrupt_source(&mut afio);
+        max30102_int.trigger_on_edge(&device.EXTI, Edge::Falling);
+        max30102_int.enable_interrupt(&device.EXTI);
+
+        // Resistive touch panel, XPT2046 takes 2.5MHz at most
+        let touch_spi = Spi::spi3(
+            device.SPI3,
+            (
+                gpioc.pc10.into_alternate_push_pull(&mut gpioc.crh),
+                gpioc.pc11.into_floating_input(&mut gpioc.crh),
+                gpioc.pc12.into_alternate_push_pull(&mut gpioc.crh),
+            ),
+            &mut afio.mapr,
+            Mode {
+                polarity: Polarity::IdleLow,
+                phase: Phase::CaptureOnFirstTransition,
+            },
+            2.mhz(),
+            clocks,
+        );
+        let touch = Touch::new(touch_spi, gpioc.pc9.into_push_pull_output(&mut gpioc.crh));
+
+        // PENIRQ goes low on a touch, it also toggles while converting,
+        // the touch task deals with that
+        let mut touch_int = gpioc.pc5.into_pull_up_input(&mut gpioc.crl);
+        touch_int.make_interrupt_source(&mut afio);
+        touch_int.trigger_on_edge(&device.EXTI, Edge::Falling);
+        touch_int.enable_interrupt(&device.EXTI);
+
+        // The board's own USB connector is taken by I2C,
+        // OTG FS needs a separate one on PA11/PA12.
+        let usb = OtgFs {
+            usb_global: device.OTG_FS_GLOBAL,
+            usb_device: device.OTG_FS_DEVICE,
+            usb_pwrclk: device.OTG_FS_PWRCLK,
+            pin_dm: gpioa.pa11,
+            pin_dp: gpioa.pa12,
+            hclk: clocks.hclk(),
+        };
 
         Board {
             test_pin,
             beeper,
-            max30102_sensor,
+            sensor,
+            max30102_int,
+            touch,
+            touch_int,
             lcd,
+            usb,
+            settings,
+            settings_store,
         }
     }
 }
+
+/// (Re)configures the sensor from scratch, every setting is written.
+/// FIFO is cleared, samples start over.
+///
+/// With the default config:
+/// Fs = 25Hz
+/// Fs/2 = 12.5Hz
+/// buffer = 100samples
+///
+/// Fhrmax = 260bpm = 4Hz
+/// Fhrmin = 40Hbpm = 0.65Hz
+///
+/// Fstop_norm = 0.026
+/// Fmax_norm = 0.16
+/// Ftypical_norm = 0.04
+pub fn configure_sensor(sensor: &mut Sensor, settings: &SensorSettings) -> Result<(), i2c::Error> {
+    sensor.configure(settings)?;
+    sensor.enable_interrupt(MAX30102_INTERRUPT)
+}
@@ -1,3 +1,8 @@
+use cardiac_monitor_shared::{
+    fifo::{ChannelOrder, FifoInterrupt},
+    model,
+    ppg::Part,
+};
 use stm32f1xx_hal::time::Hertz;
 
 use crate::lcd::{TFT_HEIGHT, TFT_WIDTH};
@@ -7,7 +12,44 @@ pub const SYS_FREQ: Hertz = Hertz(72_000_000);
 pub const UI_HEIGHT: usize = TFT_WIDTH as usize; // width in our screen orientation
 pub const UI_WIDTH: usize = TFT_HEIGHT as usize; // width in our screen orientation
 
-/// Number of samples to use as an input into heart rate / SPO2 calculations
-pub const MAX30102_NUM_SAMPLES: usize = 160;
-// configuration is in board.rs
-pub const MAX30102_SAMPLE_RATE: Hertz = Hertz(25);
+/// Touch panel covers the screen, native orientation
+pub const TFT_SIZE: (u16, u16) = (TFT_WIDTH, TFT_HEIGHT);
+
+pub use model::MAX30102_NUM_SAMPLES;
+
+/// The board has an MH-ET LIVE module, see `ChannelOrder::MH_ET_LIVE`.
+/// `ChannelOrder::DATASHEET` for modules wired per datasheet.
+pub const MAX30102_CHANNEL_ORDER: ChannelOrder = ChannelOrder::MH_ET_LIVE;
+
+/// The part on the module, they all have the same part ID.
+/// `Part::Max30101` for MAX30101 and MAX30105 modules.
+pub const MAX3010X_PART: Part = Part::Max30102;
+
+/// Samples are read as soon as the sensor has them, keeps display and
+/// beat detection latency down. `AlmostFull` would wake us up less
+/// often, but with at least 17 samples (0.7s) at a time.
+pub const MAX30102_INTERRUPT: FifoInterrupt = FifoInterrupt::NewSample;
+
+/// Die temperature changes slowly, a conversion takes about 30ms
+pub const TEMPERATURE_PERIOD_S: u64 = 10;
+
+/// Beeper follows the tone sequencer at the monotonic's resolution
+pub const BEEPER_TICK_MS: u64 = 10;
+
+/// Touch panel is read this often while pressed
+pub const TOUCH_POLL_MS: u64 = 20;
+
+/// pid.codes shared VID/PID for CDC-ACM serial devices
+pub const USB_VID_PID: (u16, u16) = (0x16c0, 0x27dd);
+
+/// Outgoing protocol frames, about 1s worth of samples
+/// at 25Hz plus vitals
+pub const SERIAL_TX_QUEUE_LEN: usize = 1024;
+
+/// Console commands waiting for idle to run them
+pub const CONSOLE_QUEUE_LEN: usize = 4;
+/// Longest console reply, `help` is the longest
+pub const CONSOLE_REPLY_LEN: usize = 448;
+
+/// Settings pages from the start of flash, `SETTINGS` in memory.x
+pub const SETTINGS_FLASH_OFFSET: u32 = 252 * 1024;
@@ -0,0 +1,47 @@
+//! Settings pages in the internal flash
+
+use cardiac_monitor_shared::settings::{SettingsFlash, SettingsStore};
+use stm32f1xx_hal::flash::{self, FlashSize, FlashWriter, SectorSize};
+
+use crate::consts::SETTINGS_FLASH_OFFSET;
+
+/// Connectivity line devices have 2K pages
+const PAGE_SIZE: u32 = 2048;
+
+pub type SettingsStorage = SettingsStore<InternalFlash>;
+
+pub struct InternalFlash {
+    parts: flash::Parts,
+}
+
+impl InternalFlash {
+    pub fn new(parts: flash::Parts) -> Self {
+        InternalFlash { parts }
+    }
+
+    fn writer(&mut self) -> FlashWriter<'_> {
+        self.parts.writer(SectorSize::Sz2K, FlashSize::Sz256K)
+    }
+}
+
+impl SettingsFlash for InternalFlash {
+    type Error = flash::Error;
+
+    const PAGE_SIZE: u32 = PAGE_SIZE;
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), flash::Error> {
+        let writer = self.writer();
+        let data = writer.read(SETTINGS_FLASH_OFFSET + offset, buf.len())?;
+        buf.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), flash::Error> {
+        self.writer().write(SETTINGS_FLASH_OFFSET + offset, data)
+    }
+
+    fn erase_page(&mut self, page: u32) -> Result<(), flash::Error> {
+        self.writer()
+            .page_erase(SETTINGS_FLASH_OFFSET + page * PAGE_SIZE)
+    }
+}
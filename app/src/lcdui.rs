@@ -1,124 +1,113 @@
-//! LCD UI
+//! LCD UI, the shared screens on the LCD
 
 use embedded_graphics::mono_font::ascii::FONT_6X12;
 use embedded_graphics::mono_font::MonoTextStyleBuilder;
-use embedded_graphics::primitives::{
-    Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle,
-};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Circle, Line, PrimitiveStyle};
 use embedded_graphics::text::Text;
-use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
 
 use core::fmt::Write;
 use heapless::String;
 
-use crate::consts::{UI_HEIGHT, UI_WIDTH};
-use crate::{delay::AsmDelay, lcd::*, model::*};
+use cardiac_monitor_shared::{
+    screens::{Action, Frame, Palette, ScreenId, Ui},
+    settings::Theme,
+    touch::TouchPoint,
+};
+
+use crate::consts::UI_HEIGHT;
+use crate::{delay::AsmDelay, lcd::*};
 
 pub struct LcdUI {
     lcd: Lcd<AsmDelay, 0>,
+    rotation: Rotation,
+    palette: Palette,
+    ui: Ui,
 }
 
-const TOP_TEXT_HEIGHT: u32 = 20;
-const GRAPH_HEIGHT: u32 = UI_HEIGHT as u32 - TOP_TEXT_HEIGHT;
-
 impl LcdUI {
-    pub fn new(lcd: Lcd<AsmDelay, 0>) -> Self {
-        LcdUI { lcd }
+    /// Portrait `rotation` falls back to the default landscape one
+    pub fn new(lcd: Lcd<AsmDelay, 0>, rotation: Rotation, theme: Theme) -> Self {
+        let rotation = match rotation {
+            Rotation::R90 | Rotation::R270 => rotation,
+            Rotation::R0 | Rotation::R180 => Rotation::R90,
+        };
+        let ui = Ui::new(theme);
+        LcdUI {
+            lcd,
+            rotation,
+            palette: ui.palette(),
+            ui,
+        }
     }
 
     pub fn init(&mut self) -> Result<(), LcdError> {
         self.lcd.init()?;
-        self.lcd.set_rotation(Rotation::R90)?;
-        self.lcd.clear(Rgb565::BLACK)
+        self.lcd.set_rotation(self.rotation)?;
+        self.lcd.clear(self.palette.background)
     }
 
-    pub fn render(&mut self, model: &UIModel) -> Result<(), LcdError> {
-        let style = MonoTextStyleBuilder::new()
-            .font(&FONT_6X12)
-            .text_color(Rgb565::YELLOW)
-            .background_color(Rgb565::BLACK)
-            .build();
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
 
-        let mut sbuf: String<64> = String::new();
-        write!(
-            sbuf,
-            "HR {:>3.1} ",
-            model
-                .ir
-                .heart_rate_bpm
-                .or(model.r.heart_rate_bpm)
-                .unwrap_or(0.0)
-        )?;
-        Text::new(&sbuf, Point::new(10, 10), style).draw(&mut self.lcd)?;
-
-        sbuf.clear();
-        write!(sbuf, "SPO2 {:>2.1} ", model.spo2())?;
-        Text::new(&sbuf, Point::new(100, 10), style).draw(&mut self.lcd)?;
-
-        self.lcd
-            .fill_solid(
-                &Rectangle::new(
-                    Point::new(0, TOP_TEXT_HEIGHT as i32),
-                    Size::new(UI_WIDTH as u32, GRAPH_HEIGHT),
-                ),
-                Rgb565::BLACK,
-            )
-            .unwrap();
-
-        self.render_ac_sample_data(&model.r, Rgb565::RED)?;
-        self.render_ac_sample_data(&model.ir, Rgb565::BLUE)?;
+    /// Next `render` starts from a blank screen
+    pub fn redraw(&mut self) {
+        self.ui.redraw();
+    }
 
+    /// Layout is landscape, so only 90 and 270 degrees
+    pub fn set_rotation(&mut self, rotation: Rotation) -> Result<(), LcdError> {
+        match rotation {
+            Rotation::R90 | Rotation::R270 => (),
+            Rotation::R0 | Rotation::R180 => return Err(LcdError::InvalidRotationId),
+        }
+        self.rotation = rotation;
+        self.lcd.set_rotation(rotation)?;
+        self.ui.redraw();
         Ok(())
     }
 
-    pub fn render_ac_sample_data(
-        &mut self,
-        samples: &Max3012SampleData,
-        color: Rgb565,
-    ) -> Result<(), LcdError> {
-        // leave some space at the top for text output
-        let scale = (GRAPH_HEIGHT - 1) as f32 / (samples.ac_max - samples.ac_min);
-        let line_style = PrimitiveStyle::with_stroke(color, 1);
-
-        #[inline]
-        fn sx(i: usize) -> i32 {
-            i as i32 * 2
-        }
+    pub fn show(&mut self, id: ScreenId) {
+        self.ui.show(id);
+    }
 
-        let sy = |s: f32| (UI_HEIGHT - 1) as i32 - (s * scale) as i32;
+    pub fn next_screen(&mut self) {
+        self.ui.next();
+    }
 
-        let mut p0_opt: Option<Point> = None;
-        for (i, acs) in samples.ac.iter().enumerate() {
-            let x = sx(i);
-            let y = sy(acs - samples.ac_min);
-            let p = Point::new(x, y);
+    /// Where the panel is pressed, in screen pixels, `None` when it isn't
+    pub fn touch(&mut self, point: Option<TouchPoint>, frame: &Frame<'_>) -> Option<Action> {
+        self.ui.touch(point, frame)
+    }
 
-            for p0 in p0_opt {
-                Line::new(p0, p)
-                    .into_styled(line_style)
-                    .draw(&mut self.lcd)?;
-            }
+    pub fn render(&mut self, frame: &Frame<'_>) -> Result<(), LcdError> {
+        self.ui.draw(frame, &mut self.lcd)
+    }
 
-            p0_opt = Some(p);
-        }
+    /// Touch calibration target, `step` of 3, on a blank screen
+    pub fn render_touch_target(&mut self, target: TouchPoint, step: usize) -> Result<(), LcdError> {
+        self.lcd.clear(self.palette.background)?;
 
-        let peak_style = PrimitiveStyleBuilder::new()
-            .stroke_color(color)
-            .fill_color(Rgb565::YELLOW)
-            .stroke_width(1)
+        let style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X12)
+            .text_color(self.palette.foreground)
+            .background_color(self.palette.background)
             .build();
-
-        let hb_cir = |i, v| {
-            let x = sx(i);
-            let y = sy(v - samples.ac_min);
-            Circle::new(Point::new(x as i32, y as i32), 5).into_styled(peak_style)
-        };
-
-        for hb in &samples.heartbeats {
-            hb_cir(hb.high_idx, hb.high_value).draw(&mut self.lcd)?;
-            hb_cir(hb.low_idx, hb.low_value).draw(&mut self.lcd)?;
+        let mut sbuf: String<32> = String::new();
+        write!(sbuf, "Touch the cross and let go {}/3", step + 1)?;
+        Text::new(&sbuf, Point::new(10, UI_HEIGHT as i32 / 2), style).draw(&mut self.lcd)?;
+
+        let center = Point::new(target.x, target.y);
+        let line_style = PrimitiveStyle::with_stroke(self.palette.foreground, 1);
+        for d in [Point::new(12, 0), Point::new(0, 12)] {
+            Line::new(center - d, center + d)
+                .into_styled(line_style)
+                .draw(&mut self.lcd)?;
         }
-
+        Circle::with_center(center, 11)
+            .into_styled(line_style)
+            .draw(&mut self.lcd)?;
         Ok(())
     }
 }
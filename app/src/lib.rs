@@ -1,9 +1,11 @@
 #![no_std]
 
+pub mod beeper;
 pub mod board;
 pub mod consts;
 pub mod delay;
+pub mod flash;
 pub mod lcd;
 pub mod lcdui;
-pub mod model;
 pub mod types;
+pub mod usb;
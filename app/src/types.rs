@@ -1,19 +1,50 @@
-use max3010x::{marker::ic::Max30102, Max3010x};
-use stm32f1::stm32f107::I2C1;
-use stm32f1xx_hal::{gpio::*, i2c::BlockingI2c};
+use cardiac_monitor_shared::{ppg::Max3010x, touch::Xpt2046};
+use stm32f1::stm32f107::{I2C1, SPI3, TIM2};
+use stm32f1xx_hal::{
+    gpio::*,
+    i2c::BlockingI2c,
+    pwm::{Pwm, C3},
+    spi::{Spi, Spi3Remap},
+    timer::Tim2NoRemap,
+};
 
 pub type TestPin = gpiob::PB5<Output<PushPull>>;
 
-pub type BeeperPin = gpioa::PA2<Output<PushPull>>;
-
-pub type Max30102Sensor = Max3010x<
-    BlockingI2c<
-        I2C1,
-        (
-            stm32f1xx_hal::gpio::Pin<Alternate<OpenDrain>, CRL, 'B', 6_u8>,
-            stm32f1xx_hal::gpio::Pin<Alternate<OpenDrain>, CRL, 'B', 7_u8>,
-        ),
-    >,
-    Max30102,
-    max3010x::marker::mode::Oximeter,
+pub type BeeperPin = gpioa::PA2<Alternate<PushPull>>;
+pub type BeeperPwm = Pwm<TIM2, Tim2NoRemap, C3, BeeperPin>;
+
+/// USB OTG FS data lines, the peripheral takes them over when enabled
+pub type UsbDmPin = gpioa::PA11<Input<Floating>>;
+pub type UsbDpPin = gpioa::PA12<Input<Floating>>;
+
+/// MAX30102 INT, open drain, active low
+pub type Max30102IntPin = gpioa::PA3<Input<PullUp>>;
+
+pub type Max30102I2C = BlockingI2c<
+    I2C1,
+    (
+        stm32f1xx_hal::gpio::Pin<Alternate<OpenDrain>, CRL, 'B', 6_u8>,
+        stm32f1xx_hal::gpio::Pin<Alternate<OpenDrain>, CRL, 'B', 7_u8>,
+    ),
+>;
+
+/// MAX30102 or a sibling, detected at boot
+pub type Sensor = Max3010x<Max30102I2C>;
+
+/// XPT2046 on SPI3, remapped to PC10..PC12
+pub type TouchSpi = Spi<
+    SPI3,
+    Spi3Remap,
+    (
+        gpioc::PC10<Alternate<PushPull>>,
+        gpioc::PC11<Input<Floating>>,
+        gpioc::PC12<Alternate<PushPull>>,
+    ),
+    u8,
 >;
+pub type TouchCsPin = gpioc::PC9<Output<PushPull>>;
+
+/// XPT2046 PENIRQ, active low while the panel is pressed
+pub type TouchIntPin = gpioc::PC5<Input<PullUp>>;
+
+pub type Touch = Xpt2046<TouchSpi, TouchCsPin>;
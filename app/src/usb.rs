@@ -0,0 +1,75 @@
+//! USB OTG FS peripheral, CDC-ACM serial on top of it
+
+use cardiac_monitor_shared::link::Transport;
+use stm32f1::stm32f107::{OTG_FS_DEVICE, OTG_FS_GLOBAL, OTG_FS_PWRCLK, RCC};
+use stm32f1xx_hal::time::Hertz;
+use synopsys_usb_otg::UsbPeripheral;
+use usb_device::{class_prelude::UsbBus, device::UsbDevice, UsbError};
+use usbd_serial::SerialPort;
+
+use crate::types::{UsbDmPin, UsbDpPin};
+
+pub use synopsys_usb_otg::UsbBus as OtgFsBus;
+
+/// Everything the OTG FS core needs, pins are connected
+/// to the core as soon as it is powered up
+pub struct OtgFs {
+    pub usb_global: OTG_FS_GLOBAL,
+    pub usb_device: OTG_FS_DEVICE,
+    pub usb_pwrclk: OTG_FS_PWRCLK,
+    pub pin_dm: UsbDmPin,
+    pub pin_dp: UsbDpPin,
+    pub hclk: Hertz,
+}
+
+unsafe impl Sync for OtgFs {}
+
+unsafe impl UsbPeripheral for OtgFs {
+    const REGISTERS: *const () = OTG_FS_GLOBAL::ptr() as *const ();
+
+    const HIGH_SPEED: bool = false;
+    // 1.25KB dedicated FIFO RAM on the connectivity line
+    const FIFO_DEPTH_WORDS: usize = 320;
+    const ENDPOINT_COUNT: usize = 4;
+
+    fn enable() {
+        let rcc = unsafe { &*RCC::ptr() };
+
+        cortex_m::interrupt::free(|_| {
+            rcc.ahbenr.modify(|_, w| w.otgfsen().set_bit());
+
+            rcc.ahbrstr.modify(|_, w| w.otgfsrst().set_bit());
+            rcc.ahbrstr.modify(|_, w| w.otgfsrst().clear_bit());
+        });
+    }
+
+    fn ahb_frequency_hz(&self) -> u32 {
+        self.hclk.0
+    }
+}
+
+pub type UsbBusType = OtgFsBus<OtgFs>;
+
+/// USB serial as a [`Transport`], host not reading is not an error
+pub struct UsbSerial<'a, B: UsbBus>(pub SerialPort<'a, B>);
+
+impl<'a, B: UsbBus> Transport for UsbSerial<'a, B> {
+    type Error = UsbError;
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, UsbError> {
+        match self.0.write(data) {
+            Err(UsbError::WouldBlock) => Ok(0),
+            r => r,
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, UsbError> {
+        match self.0.read(buf) {
+            Err(UsbError::WouldBlock) => Ok(0),
+            r => r,
+        }
+    }
+}
+
+pub type UsbDeviceType = UsbDevice<'static, UsbBusType>;
+pub type UsbSerialType = UsbSerial<'static, UsbBusType>;
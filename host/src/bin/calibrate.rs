@@ -0,0 +1,59 @@
+//! Fits an SpO2 calibration curve to paired readings
+//!
+//! `calibrate FILE`, see `calibration` for the CSV format. Prints how
+//! far each reading is from the curve, and the console command that
+//! sets it on the device.
+
+use cardiac_monitor_host::{
+    calibration::{fit_polynomial, read_points},
+    recording::RecordingError,
+};
+use cardiac_monitor_shared::calibration::Spo2;
+use std::{env, fs, io, process};
+
+const USAGE: &str = "usage: calibrate FILE";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let path = match (args.next(), args.next()) {
+        (Some(path), None) if !path.starts_with('-') => path,
+        _ => exit(USAGE),
+    };
+
+    let points = fs::File::open(&path)
+        .map_err(RecordingError::from)
+        .and_then(|f| read_points(io::BufReader::new(f)))
+        .unwrap_or_else(|e| exit(&format!("{}: {}", path, e)));
+    let fit = fit_polynomial(&points)
+        .unwrap_or_else(|| exit("need readings at 3 or more different ratios"));
+
+    println!(
+        "{:>8} {:>8} {:>8} {:>7}",
+        "ratio", "spo2", "fitted", "error"
+    );
+    for (ratio, spo2) in points.iter() {
+        match fit.calibration.spo2(*ratio) {
+            Spo2::Valid(fitted) => println!(
+                "{:>8.4} {:>8.2} {:>8.2} {:>+7.2}",
+                ratio,
+                spo2,
+                fitted,
+                fitted - spo2
+            ),
+            // curve goes past 100% or under the minimum here
+            Spo2::Invalid => println!("{:>8.4} {:>8.2}  invalid", ratio, spo2),
+        }
+    }
+    println!(
+        "{} readings, rms error {:.2}, max {:.2}",
+        points.len(),
+        fit.rms_error,
+        fit.max_error
+    );
+    println!("cal {}", fit.calibration);
+}
+
+fn exit(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
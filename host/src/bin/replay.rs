@@ -0,0 +1,61 @@
+//! Replays a recorded sensor session through the firmware model
+//!
+//! `replay [--fifo] [--step N] FILE`, prints the model state every
+//! `N` samples (25, once a second by default). `--fifo` reads a raw
+//! FIFO capture instead of CSV, see `recording` for both formats.
+
+use cardiac_monitor_host::{
+    recording::{read_csv, read_fifo_capture, RecordingError},
+    replay::Replay,
+};
+use cardiac_monitor_shared::fifo::ChannelOrder;
+use std::{env, fs, io, process};
+
+const USAGE: &str = "usage: replay [--fifo] [--swap] [--step N] FILE";
+
+fn main() {
+    let mut fifo = false;
+    let mut order = ChannelOrder::DATASHEET;
+    let mut step = 25;
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fifo" => fifo = true,
+            // recorded on an MH-ET LIVE module
+            "--swap" => order = ChannelOrder::MH_ET_LIVE,
+            "--step" => {
+                step = args
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or_else(|| exit(USAGE))
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => exit(USAGE),
+        }
+    }
+    let path = path.unwrap_or_else(|| exit(USAGE));
+
+    let samples = if fifo {
+        fs::read(&path)
+            .map(|data| read_fifo_capture(&data, order))
+            .map_err(RecordingError::from)
+    } else {
+        fs::File::open(&path)
+            .map_err(RecordingError::from)
+            .and_then(|f| read_csv(io::BufReader::new(f)))
+    }
+    .unwrap_or_else(|e| exit(&format!("{}: {}", path, e)));
+
+    let mut replay = Replay::new();
+    for chunk in samples.chunks(step) {
+        println!("{}", replay.feed(chunk));
+    }
+}
+
+fn exit(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
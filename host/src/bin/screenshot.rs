@@ -0,0 +1,110 @@
+//! Renders a firmware screen from a recorded sensor session
+//!
+//! `screenshot [--fifo] [--swap] [--screen NAME] [--at SECONDS] FILE OUT`
+//! replays `FILE` like `replay` does, up to `SECONDS` in (the whole of
+//! it by default), and writes the screen as it would look then to `OUT`,
+//! a PPM image. `NAME` is one of the tab labels, `wave` by default.
+
+use cardiac_monitor_host::{
+    recording::{read_csv, read_fifo_capture, RecordingError},
+    replay::Replay,
+};
+use cardiac_monitor_shared::{
+    alarms::{AlarmConfig, AlarmEngine},
+    calibration::Spo2,
+    fifo::ChannelOrder,
+    framebuffer::Framebuffer,
+    screens::{Frame, ScreenId, Ui, SCREEN_SIZE},
+    settings::{AlarmLimits, Theme},
+    trends::{Trends, TREND_INTERVAL_MS},
+};
+use std::{env, fs, io, process};
+
+const USAGE: &str = "usage: screenshot [--fifo] [--swap] [--screen NAME] [--at SECONDS] FILE OUT";
+
+fn main() {
+    let mut fifo = false;
+    let mut order = ChannelOrder::DATASHEET;
+    let mut screen = ScreenId::Waveform;
+    let mut at_s: Option<f32> = None;
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fifo" => fifo = true,
+            // MH-ET LIVE module has the LEDs swapped
+            "--swap" => order = ChannelOrder::MH_ET_LIVE,
+            "--screen" => {
+                screen = args
+                    .next()
+                    .and_then(|s| ScreenId::from_name(&s))
+                    .unwrap_or_else(|| exit(USAGE))
+            }
+            "--at" => {
+                at_s = Some(
+                    args.next()
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or_else(|| exit(USAGE)),
+                )
+            }
+            _ if !arg.starts_with('-') => paths.push(arg),
+            _ => exit(USAGE),
+        }
+    }
+    let (path, out) = match paths.as_slice() {
+        [path, out] => (path, out),
+        _ => exit(USAGE),
+    };
+
+    let samples = if fifo {
+        fs::read(path)
+            .map(|data| read_fifo_capture(&data, order))
+            .map_err(RecordingError::from)
+    } else {
+        fs::File::open(path)
+            .map_err(RecordingError::from)
+            .and_then(|f| read_csv(io::BufReader::new(f)))
+    }
+    .unwrap_or_else(|e| exit(&format!("{}: {}", path, e)));
+
+    // a second at a time, about as often as the firmware runs the model
+    let limits = AlarmLimits::default();
+    let mut replay = Replay::new();
+    let mut trends = Trends::new(TREND_INTERVAL_MS);
+    let mut alarms = AlarmEngine::new(AlarmConfig::default(), limits);
+    let mut now_ms = 0;
+    for chunk in samples.chunks(25) {
+        let report = replay.feed(chunk);
+        now_ms = report.time_ms;
+        if at_s.is_some_and(|at_s| now_ms as f32 > at_s * 1000.0) {
+            break;
+        }
+        let spo2 = report.spo2.and_then(Spo2::value);
+        trends.update(now_ms, report.heart_rate_bpm, spo2);
+        alarms.update(now_ms, report.heart_rate_bpm, spo2);
+    }
+
+    let frame = Frame {
+        model: replay.model(),
+        trends: &trends,
+        alarm: alarms.highest(now_ms),
+        alarm_limits: limits,
+        pulse_tone: true,
+        rotation: 1,
+    };
+    let mut ui = Ui::new(Theme::Dark);
+    ui.show(screen);
+    let mut fb = Framebuffer::new(SCREEN_SIZE, ui.palette().background);
+    ui.draw(&frame, &mut fb).unwrap();
+
+    fs::File::create(out)
+        .map(io::BufWriter::new)
+        .and_then(|w| fb.write_ppm(w))
+        .unwrap_or_else(|e| exit(&format!("{}: {}", out, e)));
+}
+
+fn exit(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
@@ -0,0 +1,57 @@
+//! Prints messages streamed by the device
+//!
+//! `stream [--csv] PORT`, `PORT` is the serial device (set it to raw
+//! mode first: `stty -F /dev/ttyACM0 raw`) or a file with a capture.
+//! `--csv` prints only the raw samples, in a format `replay` reads.
+
+use cardiac_monitor_host::stream::StreamDecoder;
+use cardiac_monitor_shared::protocol::Message;
+use std::{env, fs, io::Read, process};
+
+const USAGE: &str = "usage: stream [--csv] PORT";
+
+fn main() {
+    let mut csv = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--csv" => csv = true,
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => exit(USAGE),
+        }
+    }
+    let path = path.unwrap_or_else(|| exit(USAGE));
+    let mut port = fs::File::open(&path).unwrap_or_else(|e| exit(&format!("{}: {}", path, e)));
+
+    if csv {
+        println!("seq,time_ms,red,ir");
+    }
+
+    let mut dec = StreamDecoder::new();
+    let mut buf = [0; 256];
+    loop {
+        let n = match port.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) => exit(&format!("{}: {}", path, e)),
+        };
+        for msg in dec.push(&buf[..n]) {
+            match msg {
+                Ok(Message::Sample {
+                    seq,
+                    time_ms,
+                    red,
+                    ir,
+                }) if csv => println!("{},{},{},{}", seq, time_ms, red, ir),
+                Ok(m) if !csv => println!("{:?}", m),
+                Ok(_) => (),
+                Err(e) => eprintln!("{:?}", e),
+            }
+        }
+    }
+}
+
+fn exit(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    process::exit(1)
+}
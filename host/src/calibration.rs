@@ -0,0 +1,239 @@
+//! SpO2 calibration from paired readings
+//!
+//! CSV, one (R ratio, reference SpO2 %) pair per line, R as the firmware
+//! computed it and SpO2 from a reference oximeter at the same time.
+//! Columns are picked by the header (`ratio` or `r`, `spo2`), without
+//! a header lines are `ratio,spo2`.
+
+use crate::recording::RecordingError;
+use cardiac_monitor_shared::calibration::Spo2Calibration;
+use std::io;
+
+pub fn read_points<R: io::BufRead>(reader: R) -> Result<Vec<(f32, f32)>, RecordingError> {
+    let mut columns = None;
+    let mut points = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = i + 1;
+        let err = |msg: String| RecordingError::Parse { line: line_no, msg };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+        let (ratio, spo2) = match columns {
+            Some(c) => c,
+            None if fields[0].parse::<f32>().is_ok() => *columns.insert((0, 1)),
+            None => {
+                let find = |names: &[&str]| {
+                    fields
+                        .iter()
+                        .position(|f| names.iter().any(|n| f.eq_ignore_ascii_case(n)))
+                };
+                let cols = find(&["ratio", "r"]).zip(find(&["spo2"]));
+                columns = Some(cols.ok_or_else(|| err("unknown columns".into()))?);
+                continue;
+            }
+        };
+
+        let field = |idx: usize| -> Result<f32, RecordingError> {
+            let f = fields
+                .get(idx)
+                .ok_or_else(|| err(format!("missing column {}", idx + 1)))?;
+            f.parse::<f32>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .ok_or_else(|| err(format!("{}: not a number", f)))
+        };
+        points.push((field(ratio)?, field(spo2)?));
+    }
+
+    Ok(points)
+}
+
+/// Least squares fit and how well it matches the data
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fit {
+    /// Limited to the R range of the data, the curve is a guess outside of it
+    pub calibration: Spo2Calibration,
+    /// SpO2 %
+    pub rms_error: f32,
+    pub max_error: f32,
+}
+
+/// Quadratic through `points`, `None` without at least 3 distinct R values
+pub fn fit_polynomial(points: &[(f32, f32)]) -> Option<Fit> {
+    // normal equations, sums of R^k for k in 0..=4 and of SpO2 * R^k for k in 0..=2
+    let mut rk = [0.0f64; 5];
+    let mut srk = [0.0f64; 3];
+    for (ratio, spo2) in points {
+        let (ratio, spo2) = (*ratio as f64, *spo2 as f64);
+        let mut x = 1.0;
+        for k in 0..5 {
+            rk[k] += x;
+            if k < 3 {
+                srk[k] += spo2 * x;
+            }
+            x *= ratio;
+        }
+    }
+    let a = [
+        [rk[0], rk[1], rk[2]],
+        [rk[1], rk[2], rk[3]],
+        [rk[2], rk[3], rk[4]],
+    ];
+    let c = solve3(a, srk)?;
+    let coefficients = [c[0] as f32, c[1] as f32, c[2] as f32];
+
+    let ratio_min = points.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+    let ratio_max = points.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max);
+
+    let errors = points.iter().map(|(ratio, spo2)| {
+        let fitted = (c[2] * *ratio as f64 + c[1]) * *ratio as f64 + c[0];
+        fitted - *spo2 as f64
+    });
+    let sq_sum: f64 = errors.clone().map(|e| e * e).sum();
+    let max_error = errors.map(f64::abs).fold(0.0, f64::max);
+
+    Some(Fit {
+        calibration: Spo2Calibration::Polynomial {
+            coefficients,
+            ratio_min,
+            ratio_max,
+        },
+        rms_error: (sq_sum / points.len() as f64).sqrt() as f32,
+        max_error: max_error as f32,
+    })
+}
+
+/// Gaussian elimination with partial pivoting, `None` if `a` is singular
+fn solve3(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    let scale = a
+        .iter()
+        .flatten()
+        .fold(0.0f64, |m, x| m.max(x.abs()))
+        .max(f64::MIN_POSITIVE);
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < scale * 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..3 {
+            let f = a[row][col] / pivot_row[col];
+            for (x, p) in a[row].iter_mut().zip(pivot_row.iter()).skip(col) {
+                *x -= f * p;
+            }
+            b[row] -= f * b[col];
+        }
+    }
+
+    let mut x = [0.0; 3];
+    for row in (0..3).rev() {
+        let rest: f64 = (row + 1..3).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - rest) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cardiac_monitor_shared::calibration::Spo2;
+
+    fn coefficients(fit: &Fit) -> [f32; 3] {
+        match fit.calibration {
+            Spo2Calibration::Polynomial { coefficients, .. } => coefficients,
+            _ => panic!("{:?}", fit),
+        }
+    }
+
+    #[test]
+    fn test_read_points() {
+        let csv = "0.5,98\n# comment\n\n1.0, 80.5\n";
+        assert_eq!(
+            read_points(csv.as_bytes()).unwrap(),
+            vec![(0.5, 98.0), (1.0, 80.5)]
+        );
+
+        let csv = "time,SpO2,R\n0,98,0.5\n1,80.5,1.0\n";
+        assert_eq!(
+            read_points(csv.as_bytes()).unwrap(),
+            vec![(0.5, 98.0), (1.0, 80.5)]
+        );
+
+        let err = read_points("0.5,98\n0.7\n".as_bytes()).unwrap_err();
+        assert!(
+            matches!(err, RecordingError::Parse { line: 2, .. }),
+            "{}",
+            err
+        );
+        let err = read_points("0.5,NaN\n".as_bytes()).unwrap_err();
+        assert!(
+            matches!(err, RecordingError::Parse { line: 1, .. }),
+            "{}",
+            err
+        );
+        let err = read_points("a,b\n".as_bytes()).unwrap_err();
+        assert!(
+            matches!(err, RecordingError::Parse { line: 1, .. }),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_fit_exact() {
+        // points right on the default curve give it back
+        let default = Spo2Calibration::default();
+        let points: Vec<(f32, f32)> = (0..9)
+            .map(|i| {
+                let ratio = 0.4 + 0.1 * i as f32;
+                (ratio, default.spo2(ratio).value().unwrap())
+            })
+            .collect();
+        let fit = fit_polynomial(&points).unwrap();
+
+        let [c0, c1, c2] = coefficients(&fit);
+        assert!((c0 - 94.845).abs() < 0.01, "{:?}", fit);
+        assert!((c1 - 30.354).abs() < 0.01, "{:?}", fit);
+        assert!((c2 + 45.06).abs() < 0.01, "{:?}", fit);
+        assert!(fit.max_error < 1e-3, "{:?}", fit);
+
+        // only valid over the data
+        assert_eq!(fit.calibration.spo2(0.35), Spo2::Invalid);
+        assert_eq!(fit.calibration.spo2(1.25), Spo2::Invalid);
+    }
+
+    #[test]
+    fn test_fit_noisy() {
+        // a straight line, with readings alternating 1% high and low
+        let points: Vec<(f32, f32)> = (0..20)
+            .map(|i| {
+                let ratio = 0.5 + 0.05 * i as f32;
+                let noise = if i % 2 == 0 { 1.0 } else { -1.0 };
+                (ratio, 110.0 - 25.0 * ratio + noise)
+            })
+            .collect();
+        let fit = fit_polynomial(&points).unwrap();
+        let [c0, c1, c2] = coefficients(&fit);
+        assert!((c0 - 110.0).abs() < 1.0, "{:?}", fit);
+        assert!((c1 + 25.0).abs() < 2.0, "{:?}", fit);
+        assert!(c2.abs() < 1.0, "{:?}", fit);
+        assert!((fit.rms_error - 1.0).abs() < 0.05, "{:?}", fit);
+    }
+
+    #[test]
+    fn test_fit_underdetermined() {
+        assert_eq!(fit_polynomial(&[]), None);
+        assert_eq!(fit_polynomial(&[(0.5, 98.0), (1.0, 80.0)]), None);
+        // repeated readings at the same two ratios don't help
+        let points = [(0.5, 98.0), (1.0, 80.0), (0.5, 97.0), (1.0, 81.0)];
+        assert_eq!(fit_polynomial(&points), None);
+    }
+}
@@ -0,0 +1,6 @@
+//! Host side tools, running the firmware signal processing on a PC
+
+pub mod calibration;
+pub mod recording;
+pub mod replay;
+pub mod stream;
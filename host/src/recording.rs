@@ -0,0 +1,224 @@
+//! Recorded sensor sessions
+//!
+//! Two formats are supported:
+//! - CSV, one sample per line. Columns are picked by the header
+//!   (`red` or `r`, `ir`, optional `seq` and `time_ms`), without a header
+//!   lines are either `red,ir` or `seq,time_ms,red,ir`.
+//! - FIFO capture, raw bytes as read from the MAX30102 FIFO_DATA register
+//!   in SpO2 mode, 6 bytes per sample.
+//!
+//! Samples without a sequence number or timestamp get them assigned
+//! as if they came from the sensor at the nominal sample rate.
+
+use cardiac_monitor_shared::{
+    fifo::{decode_samples, ChannelOrder, FifoSample, LedMode},
+    model::{Max3012Sample, MAX30102_SAMPLE_RATE_HZ},
+    timing::{SampleStamp, SampleStamper},
+};
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    Parse { line: usize, msg: String },
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(e) => write!(f, "{}", e),
+            RecordingError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<io::Error> for RecordingError {
+    fn from(e: io::Error) -> Self {
+        RecordingError::Io(e)
+    }
+}
+
+/// Column positions in a CSV line
+struct Columns {
+    red: usize,
+    ir: usize,
+    seq: Option<usize>,
+    time_ms: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(fields: &[&str]) -> Option<Self> {
+        let find = |names: &[&str]| {
+            fields
+                .iter()
+                .position(|f| names.iter().any(|n| f.eq_ignore_ascii_case(n)))
+        };
+        Some(Columns {
+            red: find(&["red", "r"])?,
+            ir: find(&["ir"])?,
+            seq: find(&["seq"]),
+            time_ms: find(&["time_ms"]),
+        })
+    }
+
+    fn from_count(count: usize) -> Option<Self> {
+        match count {
+            2 => Some(Columns {
+                red: 0,
+                ir: 1,
+                seq: None,
+                time_ms: None,
+            }),
+            4 => Some(Columns {
+                red: 2,
+                ir: 3,
+                seq: Some(0),
+                time_ms: Some(1),
+            }),
+            _ => None,
+        }
+    }
+}
+
+pub fn read_csv<R: io::BufRead>(reader: R) -> Result<Vec<Max3012Sample>, RecordingError> {
+    let mut stamper = SampleStamper::new(MAX30102_SAMPLE_RATE_HZ);
+    let period_ms = 1000 / MAX30102_SAMPLE_RATE_HZ;
+    let mut columns = None;
+    let mut samples = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = i + 1;
+        let err = |msg: String| RecordingError::Parse { line: line_no, msg };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+
+        let cols = match &columns {
+            Some(c) => c,
+            None => {
+                let numeric = fields[0].parse::<f64>().is_ok();
+                let cols = if numeric {
+                    Columns::from_count(fields.len())
+                } else {
+                    Columns::from_header(&fields)
+                };
+                let cols = columns.insert(cols.ok_or_else(|| err("unknown columns".into()))?);
+                if !numeric {
+                    continue;
+                }
+                cols
+            }
+        };
+
+        let field = |idx: usize| -> Result<u32, RecordingError> {
+            let f = fields
+                .get(idx)
+                .ok_or_else(|| err(format!("missing column {}", idx + 1)))?;
+            f.parse::<f64>()
+                .map(|x| x as u32)
+                .map_err(|e| err(format!("{}: {}", f, e)))
+        };
+
+        let sample = FifoSample {
+            red: field(cols.red)?,
+            ir: field(cols.ir)?,
+            green: 0,
+        };
+        let nominal = samples.len() as u32;
+        let mut stamp = stamper
+            .stamp(nominal * period_ms, 0, 1)
+            .next()
+            .unwrap_or_else(SampleStamp::zero);
+        if let Some(c) = cols.seq {
+            stamp.seq = field(c)?;
+        }
+        if let Some(c) = cols.time_ms {
+            stamp.time_ms = field(c)?;
+        }
+        samples.push(Max3012Sample::new(sample, stamp));
+    }
+
+    Ok(samples)
+}
+
+/// Raw FIFO bytes, a trailing partial sample is ignored
+pub fn read_fifo_capture(data: &[u8], order: ChannelOrder) -> Vec<Max3012Sample> {
+    let mut fifo_samples = vec![FifoSample::zero(); data.len() / (3 * LedMode::SpO2.slots())];
+    let num_samples = decode_samples(data, LedMode::SpO2, order, &mut fifo_samples);
+
+    let mut stamper = SampleStamper::new(MAX30102_SAMPLE_RATE_HZ);
+    let period_ms = 1000 / MAX30102_SAMPLE_RATE_HZ;
+    let last_ms = (num_samples as u32).saturating_sub(1) * period_ms;
+    fifo_samples[..num_samples]
+        .iter()
+        .zip(stamper.stamp(last_ms, 0, num_samples))
+        .map(|(s, stamp)| Max3012Sample::new(*s, stamp))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(red: u32, ir: u32, seq: u32, time_ms: u32) -> Max3012Sample {
+        Max3012Sample::new(
+            FifoSample { red, ir, green: 0 },
+            SampleStamp { seq, time_ms },
+        )
+    }
+
+    #[test]
+    fn test_csv_no_header() {
+        let csv = "100,200\n101,201\n\n# comment\n102,202\n";
+        assert_eq!(
+            read_csv(csv.as_bytes()).unwrap(),
+            vec![
+                sample(100, 200, 1, 0),
+                sample(101, 201, 2, 40),
+                sample(102, 202, 3, 80)
+            ]
+        );
+
+        let csv = "7,1000,100,200\n9,1080,101,201\n";
+        assert_eq!(
+            read_csv(csv.as_bytes()).unwrap(),
+            vec![sample(100, 200, 7, 1000), sample(101, 201, 9, 1080)]
+        );
+    }
+
+    #[test]
+    fn test_csv_header() {
+        let csv = "time_ms, IR, Red\n500, 200, 100\n540, 201, 101\n";
+        assert_eq!(
+            read_csv(csv.as_bytes()).unwrap(),
+            vec![sample(100, 200, 1, 500), sample(101, 201, 2, 540)]
+        );
+    }
+
+    #[test]
+    fn test_csv_errors() {
+        match read_csv("100,200\n101,x\n".as_bytes()) {
+            Err(RecordingError::Parse { line: 2, .. }) => (),
+            r => panic!("{:?}", r),
+        }
+        assert!(read_csv("a,b\n1,2\n".as_bytes()).is_err());
+        assert!(read_csv("1,2,3\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_fifo_capture() {
+        let data = [
+            0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x03, 0xff, 0xff, 0x00, 0x00, 0x01, 0x00,
+        ];
+        assert_eq!(
+            read_fifo_capture(&data, ChannelOrder::RedIr),
+            vec![sample(0x100, 0x200, 1, 0), sample(0x3ffff, 1, 2, 40)]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Feeds recorded samples through the model, the same way the firmware does
+
+use cardiac_monitor_shared::{
+    calibration::Spo2,
+    circ::Circ,
+    model::{Max3012Sample, UIModel, MAX30102_NUM_SAMPLES},
+    presence::{FingerEvent, FingerState},
+    quality::SignalQuality,
+};
+use std::fmt;
+
+/// Model state after a chunk of samples
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowReport {
+    /// Samples fed so far
+    pub sample_count: usize,
+    /// Time of the last sample, ms
+    pub time_ms: u32,
+    pub finger: FingerState,
+    pub quality: SignalQuality,
+    pub heart_rate_bpm: Option<f32>,
+    pub spo2: Option<Spo2>,
+    /// IR heartbeat positions in the sample window
+    pub beats: Vec<usize>,
+    pub lost_samples: u32,
+}
+
+impl fmt::Display for WindowReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>7} {:>9.2}s {:<8} {:<10}",
+            self.sample_count,
+            self.time_ms as f32 / 1000.0,
+            format!("{:?}", self.finger),
+            format!("{:?}", self.quality),
+        )?;
+        match self.heart_rate_bpm {
+            Some(hr) => write!(f, " HR {:>5.1}", hr)?,
+            None => write!(f, " HR   ---")?,
+        }
+        match self.spo2 {
+            Some(Spo2::Valid(spo2)) => write!(f, " SPO2 {:>5.1}", spo2)?,
+            Some(Spo2::Invalid) => write!(f, " SPO2   inv")?,
+            None => write!(f, " SPO2   ---")?,
+        }
+        if self.lost_samples > 0 {
+            write!(f, " LOST {}", self.lost_samples)?;
+        }
+        write!(f, " beats {:?}", self.beats)
+    }
+}
+
+/// Sample window and model, as in the firmware idle loop
+pub struct Replay {
+    samples: Circ<Max3012Sample, MAX30102_NUM_SAMPLES>,
+    model: UIModel<MAX30102_NUM_SAMPLES>,
+    sample_count: usize,
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        Replay {
+            samples: Circ::new(Max3012Sample::zero()),
+            model: UIModel::new(),
+            sample_count: 0,
+        }
+    }
+
+    pub fn model(&self) -> &UIModel<MAX30102_NUM_SAMPLES> {
+        &self.model
+    }
+
+    /// Runs the model over new samples, like a pass of the idle loop
+    /// that found `chunk` in the sample buffer. Chunks longer than the
+    /// window take several passes, so that no samples are skipped.
+    pub fn feed(&mut self, chunk: &[Max3012Sample]) -> WindowReport {
+        for part in chunk.chunks(MAX30102_NUM_SAMPLES) {
+            self.update(part);
+        }
+
+        let ir = &self.model.ir;
+        WindowReport {
+            sample_count: self.sample_count,
+            time_ms: chunk.last().map(|s| s.time_ms).unwrap_or(0),
+            finger: self.model.finger_state(),
+            quality: self.model.quality(),
+            heart_rate_bpm: self.model.heart_rate_bpm(),
+            spo2: self.model.spo2(),
+            beats: ir
+                .heartbeats
+                .iter()
+                .map(|hb| ir.window_idx(hb.low_idx))
+                .collect(),
+            lost_samples: self.model.sensor_overflow,
+        }
+    }
+
+    fn update(&mut self, part: &[Max3012Sample]) {
+        for s in part.iter() {
+            self.samples.add(*s);
+        }
+        self.sample_count += part.len();
+
+        let mut window = [Max3012Sample::zero(); MAX30102_NUM_SAMPLES];
+        for (i, s) in self.samples.iter().enumerate() {
+            window[i] = s;
+        }
+
+        let event = self.model.update_from_samples(&window, part.len());
+        if event == Some(FingerEvent::Removed) {
+            self.samples.clear(Max3012Sample::zero());
+        }
+    }
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cardiac_monitor_shared::{
+        synth::{PpgConfig, PpgSynth},
+        timing::SampleStamper,
+    };
+
+    fn synth_samples(cfg: PpgConfig, seconds: usize) -> Vec<Max3012Sample> {
+        let mut stamper = SampleStamper::new(25);
+        PpgSynth::new(cfg)
+            .take(25 * seconds)
+            .enumerate()
+            .map(|(i, s)| {
+                let stamp = stamper.stamp(i as u32 * 40, 0, 1).next().unwrap();
+                Max3012Sample::new(s.sample, stamp)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_synthetic() {
+        let cfg = PpgConfig {
+            heart_rate_bpm: 75.0,
+            spo2: 96.0,
+            ..PpgConfig::default()
+        };
+        let mut replay = Replay::new();
+        let report = synth_samples(cfg, 30)
+            .chunks(25)
+            .map(|c| replay.feed(c))
+            .last()
+            .unwrap();
+
+        assert_eq!(report.finger, FingerState::Present);
+        assert!(report.quality >= SignalQuality::Acceptable, "{}", report);
+        let hr = report.heart_rate_bpm.unwrap();
+        assert!((hr - 75.0).abs() < 3.0, "{}", report);
+        let spo2 = report.spo2.and_then(Spo2::value).unwrap();
+        assert!((spo2 - 96.0).abs() < 2.0, "{}", report);
+        assert_eq!(report.sample_count, 750);
+        assert_eq!(report.lost_samples, 0);
+    }
+
+    #[test]
+    fn test_replay_lost_samples() {
+        let mut samples = synth_samples(PpgConfig::default(), 10);
+        samples.drain(100..103);
+
+        let mut replay = Replay::new();
+        let report = samples.chunks(25).map(|c| replay.feed(c)).last().unwrap();
+        assert_eq!(report.lost_samples, 3);
+
+        // larger than the sample window
+        let mut replay = Replay::new();
+        let report = replay.feed(&synth_samples(PpgConfig::default(), 10));
+        assert_eq!(report.sample_count, 250);
+        assert_eq!(report.lost_samples, 0);
+    }
+}
@@ -0,0 +1,167 @@
+//! Decoder for the device's serial stream, see `protocol` in shared
+
+use cardiac_monitor_shared::protocol::{Message, ProtocolError, FRAME_END, MAX_FRAME_LEN};
+
+/// Splits a byte stream into frames and decodes them. Bytes before the
+/// first delimiter are dropped, the stream may have been joined midway.
+pub struct StreamDecoder {
+    frame: Vec<u8>,
+    synced: bool,
+    overlong: bool,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        StreamDecoder {
+            frame: Vec::with_capacity(MAX_FRAME_LEN),
+            synced: false,
+            overlong: false,
+        }
+    }
+
+    /// A decoded message or an error for each complete frame in `bytes`
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<Message, ProtocolError>> {
+        let mut out = Vec::new();
+        for b in bytes.iter() {
+            if *b != FRAME_END {
+                if self.frame.len() < MAX_FRAME_LEN {
+                    self.frame.push(*b);
+                } else {
+                    self.overlong = true;
+                }
+                continue;
+            }
+
+            if self.synced && !self.frame.is_empty() {
+                out.push(if self.overlong {
+                    Err(ProtocolError::Framing)
+                } else {
+                    Message::decode(&mut self.frame)
+                });
+            }
+            self.synced = true;
+            self.overlong = false;
+            self.frame.clear();
+        }
+        out
+    }
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cardiac_monitor_shared::{
+        calibration::Spo2, hrv::HrvStats, presence::FingerState, quality::SignalQuality,
+    };
+
+    fn encode(msgs: &[Message]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for m in msgs.iter() {
+            let mut frame = [0; MAX_FRAME_LEN];
+            let n = m.encode(&mut frame).unwrap();
+            out.extend_from_slice(&frame[..n]);
+        }
+        out
+    }
+
+    fn messages() -> Vec<Message> {
+        let mut msgs: Vec<Message> = (0..50)
+            .map(|i| Message::Sample {
+                seq: i + 1,
+                time_ms: i * 40,
+                red: 80_000 + i,
+                ir: 100_000 - i,
+            })
+            .collect();
+        msgs.push(Message::Beat { time_ms: 1_000 });
+        msgs.push(Message::Vitals {
+            time_ms: 1_960,
+            heart_rate_bpm: Some(61.5),
+            spo2: None,
+            quality: SignalQuality::Acceptable,
+            finger: FingerState::Present,
+        });
+        msgs.push(Message::Vitals {
+            time_ms: 2_000,
+            heart_rate_bpm: Some(62.0),
+            spo2: Some(Spo2::Invalid),
+            quality: SignalQuality::Acceptable,
+            finger: FingerState::Present,
+        });
+        msgs.push(Message::Vitals {
+            time_ms: 2_040,
+            heart_rate_bpm: Some(62.0),
+            spo2: Some(Spo2::Valid(96.5)),
+            quality: SignalQuality::Good,
+            finger: FingerState::Present,
+        });
+        msgs.push(Message::Hrv {
+            time_ms: 1_960,
+            stats: HrvStats {
+                num_intervals: 2,
+                mean_nn: 975.0,
+                sdnn: 35.36,
+                rmssd: 50.0,
+                pnn50: 0.0,
+            },
+        });
+        msgs
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let msgs = messages();
+        let mut stream = vec![FRAME_END];
+        stream.extend(encode(&msgs));
+
+        // arbitrary read sizes
+        let mut dec = StreamDecoder::new();
+        let decoded: Vec<Message> = stream
+            .chunks(7)
+            .flat_map(|c| dec.push(c))
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(decoded, msgs);
+    }
+
+    #[test]
+    fn test_join_midway() {
+        let msgs = messages();
+        let stream = encode(&msgs);
+
+        // first frame is cut, dropped without an error
+        let mut dec = StreamDecoder::new();
+        let decoded: Vec<Message> = dec
+            .push(&stream[3..])
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(decoded, msgs[1..]);
+    }
+
+    #[test]
+    fn test_corrupted_frames() {
+        let msgs = messages();
+        let mut stream = vec![FRAME_END];
+        stream.extend(encode(&msgs[..3]));
+        stream[4] ^= 0x10;
+        stream.extend(vec![0x55; 200]);
+        stream.push(FRAME_END);
+        stream.extend(encode(&msgs[3..4]));
+
+        let mut dec = StreamDecoder::new();
+        let decoded = dec.push(&stream);
+        assert_eq!(decoded.len(), 5);
+        assert!(decoded[0].is_err());
+        assert_eq!(decoded[1], Ok(msgs[1]));
+        assert_eq!(decoded[2], Ok(msgs[2]));
+        assert_eq!(decoded[3], Err(ProtocolError::Framing));
+        assert_eq!(decoded[4], Ok(msgs[3]));
+    }
+}
@@ -0,0 +1,480 @@
+//! Vital sign alarms
+//!
+//! Heart rate and SpO2 are checked against [`AlarmLimits`]. A reading
+//! has to stay past a limit for a while before the alarm goes off, so a
+//! single odd window or a bit of motion doesn't sound it, and has to get
+//! back inside the limit by a margin for it to clear, so a value sitting
+//! right at the limit doesn't keep toggling it.
+//!
+//! High priority alarms latch: once gone off they stay on, sound
+//! included, until acknowledged. Acknowledging silences the others for
+//! a while. Losing the signal after there were readings is a technical
+//! alarm of its own.
+
+use core::fmt;
+
+use crate::{settings::AlarmLimits, tones::AlarmTone};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlarmPriority {
+    Low,
+    Medium,
+    High,
+}
+
+impl AlarmPriority {
+    pub fn tone(self) -> AlarmTone {
+        match self {
+            AlarmPriority::Low => AlarmTone::Low,
+            AlarmPriority::Medium => AlarmTone::Medium,
+            AlarmPriority::High => AlarmTone::High,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlarmKind {
+    Spo2Low,
+    HeartRateLow,
+    HeartRateHigh,
+    /// No readings, finger off or too much motion
+    SignalLost,
+}
+
+impl AlarmKind {
+    /// Highest priority first
+    pub const ALL: [AlarmKind; 4] = [
+        Self::Spo2Low,
+        Self::HeartRateLow,
+        Self::HeartRateHigh,
+        Self::SignalLost,
+    ];
+
+    pub fn priority(self) -> AlarmPriority {
+        match self {
+            Self::Spo2Low => AlarmPriority::High,
+            Self::HeartRateLow | Self::HeartRateHigh => AlarmPriority::Medium,
+            Self::SignalLost => AlarmPriority::Low,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for AlarmKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Spo2Low => "SPO2 LOW",
+            Self::HeartRateLow => "HR LOW",
+            Self::HeartRateHigh => "HR HIGH",
+            Self::SignalLost => "SIGNAL LOST",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AlarmConfig {
+    /// A reading has to stay past its limit this long, ms
+    pub delay_ms: u32,
+    /// How far back inside a limit a reading has to get for the alarm to clear
+    pub heart_rate_hysteresis_bpm: f32,
+    pub spo2_hysteresis: f32,
+    /// No readings this long, after there were some, ms
+    pub signal_lost_delay_ms: u32,
+    /// Acknowledged alarms are quiet this long, ms
+    pub silence_ms: u32,
+    /// Alarms of this priority and up latch
+    pub latching: AlarmPriority,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        AlarmConfig {
+            delay_ms: 10_000,
+            heart_rate_hysteresis_bpm: 5.0,
+            spo2_hysteresis: 2.0,
+            signal_lost_delay_ms: 15_000,
+            // 2 minutes, the usual audio pause
+            silence_ms: 120_000,
+            latching: AlarmPriority::High,
+        }
+    }
+}
+
+/// Alarm that is on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Alarm {
+    pub kind: AlarmKind,
+    /// Condition is over, on until acknowledged
+    pub latched: bool,
+    /// Acknowledged, no sound for now
+    pub silenced: bool,
+}
+
+impl Alarm {
+    pub fn priority(&self) -> AlarmPriority {
+        self.kind.priority()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Inactive,
+    /// Past the limit since, ms
+    Pending(u32),
+    Active,
+    Latched,
+}
+
+/// Where a reading is relative to a limit
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Condition {
+    Alarm,
+    /// Inside the limit, but not by the hysteresis margin yet
+    Margin,
+    Clear,
+}
+
+#[derive(Copy, Clone)]
+struct AlarmState {
+    state: State,
+    /// Acknowledged, quiet until then
+    silenced_until: Option<u32>,
+}
+
+pub struct AlarmEngine {
+    config: AlarmConfig,
+    limits: AlarmLimits,
+    alarms: [AlarmState; 4],
+    /// There were readings, losing them is an alarm
+    monitoring: bool,
+}
+
+impl AlarmEngine {
+    pub fn new(config: AlarmConfig, limits: AlarmLimits) -> Self {
+        AlarmEngine {
+            config,
+            limits,
+            alarms: [AlarmState {
+                state: State::Inactive,
+                silenced_until: None,
+            }; 4],
+            monitoring: false,
+        }
+    }
+
+    pub fn limits(&self) -> AlarmLimits {
+        self.limits
+    }
+
+    /// Takes effect on the next update, with the usual delay and hysteresis
+    pub fn set_limits(&mut self, limits: AlarmLimits) {
+        self.limits = limits;
+    }
+
+    /// Latest readings, `None` when there is none
+    pub fn update(&mut self, now_ms: u32, heart_rate_bpm: Option<f32>, spo2: Option<f32>) {
+        let has_readings = heart_rate_bpm.is_some() || spo2.is_some();
+        if has_readings {
+            self.monitoring = true;
+        }
+
+        for kind in AlarmKind::ALL {
+            let condition = match kind {
+                AlarmKind::Spo2Low => below(
+                    spo2,
+                    self.limits.spo2_low as f32,
+                    self.config.spo2_hysteresis,
+                ),
+                AlarmKind::HeartRateLow => below(
+                    heart_rate_bpm,
+                    self.limits.hr_low_bpm as f32,
+                    self.config.heart_rate_hysteresis_bpm,
+                ),
+                AlarmKind::HeartRateHigh => below(
+                    heart_rate_bpm.map(|hr| -hr),
+                    -(self.limits.hr_high_bpm as f32),
+                    self.config.heart_rate_hysteresis_bpm,
+                ),
+                AlarmKind::SignalLost if self.monitoring && !has_readings => Condition::Alarm,
+                AlarmKind::SignalLost => Condition::Clear,
+            };
+            self.step(kind, condition, now_ms);
+        }
+    }
+
+    fn step(&mut self, kind: AlarmKind, condition: Condition, now_ms: u32) {
+        let delay_ms = match kind {
+            AlarmKind::SignalLost => self.config.signal_lost_delay_ms,
+            _ => self.config.delay_ms,
+        };
+        let latching = kind.priority() >= self.config.latching;
+
+        let alarm = &mut self.alarms[kind.index()];
+        alarm.state = match (alarm.state, condition) {
+            (State::Inactive, Condition::Alarm) => State::Pending(now_ms),
+            (State::Pending(since), Condition::Alarm) => State::Pending(since),
+            (State::Pending(_), _) => State::Inactive,
+            (State::Latched, Condition::Alarm) => State::Active,
+            (State::Active, Condition::Clear) if latching => State::Latched,
+            (State::Active, Condition::Clear) => State::Inactive,
+            (state, _) => state,
+        };
+        if let State::Pending(since) = alarm.state {
+            if now_ms.wrapping_sub(since) >= delay_ms {
+                alarm.state = State::Active;
+            }
+        }
+        if alarm.state == State::Inactive {
+            alarm.silenced_until = None;
+        }
+    }
+
+    /// Latched alarms go away, active ones are silenced for a while.
+    /// Acknowledging a lost signal means monitoring is over, until
+    /// there are readings again.
+    pub fn acknowledge(&mut self, now_ms: u32) {
+        let silence_ms = self.config.silence_ms;
+        for kind in AlarmKind::ALL {
+            let alarm = &mut self.alarms[kind.index()];
+            match (kind, alarm.state) {
+                (AlarmKind::SignalLost, State::Active | State::Latched) => {
+                    alarm.state = State::Inactive;
+                    self.monitoring = false;
+                }
+                (_, State::Latched) => alarm.state = State::Inactive,
+                (_, State::Active) => alarm.silenced_until = Some(now_ms.wrapping_add(silence_ms)),
+                _ => (),
+            }
+            if alarm.state == State::Inactive {
+                alarm.silenced_until = None;
+            }
+        }
+    }
+
+    /// Alarms that are on, highest priority first
+    pub fn alarms(&self, now_ms: u32) -> impl Iterator<Item = Alarm> + '_ {
+        AlarmKind::ALL.iter().filter_map(move |kind| {
+            let alarm = &self.alarms[kind.index()];
+            let latched = match alarm.state {
+                State::Active => false,
+                State::Latched => true,
+                _ => return None,
+            };
+            let silenced = alarm
+                .silenced_until
+                .is_some_and(|until| (until.wrapping_sub(now_ms) as i32) > 0);
+            Some(Alarm {
+                kind: *kind,
+                latched,
+                silenced,
+            })
+        })
+    }
+
+    /// For the banner
+    pub fn highest(&self, now_ms: u32) -> Option<Alarm> {
+        self.alarms(now_ms).next()
+    }
+
+    /// Pattern for the loudest alarm that isn't silenced
+    pub fn tone(&self, now_ms: u32) -> Option<AlarmTone> {
+        self.alarms(now_ms)
+            .find(|a| !a.silenced)
+            .map(|a| a.priority().tone())
+    }
+}
+
+/// Low limit check, high limits are checked on negated values
+fn below(value: Option<f32>, limit: f32, hysteresis: f32) -> Condition {
+    match value {
+        Some(v) if v < limit => Condition::Alarm,
+        Some(v) if v < limit + hysteresis => Condition::Margin,
+        // no reading, nothing to alarm about, a lost signal is its own alarm
+        _ => Condition::Clear,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One update a second, like the firmware idle loop
+    fn run(engine: &mut AlarmEngine, from_s: u32, to_s: u32, hr: Option<f32>, spo2: Option<f32>) {
+        for s in from_s..to_s {
+            engine.update(s * 1000, hr, spo2);
+        }
+    }
+
+    fn kinds(engine: &AlarmEngine, now_ms: u32) -> Vec<AlarmKind> {
+        engine.alarms(now_ms).map(|a| a.kind).collect()
+    }
+
+    fn default_engine() -> AlarmEngine {
+        AlarmEngine::new(AlarmConfig::default(), AlarmLimits::default())
+    }
+
+    #[test]
+    fn test_delay() {
+        let mut engine = default_engine();
+        run(&mut engine, 0, 10, Some(70.0), Some(97.0));
+        assert_eq!(engine.highest(10_000), None);
+        assert_eq!(engine.tone(10_000), None);
+
+        // desaturation, alarm 10s later
+        run(&mut engine, 10, 20, Some(70.0), Some(85.0));
+        assert_eq!(kinds(&engine, 19_000), vec![]);
+        run(&mut engine, 20, 21, Some(70.0), Some(85.0));
+        assert_eq!(
+            engine.highest(20_000),
+            Some(Alarm {
+                kind: AlarmKind::Spo2Low,
+                latched: false,
+                silenced: false,
+            })
+        );
+        assert_eq!(engine.tone(20_000), Some(AlarmTone::High));
+    }
+
+    #[test]
+    fn test_short_excursions() {
+        let mut engine = default_engine();
+        // dips shorter than the delay, over and over
+        for i in 0..10 {
+            let t = i * 20;
+            run(&mut engine, t, t + 9, Some(130.0), Some(85.0));
+            run(&mut engine, t + 9, t + 20, Some(80.0), Some(97.0));
+        }
+        assert_eq!(kinds(&engine, 200_000), vec![]);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut engine = default_engine();
+        run(&mut engine, 0, 11, Some(130.0), Some(97.0));
+        assert_eq!(kinds(&engine, 11_000), vec![AlarmKind::HeartRateHigh]);
+
+        // just under the limit isn't enough
+        run(&mut engine, 11, 60, Some(117.0), Some(97.0));
+        assert_eq!(kinds(&engine, 60_000), vec![AlarmKind::HeartRateHigh]);
+        run(&mut engine, 60, 61, Some(114.0), Some(97.0));
+        assert_eq!(kinds(&engine, 61_000), vec![]);
+
+        // hovering around the limit while pending starts the delay over
+        run(&mut engine, 61, 66, Some(121.0), Some(97.0));
+        run(&mut engine, 66, 67, Some(119.0), Some(97.0));
+        run(&mut engine, 67, 76, Some(121.0), Some(97.0));
+        assert_eq!(kinds(&engine, 76_000), vec![]);
+        run(&mut engine, 76, 78, Some(121.0), Some(97.0));
+        assert_eq!(kinds(&engine, 78_000), vec![AlarmKind::HeartRateHigh]);
+
+        // low limit the other way around
+        let mut engine = default_engine();
+        run(&mut engine, 0, 11, Some(45.0), Some(97.0));
+        assert_eq!(kinds(&engine, 11_000), vec![AlarmKind::HeartRateLow]);
+        run(&mut engine, 11, 12, Some(52.0), Some(97.0));
+        assert_eq!(kinds(&engine, 12_000), vec![AlarmKind::HeartRateLow]);
+        run(&mut engine, 12, 13, Some(56.0), Some(97.0));
+        assert_eq!(kinds(&engine, 13_000), vec![]);
+    }
+
+    #[test]
+    fn test_latching() {
+        let mut engine = default_engine();
+        run(&mut engine, 0, 11, Some(70.0), Some(85.0));
+        run(&mut engine, 11, 20, Some(70.0), Some(97.0));
+        // over, but still on and sounding
+        let alarm = engine.highest(20_000).unwrap();
+        assert_eq!(alarm.kind, AlarmKind::Spo2Low);
+        assert!(alarm.latched);
+        assert_eq!(engine.tone(20_000), Some(AlarmTone::High));
+
+        // back again while latched, no new delay
+        run(&mut engine, 20, 21, Some(70.0), Some(85.0));
+        assert!(!engine.highest(21_000).unwrap().latched);
+
+        run(&mut engine, 21, 22, Some(70.0), Some(97.0));
+        engine.acknowledge(22_000);
+        assert_eq!(engine.highest(22_000), None);
+        assert_eq!(engine.tone(22_000), None);
+    }
+
+    #[test]
+    fn test_acknowledge() {
+        let mut engine = default_engine();
+        run(&mut engine, 0, 11, Some(130.0), Some(97.0));
+        engine.acknowledge(11_000);
+
+        // still on, quiet for the silence period
+        let alarm = engine.highest(12_000).unwrap();
+        assert_eq!(alarm.kind, AlarmKind::HeartRateHigh);
+        assert!(alarm.silenced);
+        assert_eq!(engine.tone(12_000), None);
+        run(&mut engine, 11, 130, Some(130.0), Some(97.0));
+        assert_eq!(engine.tone(129_000), None);
+        run(&mut engine, 130, 132, Some(130.0), Some(97.0));
+        assert_eq!(engine.tone(131_000), Some(AlarmTone::Medium));
+
+        // a new alarm sounds right away, silenced ones stay quiet
+        engine.acknowledge(132_000);
+        run(&mut engine, 132, 143, Some(130.0), Some(85.0));
+        assert_eq!(
+            kinds(&engine, 143_000),
+            vec![AlarmKind::Spo2Low, AlarmKind::HeartRateHigh]
+        );
+        assert_eq!(engine.tone(143_000), Some(AlarmTone::High));
+
+        // cleared and back, it's a new alarm
+        run(&mut engine, 143, 144, Some(100.0), Some(85.0));
+        run(&mut engine, 144, 155, Some(130.0), Some(85.0));
+        let hr = engine.alarms(155_000).nth(1).unwrap();
+        assert_eq!(hr.kind, AlarmKind::HeartRateHigh);
+        assert!(!hr.silenced);
+    }
+
+    #[test]
+    fn test_signal_lost() {
+        let mut engine = default_engine();
+        // nothing to lose yet
+        run(&mut engine, 0, 30, None, None);
+        assert_eq!(kinds(&engine, 30_000), vec![]);
+
+        run(&mut engine, 30, 40, Some(70.0), Some(97.0));
+        run(&mut engine, 40, 55, None, None);
+        assert_eq!(kinds(&engine, 55_000), vec![]);
+        run(&mut engine, 55, 56, None, None);
+        assert_eq!(kinds(&engine, 56_000), vec![AlarmKind::SignalLost]);
+        assert_eq!(engine.tone(56_000), Some(AlarmTone::Low));
+
+        // back, and gone again
+        run(&mut engine, 56, 57, Some(70.0), Some(97.0));
+        assert_eq!(kinds(&engine, 57_000), vec![]);
+        run(&mut engine, 57, 73, None, None);
+        assert_eq!(kinds(&engine, 73_000), vec![AlarmKind::SignalLost]);
+
+        // acknowledged, finger is off for good
+        engine.acknowledge(73_000);
+        run(&mut engine, 73, 200, None, None);
+        assert_eq!(kinds(&engine, 200_000), vec![]);
+    }
+
+    #[test]
+    fn test_set_limits() {
+        let mut engine = default_engine();
+        run(&mut engine, 0, 20, Some(110.0), Some(93.0));
+        assert_eq!(kinds(&engine, 20_000), vec![]);
+
+        engine.set_limits(AlarmLimits {
+            hr_low_bpm: 40,
+            hr_high_bpm: 100,
+            spo2_low: 95,
+        });
+        run(&mut engine, 20, 31, Some(110.0), Some(93.0));
+        assert_eq!(
+            kinds(&engine, 31_000),
+            vec![AlarmKind::Spo2Low, AlarmKind::HeartRateHigh]
+        );
+    }
+}
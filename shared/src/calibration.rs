@@ -0,0 +1,228 @@
+//! SpO2 calibration, SpO2 (%) from the R ratio
+//!
+//! R is the ratio of the red and IR AC/DC ratios. How it maps onto SpO2
+//! depends on the LED wavelengths and the optics, so the curve comes from
+//! readings taken next to a reference oximeter, see the host `calibrate`
+//! tool. Either a quadratic or a table of points, linearly interpolated.
+//!
+//! Readings outside of what a curve was fitted for are [`Spo2::Invalid`],
+//! not clamped into a plausible looking number.
+
+use core::fmt;
+
+/// Lowest reading reported, below this the R ratio is far outside of
+/// any calibration data, or the signal is not a pulse
+pub const SPO2_MIN: f32 = 50.0;
+pub const SPO2_MAX: f32 = 100.0;
+/// Readings up to this much over 100% are noise around a saturated
+/// reading and shown as 100%, anything higher is invalid
+pub const SPO2_OVERSHOOT: f32 = 2.0;
+
+/// Points in a [`LookupTable`]
+pub const MAX_CALIBRATION_POINTS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Spo2 {
+    /// %, within `SPO2_MIN..=SPO2_MAX`
+    Valid(f32),
+    /// R ratio out of the calibrated range
+    Invalid,
+}
+
+impl Spo2 {
+    /// Clamps a reading just over 100%, anything else out of range is invalid
+    pub fn from_percent(spo2: f32) -> Self {
+        if (SPO2_MIN..=SPO2_MAX).contains(&spo2) {
+            Spo2::Valid(spo2)
+        } else if spo2 > SPO2_MAX && spo2 <= SPO2_MAX + SPO2_OVERSHOOT {
+            Spo2::Valid(SPO2_MAX)
+        } else {
+            Spo2::Invalid
+        }
+    }
+
+    pub fn value(self) -> Option<f32> {
+        match self {
+            Spo2::Valid(spo2) => Some(spo2),
+            Spo2::Invalid => None,
+        }
+    }
+}
+
+/// (R ratio, SpO2 %) points, R strictly increasing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LookupTable {
+    points: [(f32, f32); MAX_CALIBRATION_POINTS],
+    len: usize,
+}
+
+impl LookupTable {
+    /// `None` unless there are 2 to `MAX_CALIBRATION_POINTS` points
+    /// in increasing R order
+    pub fn new(points: &[(f32, f32)]) -> Option<Self> {
+        if !(2..=MAX_CALIBRATION_POINTS).contains(&points.len())
+            || points.windows(2).any(|w| w[0].0 >= w[1].0)
+            || points.iter().any(|(r, s)| !r.is_finite() || !s.is_finite())
+        {
+            return None;
+        }
+        let mut table = LookupTable {
+            points: [(0.0, 0.0); MAX_CALIBRATION_POINTS],
+            len: points.len(),
+        };
+        table.points[..points.len()].copy_from_slice(points);
+        Some(table)
+    }
+
+    pub fn points(&self) -> &[(f32, f32)] {
+        &self.points[..self.len]
+    }
+
+    /// `None` outside of the table
+    fn interpolate(&self, ratio: f32) -> Option<f32> {
+        self.points().windows(2).find_map(|w| {
+            let ((r0, s0), (r1, s1)) = (w[0], w[1]);
+            if (r0..=r1).contains(&ratio) {
+                Some(s0 + (s1 - s0) * (ratio - r0) / (r1 - r0))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Spo2Calibration {
+    /// `c0 + c1 * R + c2 * R^2`, only used for R within `ratio_min..=ratio_max`
+    Polynomial {
+        coefficients: [f32; 3],
+        ratio_min: f32,
+        ratio_max: f32,
+    },
+    Lookup(LookupTable),
+}
+
+impl Default for Spo2Calibration {
+    /// Maxim's reference code curve. It peaks just under 100% at R = 0.34,
+    /// for a lower R it would turn down again.
+    fn default() -> Self {
+        Spo2Calibration::Polynomial {
+            coefficients: [94.845, 30.354, -45.06],
+            ratio_min: 0.3,
+            ratio_max: 1.3,
+        }
+    }
+}
+
+impl Spo2Calibration {
+    pub fn spo2(&self, ratio: f32) -> Spo2 {
+        let spo2 = match self {
+            Spo2Calibration::Polynomial {
+                coefficients: [c0, c1, c2],
+                ratio_min,
+                ratio_max,
+            } => {
+                if !(*ratio_min..=*ratio_max).contains(&ratio) {
+                    return Spo2::Invalid;
+                }
+                (c2 * ratio + c1) * ratio + c0
+            }
+            Spo2Calibration::Lookup(table) => match table.interpolate(ratio) {
+                Some(spo2) => spo2,
+                None => return Spo2::Invalid,
+            },
+        };
+        Spo2::from_percent(spo2)
+    }
+}
+
+/// Same syntax as the console `cal` command
+impl fmt::Display for Spo2Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Spo2Calibration::Polynomial {
+                coefficients: [c0, c1, c2],
+                ratio_min,
+                ratio_max,
+            } => write!(f, "poly {} {} {} {} {}", c0, c1, c2, ratio_min, ratio_max),
+            Spo2Calibration::Lookup(table) => {
+                write!(f, "table")?;
+                for (r, s) in table.points() {
+                    write!(f, " {} {}", r, s)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn valid(spo2: Spo2) -> f32 {
+        spo2.value().unwrap()
+    }
+
+    #[test]
+    fn test_default_polynomial() {
+        let cal = Spo2Calibration::default();
+        assert!((valid(cal.spo2(0.5)) - 98.757).abs() < 1e-3);
+        assert!((valid(cal.spo2(1.0)) - 80.139).abs() < 1e-3);
+        // curve tops out just under 100%
+        let peak = valid(cal.spo2(30.354 / (2.0 * 45.06)));
+        assert!(peak > 99.9 && peak < 100.0);
+
+        // past the turning point, or too low to be real
+        assert_eq!(cal.spo2(0.1), Spo2::Invalid);
+        assert_eq!(cal.spo2(2.0), Spo2::Invalid);
+        assert_eq!(cal.spo2(f32::NAN), Spo2::Invalid);
+    }
+
+    #[test]
+    fn test_clamping() {
+        // steeper curve that goes over 100% at the low end
+        let cal = Spo2Calibration::Polynomial {
+            coefficients: [110.0, -25.0, 0.0],
+            ratio_min: 0.2,
+            ratio_max: 2.0,
+        };
+        assert_eq!(cal.spo2(0.35), Spo2::Valid(100.0));
+        assert_eq!(cal.spo2(0.3), Spo2::Invalid);
+        assert_eq!(cal.spo2(0.8), Spo2::Valid(90.0));
+        assert_eq!(cal.spo2(2.0), Spo2::Valid(60.0));
+
+        assert_eq!(Spo2::from_percent(49.9), Spo2::Invalid);
+        assert_eq!(Spo2::from_percent(f32::NAN), Spo2::Invalid);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let table = LookupTable::new(&[(0.4, 100.0), (1.0, 85.0), (2.0, 60.0)]).unwrap();
+        let cal = Spo2Calibration::Lookup(table);
+        assert_eq!(cal.spo2(0.4), Spo2::Valid(100.0));
+        assert!((valid(cal.spo2(0.7)) - 92.5).abs() < 1e-4);
+        assert_eq!(cal.spo2(1.0), Spo2::Valid(85.0));
+        assert!((valid(cal.spo2(1.6)) - 70.0).abs() < 1e-4);
+        assert_eq!(cal.spo2(0.39), Spo2::Invalid);
+        assert_eq!(cal.spo2(2.01), Spo2::Invalid);
+
+        assert_eq!(LookupTable::new(&[(0.4, 100.0)]), None);
+        assert_eq!(LookupTable::new(&[(1.0, 85.0), (0.4, 100.0)]), None);
+        assert_eq!(LookupTable::new(&[(0.4, 100.0), (0.4, 99.0)]), None);
+        assert_eq!(LookupTable::new(&[(0.5, 90.0); 9]), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Spo2Calibration::default().to_string(),
+            "poly 94.845 30.354 -45.06 0.3 1.3"
+        );
+        let table = LookupTable::new(&[(0.4, 100.0), (1.5, 70.5)]).unwrap();
+        assert_eq!(
+            Spo2Calibration::Lookup(table).to_string(),
+            "table 0.4 100 1.5 70.5"
+        );
+    }
+}
@@ -4,6 +4,7 @@
 pub struct Circ<T, const COUNT: usize> {
     pub data: [T; COUNT],
     next: usize,
+    total_added: usize,
 }
 
 impl<T, const COUNT: usize> Circ<T, COUNT>
@@ -14,12 +15,30 @@ where
         Circ {
             data: [zero; COUNT],
             next: 0,
+            total_added: 0,
         }
     }
 
     pub fn add(&mut self, s: T) {
         self.data[self.next] = s;
         self.next = wrap_next::<COUNT>(self.next);
+        self.total_added = self.total_added.wrapping_add(1);
+    }
+
+    /// `i`-th element, counting from the oldest one
+    pub fn get(&self, i: usize) -> T {
+        self.data[(self.next + i) % COUNT]
+    }
+
+    /// Overwrites all elements, `total_added` keeps counting
+    pub fn clear(&mut self, zero: T) {
+        self.data = [zero; COUNT];
+    }
+
+    /// Number of elements added since creation,
+    /// tells which ones are new since last look.
+    pub fn total_added(&self) -> usize {
+        self.total_added
     }
 
     pub fn iter<'a>(&'a self) -> CircIter<'a, T, COUNT> {
@@ -100,6 +119,11 @@ mod tests {
         c.add(4);
         c.add(5);
         assert_eq!(c.data, [4, 5, 3]);
+        assert_eq!(c.total_added(), 5);
+
+        c.clear(0);
+        assert_eq!(c.data, [0, 0, 0]);
+        assert_eq!(c.total_added(), 5);
     }
 
     #[test]
@@ -122,5 +146,8 @@ mod tests {
 
         let all: Vec<u32> = c.iter().collect();
         assert_eq!(vec![2, 3, 4], all);
+
+        let all: Vec<u32> = (0..3).map(|i| c.get(i)).collect();
+        assert_eq!(vec![2, 3, 4], all);
     }
 }
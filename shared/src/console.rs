@@ -0,0 +1,606 @@
+//! Text command console
+//!
+//! One command per line, words separated by spaces:
+//!
+//! ```text
+//! set led 200         starting LED amplitude, 0..255, 0.2mA steps
+//! set pw 411          pulse width, us
+//! set rate 400        sensor sample rate, sps
+//! set avg 16          samples averaged per FIFO sample
+//! set range 16384     ADC full scale, nA
+//! get hr|spo2|quality|finger|temp|sensor|cal|alarms|touch
+//! cal poly C0 C1 C2 RMIN RMAX     SpO2 = C0 + C1 R + C2 R^2, for R in RMIN..RMAX
+//! cal table R1 S1 R2 S2 ...       2 to 8 (R, SpO2) points, increasing R
+//! cal default         Maxim's reference curve
+//! stream on|off       binary sample/vitals frames
+//! beep on|off         pulse tone, alarms sound regardless
+//! alarm hr 50 120     heart rate alarm limits, bpm
+//! alarm spo2 90       SpO2 low alarm limit, %
+//! ack                 acknowledge alarms
+//! touch cal           calibrate the touch panel, targets on screen
+//! screen wave|big|trend|hrv|setup|next    switch screens, like the tabs
+//! rotate 90|270       screen rotation, degrees CCW
+//! save                keep settings across resets
+//! help
+//! ```
+//!
+//! Parsing only, running the commands is up to the firmware.
+
+use core::{convert::TryFrom, fmt};
+use heapless::Vec;
+
+use crate::calibration::{LookupTable, Spo2Calibration, MAX_CALIBRATION_POINTS};
+use crate::screens::ScreenId;
+use crate::sensor::{
+    AdcRange, PulseWidth, SampleAveraging, SampleRate, SensorSettings, SettingsError,
+};
+use crate::settings::AlarmLimits;
+
+/// Longest accepted command line
+pub const MAX_LINE_LEN: usize = 128;
+
+pub const HELP: &str = "\
+set led 0..255 | pw 69..411 | rate 50..3200 | avg 1..32 | range 2048..16384\r\n\
+get hr | spo2 | quality | finger | temp | sensor | cal | alarms | touch\r\n\
+cal poly C0 C1 C2 RMIN RMAX | table R1 S1 R2 S2 ... | default\r\n\
+stream on | off\r\n\
+beep on | off\r\n\
+alarm hr 25..250 25..250 | spo2 50..99\r\n\
+ack\r\n\
+touch cal\r\n\
+screen wave | big | trend | hrv | setup | next\r\n\
+rotate 90 | 270\r\n\
+save\r\n";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// Line didn't fit, the whole line is dropped
+    LineTooLong,
+    /// Not UTF-8
+    Encoding,
+    UnknownCommand,
+    /// Missing, extra or unknown arguments
+    Arguments,
+    /// Argument isn't one of the allowed values
+    Value,
+    Settings(SettingsError),
+}
+
+impl From<SettingsError> for ConsoleError {
+    fn from(e: SettingsError) -> Self {
+        ConsoleError::Settings(e)
+    }
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleError::LineTooLong => write!(f, "line too long"),
+            ConsoleError::Encoding => write!(f, "not UTF-8"),
+            ConsoleError::UnknownCommand => write!(f, "unknown command, try help"),
+            ConsoleError::Arguments => write!(f, "bad arguments, try help"),
+            ConsoleError::Value => write!(f, "bad value, try help"),
+            ConsoleError::Settings(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Collects bytes into lines, with backspace editing
+pub struct LineBuffer<const N: usize> {
+    buf: Vec<u8, N>,
+    /// last byte ended a line, start over on the next one
+    done: bool,
+    overflow: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub fn new() -> Self {
+        LineBuffer {
+            buf: Vec::new(),
+            done: false,
+            overflow: false,
+        }
+    }
+
+    /// Feeds one byte, returns the line once its end (CR or LF) is seen.
+    /// Empty lines are skipped, so CRLF ends just one line.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&str, ConsoleError>> {
+        if self.done {
+            self.buf.clear();
+            self.done = false;
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                if self.overflow {
+                    self.overflow = false;
+                    self.done = true;
+                    Some(Err(ConsoleError::LineTooLong))
+                } else if self.buf.is_empty() {
+                    None
+                } else {
+                    self.done = true;
+                    Some(core::str::from_utf8(&self.buf).map_err(|_| ConsoleError::Encoding))
+                }
+            }
+            // backspace, DEL
+            0x08 | 0x7f => {
+                self.buf.pop();
+                None
+            }
+            _ => {
+                if self.buf.push(byte).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Changes one sensor setting
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Setting {
+    LedAmplitude(u8),
+    PulseWidth(PulseWidth),
+    SampleRate(SampleRate),
+    Averaging(SampleAveraging),
+    AdcRange(AdcRange),
+}
+
+impl Setting {
+    fn parse(name: &str, value: &str) -> Result<Self, ConsoleError> {
+        let number = value.parse::<u16>().map_err(|_| ConsoleError::Value)?;
+        let setting = match name {
+            "led" => Setting::LedAmplitude(u8::try_from(number).map_err(|_| ConsoleError::Value)?),
+            "pw" => Setting::PulseWidth(PulseWidth::from_us(number).ok_or(ConsoleError::Value)?),
+            "rate" => Setting::SampleRate(SampleRate::from_sps(number).ok_or(ConsoleError::Value)?),
+            "avg" => {
+                Setting::Averaging(SampleAveraging::from_count(number).ok_or(ConsoleError::Value)?)
+            }
+            "range" => {
+                Setting::AdcRange(AdcRange::from_full_scale_na(number).ok_or(ConsoleError::Value)?)
+            }
+            _ => return Err(ConsoleError::Arguments),
+        };
+        Ok(setting)
+    }
+
+    /// `settings` with this one changed, if the sensor can do it
+    pub fn apply(self, settings: &SensorSettings) -> Result<SensorSettings, ConsoleError> {
+        let mut s = *settings;
+        match self {
+            Setting::LedAmplitude(a) => s.led_amplitude = a,
+            Setting::PulseWidth(pw) => s.pulse_width = pw,
+            Setting::SampleRate(sr) => s.sample_rate = sr,
+            Setting::Averaging(sa) => s.averaging = sa,
+            Setting::AdcRange(r) => s.adc_range = r,
+        }
+        s.validate()?;
+        Ok(s)
+    }
+}
+
+/// Changes alarm limits
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlarmSetting {
+    HeartRate { low_bpm: u16, high_bpm: u16 },
+    Spo2Low(u8),
+}
+
+impl AlarmSetting {
+    fn parse<'a>(
+        name: &str,
+        mut values: impl Iterator<Item = &'a str>,
+    ) -> Result<Self, ConsoleError> {
+        let mut number = || -> Result<u16, ConsoleError> {
+            let value = values.next().ok_or(ConsoleError::Arguments)?;
+            value.parse::<u16>().map_err(|_| ConsoleError::Value)
+        };
+        let setting = match name {
+            "hr" => {
+                let (low_bpm, high_bpm) = (number()?, number()?);
+                let limits = AlarmLimits {
+                    hr_low_bpm: low_bpm,
+                    hr_high_bpm: high_bpm,
+                    ..AlarmLimits::default()
+                };
+                if !limits.is_valid() {
+                    return Err(ConsoleError::Value);
+                }
+                AlarmSetting::HeartRate { low_bpm, high_bpm }
+            }
+            "spo2" => match u8::try_from(number()?) {
+                Ok(low) if AlarmLimits::SPO2_RANGE.contains(&low) => AlarmSetting::Spo2Low(low),
+                _ => return Err(ConsoleError::Value),
+            },
+            _ => return Err(ConsoleError::Arguments),
+        };
+        if values.next().is_some() {
+            return Err(ConsoleError::Arguments);
+        }
+        Ok(setting)
+    }
+
+    /// `limits` with this one changed
+    pub fn apply(self, limits: &AlarmLimits) -> AlarmLimits {
+        let mut l = *limits;
+        match self {
+            AlarmSetting::HeartRate { low_bpm, high_bpm } => {
+                l.hr_low_bpm = low_bpm;
+                l.hr_high_bpm = high_bpm;
+            }
+            AlarmSetting::Spo2Low(low) => l.spo2_low = low,
+        }
+        l
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Query {
+    HeartRate,
+    Spo2,
+    Quality,
+    Finger,
+    /// Sensor die temperature
+    Temperature,
+    /// Sensor part and all its settings
+    Sensor,
+    /// SpO2 calibration curve
+    Calibration,
+    /// Alarm limits and the alarms that are on
+    Alarms,
+    /// Where the touch panel is pressed
+    Touch,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Set(Setting),
+    Get(Query),
+    /// Binary frames on or off
+    Stream(bool),
+    /// Pulse tone on or off
+    Beep(bool),
+    Alarm(AlarmSetting),
+    /// Acknowledges alarms
+    Acknowledge,
+    /// Starts touch panel calibration
+    CalibrateTouch,
+    /// Shows a screen, `None` for the next one
+    Screen(Option<ScreenId>),
+    /// Screen rotation, CCW quarter turns
+    Rotate(u32),
+    /// SpO2 calibration curve
+    Calibrate(Spo2Calibration),
+    /// Writes current settings to flash
+    Save,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, ConsoleError> {
+        let mut words = line.split_ascii_whitespace();
+        let mut arg = || words.next().ok_or(ConsoleError::Arguments);
+
+        let cmd = match arg().map_err(|_| ConsoleError::UnknownCommand)? {
+            "help" | "?" => Command::Help,
+            "save" => Command::Save,
+            "ack" => Command::Acknowledge,
+            "set" => {
+                let name = arg()?;
+                Command::Set(Setting::parse(name, arg()?)?)
+            }
+            "get" => Command::Get(match arg()? {
+                "hr" => Query::HeartRate,
+                "spo2" => Query::Spo2,
+                "quality" => Query::Quality,
+                "finger" => Query::Finger,
+                "temp" => Query::Temperature,
+                "sensor" => Query::Sensor,
+                "cal" => Query::Calibration,
+                "alarms" => Query::Alarms,
+                "touch" => Query::Touch,
+                _ => return Err(ConsoleError::Arguments),
+            }),
+            "stream" => Command::Stream(on_off(arg()?)?),
+            "beep" => Command::Beep(on_off(arg()?)?),
+            "rotate" => {
+                // the layout is landscape only
+                match arg()?.parse::<u32>().map_err(|_| ConsoleError::Value)? {
+                    degrees @ (90 | 270) => Command::Rotate(degrees / 90),
+                    _ => return Err(ConsoleError::Value),
+                }
+            }
+            "touch" => match arg()? {
+                "cal" => Command::CalibrateTouch,
+                _ => return Err(ConsoleError::Arguments),
+            },
+            "screen" => match arg()? {
+                "next" => Command::Screen(None),
+                name => {
+                    Command::Screen(Some(ScreenId::from_name(name).ok_or(ConsoleError::Value)?))
+                }
+            },
+            "alarm" => {
+                let name = arg()?;
+                return AlarmSetting::parse(name, words).map(Command::Alarm);
+            }
+            "cal" => {
+                let kind = arg()?;
+                return parse_calibration(kind, words).map(Command::Calibrate);
+            }
+            _ => return Err(ConsoleError::UnknownCommand),
+        };
+
+        if words.next().is_some() {
+            return Err(ConsoleError::Arguments);
+        }
+        Ok(cmd)
+    }
+}
+
+fn on_off(word: &str) -> Result<bool, ConsoleError> {
+    match word {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ConsoleError::Value),
+    }
+}
+
+/// `words` are the rest of the line after `cal KIND`
+fn parse_calibration<'a>(
+    kind: &str,
+    words: impl Iterator<Item = &'a str>,
+) -> Result<Spo2Calibration, ConsoleError> {
+    let mut numbers: Vec<f32, { 2 * MAX_CALIBRATION_POINTS }> = Vec::new();
+    for word in words {
+        let x = word.parse::<f32>().map_err(|_| ConsoleError::Value)?;
+        if !x.is_finite() {
+            return Err(ConsoleError::Value);
+        }
+        numbers.push(x).map_err(|_| ConsoleError::Arguments)?;
+    }
+
+    match (kind, numbers.as_slice()) {
+        ("default", []) => Ok(Spo2Calibration::default()),
+        ("poly", [c0, c1, c2, ratio_min, ratio_max]) => {
+            if ratio_min >= ratio_max {
+                return Err(ConsoleError::Value);
+            }
+            Ok(Spo2Calibration::Polynomial {
+                coefficients: [*c0, *c1, *c2],
+                ratio_min: *ratio_min,
+                ratio_max: *ratio_max,
+            })
+        }
+        ("table", _) if numbers.len() % 2 == 0 => {
+            let mut points: Vec<(f32, f32), MAX_CALIBRATION_POINTS> = Vec::new();
+            for pair in numbers.chunks(2) {
+                let _ = points.push((pair[0], pair[1]));
+            }
+            LookupTable::new(&points)
+                .map(Spo2Calibration::Lookup)
+                .ok_or(ConsoleError::Value)
+        }
+        _ => Err(ConsoleError::Arguments),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines<const N: usize>(
+        lb: &mut LineBuffer<N>,
+        input: &[u8],
+    ) -> std::vec::Vec<Result<String, ConsoleError>> {
+        input
+            .iter()
+            .filter_map(|b| lb.push(*b).map(|line| line.map(String::from)))
+            .collect()
+    }
+
+    #[test]
+    fn test_line_buffer() {
+        let mut lb: LineBuffer<8> = LineBuffer::new();
+        assert_eq!(
+            lines(&mut lb, b"get hr\r\n\r\nhelp\nsetx\x08 "),
+            [Ok("get hr".into()), Ok("help".into())]
+        );
+        assert_eq!(lines(&mut lb, b"led\r"), [Ok("set led".into())]);
+
+        assert_eq!(
+            lines(&mut lb, b"123456789\nok\n"),
+            [Err(ConsoleError::LineTooLong), Ok("ok".into())]
+        );
+        assert_eq!(lines(&mut lb, b"\xff\xfe\n"), [Err(ConsoleError::Encoding)]);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("help"), Ok(Command::Help));
+        assert_eq!(
+            Command::parse("  set   led 200 "),
+            Ok(Command::Set(Setting::LedAmplitude(200)))
+        );
+        assert_eq!(
+            Command::parse("set avg 16"),
+            Ok(Command::Set(Setting::Averaging(SampleAveraging::Sa16)))
+        );
+        assert_eq!(
+            Command::parse("set pw 118"),
+            Ok(Command::Set(Setting::PulseWidth(PulseWidth::Us118)))
+        );
+        assert_eq!(Command::parse("get hr"), Ok(Command::Get(Query::HeartRate)));
+        assert_eq!(
+            Command::parse("get temp"),
+            Ok(Command::Get(Query::Temperature))
+        );
+        assert_eq!(Command::parse("stream off"), Ok(Command::Stream(false)));
+        assert_eq!(Command::parse("beep on"), Ok(Command::Beep(true)));
+        assert_eq!(Command::parse("rotate 270"), Ok(Command::Rotate(3)));
+        assert_eq!(Command::parse("save"), Ok(Command::Save));
+        assert_eq!(
+            Command::parse("get cal"),
+            Ok(Command::Get(Query::Calibration))
+        );
+        assert_eq!(
+            Command::parse("get alarms"),
+            Ok(Command::Get(Query::Alarms))
+        );
+        assert_eq!(Command::parse("get touch"), Ok(Command::Get(Query::Touch)));
+        assert_eq!(Command::parse("ack"), Ok(Command::Acknowledge));
+        assert_eq!(Command::parse("touch cal"), Ok(Command::CalibrateTouch));
+        assert_eq!(
+            Command::parse("screen trend"),
+            Ok(Command::Screen(Some(ScreenId::Trends)))
+        );
+        assert_eq!(Command::parse("screen next"), Ok(Command::Screen(None)));
+        assert_eq!(Command::parse("screen foo"), Err(ConsoleError::Value));
+    }
+
+    #[test]
+    fn test_parse_alarm() {
+        assert_eq!(
+            Command::parse("alarm hr 40 140"),
+            Ok(Command::Alarm(AlarmSetting::HeartRate {
+                low_bpm: 40,
+                high_bpm: 140
+            }))
+        );
+        assert_eq!(
+            Command::parse("alarm spo2 88"),
+            Ok(Command::Alarm(AlarmSetting::Spo2Low(88)))
+        );
+        assert_eq!(
+            AlarmSetting::Spo2Low(85).apply(&AlarmLimits::default()),
+            AlarmLimits {
+                spo2_low: 85,
+                ..AlarmLimits::default()
+            }
+        );
+
+        assert_eq!(Command::parse("alarm"), Err(ConsoleError::Arguments));
+        assert_eq!(Command::parse("alarm hr 40"), Err(ConsoleError::Arguments));
+        assert_eq!(
+            Command::parse("alarm hr 40 140 1"),
+            Err(ConsoleError::Arguments)
+        );
+        assert_eq!(Command::parse("alarm hr 140 40"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("alarm hr 0 140"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("alarm hr 40 300"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("alarm spo2 100"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("alarm spo2 x"), Err(ConsoleError::Value));
+        assert_eq!(
+            Command::parse("alarm temp 30"),
+            Err(ConsoleError::Arguments)
+        );
+        assert_eq!(Command::parse("ack now"), Err(ConsoleError::Arguments));
+    }
+
+    #[test]
+    fn test_parse_calibration() {
+        assert_eq!(
+            Command::parse("cal default"),
+            Ok(Command::Calibrate(Spo2Calibration::default()))
+        );
+        let poly = Spo2Calibration::Polynomial {
+            coefficients: [101.5, -3.0, -20.25],
+            ratio_min: 0.4,
+            ratio_max: 1.5,
+        };
+        assert_eq!(
+            Command::parse("cal poly 101.5 -3 -20.25 0.4 1.5"),
+            Ok(Command::Calibrate(poly))
+        );
+        // what `get cal` prints parses back
+        let line = format!("cal {}", poly);
+        assert_eq!(Command::parse(&line), Ok(Command::Calibrate(poly)));
+
+        let table = LookupTable::new(&[(0.4, 100.0), (1.0, 85.0), (2.0, 60.0)]).unwrap();
+        assert_eq!(
+            Command::parse("cal table 0.4 100 1 85 2 60"),
+            Ok(Command::Calibrate(Spo2Calibration::Lookup(table)))
+        );
+
+        assert_eq!(Command::parse("cal"), Err(ConsoleError::Arguments));
+        assert_eq!(
+            Command::parse("cal poly 1 2 3 4"),
+            Err(ConsoleError::Arguments)
+        );
+        assert_eq!(
+            Command::parse("cal poly 1 2 3 1.5 0.4"),
+            Err(ConsoleError::Value)
+        );
+        assert_eq!(
+            Command::parse("cal poly 1 2 x 0 1"),
+            Err(ConsoleError::Value)
+        );
+        assert_eq!(
+            Command::parse("cal poly 1 2 inf 0 1"),
+            Err(ConsoleError::Value)
+        );
+        assert_eq!(
+            Command::parse("cal table 0.4 100 1"),
+            Err(ConsoleError::Arguments)
+        );
+        // R has to increase
+        assert_eq!(
+            Command::parse("cal table 1 85 0.4 100"),
+            Err(ConsoleError::Value)
+        );
+        let too_many = format!("cal table{}", " 1 90".repeat(9));
+        assert_eq!(Command::parse(&too_many), Err(ConsoleError::Arguments));
+        assert_eq!(
+            Command::parse("cal default 1"),
+            Err(ConsoleError::Arguments)
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Command::parse(""), Err(ConsoleError::UnknownCommand));
+        assert_eq!(Command::parse("reboot"), Err(ConsoleError::UnknownCommand));
+        assert_eq!(Command::parse("set led"), Err(ConsoleError::Arguments));
+        assert_eq!(Command::parse("set led 256"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("set led -1"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("set avg 3"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("set foo 1"), Err(ConsoleError::Arguments));
+        assert_eq!(Command::parse("get hr now"), Err(ConsoleError::Arguments));
+        assert_eq!(Command::parse("stream maybe"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("beep"), Err(ConsoleError::Arguments));
+        assert_eq!(Command::parse("rotate 0"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("rotate 45"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("rotate 180"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("rotate 360"), Err(ConsoleError::Value));
+        assert_eq!(Command::parse("touch"), Err(ConsoleError::Arguments));
+        assert_eq!(Command::parse("touch x"), Err(ConsoleError::Arguments));
+    }
+
+    #[test]
+    fn test_apply() {
+        let s = SensorSettings::default();
+        assert_eq!(
+            Setting::LedAmplitude(50).apply(&s).map(|s| s.led_amplitude),
+            Ok(50)
+        );
+        // 400sps / 8 = 50Hz
+        assert_eq!(
+            Setting::Averaging(SampleAveraging::Sa8)
+                .apply(&s)
+                .map(|s| s.sample_rate_hz()),
+            Ok(50)
+        );
+        assert_eq!(
+            Setting::SampleRate(SampleRate::Sps1000).apply(&s),
+            Err(ConsoleError::Settings(SettingsError::PulseWidth))
+        );
+    }
+}
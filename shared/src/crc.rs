@@ -0,0 +1,32 @@
+//! CRC-16/CCITT-FALSE, poly 0x1021, init 0xffff
+
+pub fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0xffff, data)
+}
+
+/// Continues a CRC over more data
+pub fn crc16_update(mut crc: u16, data: &[u8]) -> u16 {
+    for b in data.iter() {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+        assert_eq!(crc16_update(crc16(b"1234"), b"56789"), 0x29b1);
+    }
+}
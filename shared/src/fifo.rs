@@ -0,0 +1,738 @@
+//! MAX30102 FIFO access and decoding
+//!
+//! <https://datasheets.maximintegrated.com/en/ds/MAX30102.pdf>, "FIFO Configuration".
+//!
+//! Every sample is 3 bytes per active LED slot, MSB first, left
+//! justified 18 bit ADC value. Number of slots depends on the mode:
+//! heart rate mode has a single (red) slot, SpO2 mode has 2 of them,
+//! multi-LED mode as many as configured.
+//!
+//! MAX30101 and MAX30105 FIFOs work the same, see [`crate::ppg`].
+//!
+//! Sensor INT pin (active low, open drain) can signal new data,
+//! it's released by reading the interrupt status register.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+pub const I2C_ADDRESS: u8 = 0x57;
+
+/// Number of samples the sensor can hold
+pub const FIFO_DEPTH: usize = 32;
+
+const BYTES_PER_SLOT: usize = 3;
+const MAX_SLOTS: usize = 3;
+const SLOT_MASK: u32 = 0x3_FFFF;
+
+pub mod register {
+    pub const INT_STATUS_1: u8 = 0x00;
+    pub const INT_ENABLE_1: u8 = 0x02;
+    pub const FIFO_WR_PTR: u8 = 0x04;
+    pub const OVF_COUNTER: u8 = 0x05;
+    pub const FIFO_RD_PTR: u8 = 0x06;
+    pub const FIFO_DATA: u8 = 0x07;
+    pub const FIFO_CONFIG: u8 = 0x08;
+    pub const MODE_CONFIG: u8 = 0x09;
+    pub const SPO2_CONFIG: u8 = 0x0a;
+    pub const LED1_PA: u8 = 0x0c;
+    pub const LED2_PA: u8 = 0x0d;
+    /// MAX30101/MAX30105 green LED, reserved on the MAX30102
+    pub const LED3_PA: u8 = 0x0e;
+    pub const MULTI_LED_CTRL1: u8 = 0x11;
+    pub const MULTI_LED_CTRL2: u8 = 0x12;
+    pub const TEMP_INT: u8 = 0x1f;
+    pub const TEMP_FRAC: u8 = 0x20;
+    pub const TEMP_CONFIG: u8 = 0x21;
+    pub const PART_ID: u8 = 0xff;
+}
+
+/// INT_STATUS_1 / INT_ENABLE_1 bits
+const INT_A_FULL: u8 = 1 << 7;
+const INT_PPG_RDY: u8 = 1 << 6;
+
+/// FIFO_CONFIG almost full level bits
+pub(crate) const FIFO_A_FULL_MASK: u8 = 0x0f;
+
+/// What should pull the INT pin low
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FifoInterrupt {
+    /// FIFO has only `free_slots` (0-15) empty slots left,
+    /// i.e. it holds `FIFO_DEPTH - free_slots` samples.
+    AlmostFull { free_slots: u8 },
+    /// Every new sample
+    NewSample,
+}
+
+/// INT_STATUS_1 register value
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InterruptStatus(pub u8);
+
+impl InterruptStatus {
+    pub fn almost_full(self) -> bool {
+        self.0 & INT_A_FULL != 0
+    }
+
+    pub fn new_sample(self) -> bool {
+        self.0 & INT_PPG_RDY != 0
+    }
+}
+
+/// Sensor mode, as set in the mode configuration register
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LedMode {
+    /// Red LED only
+    HeartRate,
+    /// Red and IR LEDs
+    SpO2,
+    /// Red, IR and green LEDs, one per slot in that order
+    MultiLed,
+}
+
+impl LedMode {
+    /// Number of LED slots in a FIFO sample
+    pub fn slots(self) -> usize {
+        match self {
+            LedMode::HeartRate => 1,
+            LedMode::SpO2 => 2,
+            LedMode::MultiLed => 3,
+        }
+    }
+}
+
+/// Order of red/IR values in a FIFO sample in SpO2 and multi-LED mode,
+/// which LED the LED1 and LED2 drivers light up.
+///
+/// Per datasheet LED1 is red and LED2 IR, in SpO2 mode the 1st slot is
+/// LED1 and the 2nd one LED2: [`ChannelOrder::DATASHEET`], the default.
+/// Boards known to differ have a named constant.
+///
+/// To check a board, light LED1 only (`LED1_PA` > 0, `LED2_PA` = 0):
+/// a visible red glow is the datasheet order, none at all means LED1
+/// drives the IR LED.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelOrder {
+    RedIr,
+    IrRed,
+}
+
+impl ChannelOrder {
+    /// MAX30102 datasheet, LED1 red, LED2 IR
+    pub const DATASHEET: ChannelOrder = ChannelOrder::RedIr;
+
+    /// The MH-ET LIVE MAX30102 module this firmware was developed on.
+    /// No vendor document says so, it's what was observed: with the
+    /// datasheet order its SpO2 readings made no sense, the first
+    /// firmware read the FIFO swapped (with a TODO about it). Worth
+    /// confirming with the check above on another batch of modules.
+    pub const MH_ET_LIVE: ChannelOrder = ChannelOrder::IrRed;
+}
+
+impl Default for ChannelOrder {
+    fn default() -> Self {
+        Self::DATASHEET
+    }
+}
+
+/// Raw ADC values of a single FIFO sample
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FifoSample {
+    pub red: u32,
+    /// 0 in heart rate mode
+    pub ir: u32,
+    /// 0 except in multi-LED mode
+    pub green: u32,
+}
+
+impl FifoSample {
+    pub fn zero() -> Self {
+        FifoSample {
+            red: 0,
+            ir: 0,
+            green: 0,
+        }
+    }
+}
+
+/// FIFO_WR_PTR, OVF_COUNTER, FIFO_RD_PTR register values
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FifoPointers {
+    pub write: u8,
+    pub overflow: u8,
+    pub read: u8,
+}
+
+impl FifoPointers {
+    /// From 3 consecutive registers, starting at FIFO_WR_PTR
+    pub fn from_registers(regs: [u8; 3]) -> Self {
+        FifoPointers {
+            write: regs[0] & 0x1f,
+            overflow: regs[1] & 0x1f,
+            read: regs[2] & 0x1f,
+        }
+    }
+
+    /// Number of samples waiting to be read.
+    /// Equal pointers mean either an empty or a full FIFO,
+    /// overflow counter tells them apart.
+    pub fn available(&self) -> usize {
+        let n = (self.write.wrapping_sub(self.read) & 0x1f) as usize;
+        if n == 0 && self.overflow > 0 {
+            FIFO_DEPTH
+        } else {
+            n
+        }
+    }
+}
+
+/// Result of a FIFO read
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FifoRead {
+    /// Samples written to the output buffer
+    pub num_samples: usize,
+    /// Samples lost since the previous read, FIFO was full.
+    /// Sensor counter saturates at 31 and resets on every FIFO read.
+    pub overflow: usize,
+}
+
+/// 3 bytes, MSB first
+pub fn decode_slot(bytes: &[u8]) -> u32 {
+    ((bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32) & SLOT_MASK
+}
+
+/// Decodes raw FIFO data into `out`, returns the number of decoded samples.
+/// Trailing incomplete sample bytes are ignored.
+pub fn decode_samples(
+    data: &[u8],
+    mode: LedMode,
+    order: ChannelOrder,
+    out: &mut [FifoSample],
+) -> usize {
+    let sample_len = mode.slots() * BYTES_PER_SLOT;
+    let mut n = 0;
+    for (bytes, s) in data.chunks_exact(sample_len).zip(out.iter_mut()) {
+        let slot = |i: usize| decode_slot(&bytes[(i * BYTES_PER_SLOT)..]);
+        let green = if mode == LedMode::MultiLed {
+            slot(2)
+        } else {
+            0
+        };
+        *s = match (mode, order) {
+            (LedMode::HeartRate, _) => FifoSample {
+                red: slot(0),
+                ir: 0,
+                green,
+            },
+            (_, ChannelOrder::RedIr) => FifoSample {
+                red: slot(0),
+                ir: slot(1),
+                green,
+            },
+            (_, ChannelOrder::IrRed) => FifoSample {
+                red: slot(1),
+                ir: slot(0),
+                green,
+            },
+        };
+        n += 1;
+    }
+    n
+}
+
+/// Reads samples straight from the sensor FIFO registers,
+/// sensor configuration is up to the caller.
+pub struct Max30102Fifo<I2C> {
+    i2c: I2C,
+    mode: LedMode,
+    order: ChannelOrder,
+}
+
+impl<I2C, E> Max30102Fifo<I2C>
+where
+    I2C: WriteRead<Error = E> + Write<Error = E>,
+{
+    pub fn new(i2c: I2C, mode: LedMode, order: ChannelOrder) -> Self {
+        Max30102Fifo { i2c, mode, order }
+    }
+
+    pub fn destroy(self) -> I2C {
+        self.i2c
+    }
+
+    #[cfg(test)]
+    pub(crate) fn i2c(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+
+    pub(crate) fn read_register(&mut self, reg: u8) -> Result<u8, E> {
+        let mut data = [0];
+        self.i2c.write_read(I2C_ADDRESS, &[reg], &mut data)?;
+        Ok(data[0])
+    }
+
+    pub(crate) fn write_register(&mut self, reg: u8, value: u8) -> Result<(), E> {
+        self.i2c.write(I2C_ADDRESS, &[reg, value])
+    }
+
+    /// Enables one of the FIFO interrupts, disables the rest.
+    /// Other FIFO settings (averaging, rollover) are kept.
+    pub fn enable_interrupt(&mut self, int: FifoInterrupt) -> Result<(), E> {
+        let enable = match int {
+            FifoInterrupt::AlmostFull { free_slots } => {
+                let cfg = self.read_register(register::FIFO_CONFIG)?;
+                self.write_register(
+                    register::FIFO_CONFIG,
+                    (cfg & !FIFO_A_FULL_MASK) | (free_slots & FIFO_A_FULL_MASK),
+                )?;
+                INT_A_FULL
+            }
+            FifoInterrupt::NewSample => INT_PPG_RDY,
+        };
+        self.write_register(register::INT_ENABLE_1, enable)?;
+
+        // release INT pin, whatever was pending
+        self.read_interrupt_status()?;
+        Ok(())
+    }
+
+    /// LED currents, 0.2mA per step. With [`ChannelOrder::IrRed`]
+    /// the LED1 driver lights up the IR LED.
+    pub fn set_led_amplitudes(&mut self, red: u8, ir: u8) -> Result<(), E> {
+        let (led1, led2) = match self.order {
+            ChannelOrder::RedIr => (red, ir),
+            ChannelOrder::IrRed => (ir, red),
+        };
+        self.write_register(register::LED1_PA, led1)?;
+        self.write_register(register::LED2_PA, led2)
+    }
+
+    /// Reading the status clears it and releases the INT pin
+    pub fn read_interrupt_status(&mut self) -> Result<InterruptStatus, E> {
+        Ok(InterruptStatus(self.read_register(register::INT_STATUS_1)?))
+    }
+
+    /// To be called when INT pin goes low.
+    /// Clears the interrupt, reads pending samples if there are any.
+    pub fn on_interrupt(&mut self, out: &mut [FifoSample]) -> Result<FifoRead, E> {
+        let status = self.read_interrupt_status()?;
+        if status.almost_full() || status.new_sample() {
+            self.read_samples(out)
+        } else {
+            Ok(FifoRead {
+                num_samples: 0,
+                overflow: 0,
+            })
+        }
+    }
+
+    pub fn read_pointers(&mut self) -> Result<FifoPointers, E> {
+        let mut regs = [0; 3];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[register::FIFO_WR_PTR], &mut regs)?;
+        Ok(FifoPointers::from_registers(regs))
+    }
+
+    /// Reads as many pending samples as fit in `out`.
+    pub fn read_samples(&mut self, out: &mut [FifoSample]) -> Result<FifoRead, E> {
+        let ptrs = self.read_pointers()?;
+        let n = ptrs.available().min(out.len());
+        if n == 0 {
+            return Ok(FifoRead {
+                num_samples: 0,
+                overflow: ptrs.overflow as usize,
+            });
+        }
+
+        let mut data = [0; FIFO_DEPTH * MAX_SLOTS * BYTES_PER_SLOT];
+        let data = &mut data[..(n * self.mode.slots() * BYTES_PER_SLOT)];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[register::FIFO_DATA], data)?;
+
+        Ok(FifoRead {
+            num_samples: decode_samples(data, self.mode, self.order, out),
+            overflow: ptrs.overflow as usize,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointers() {
+        let p = FifoPointers::from_registers([5, 0, 2]);
+        assert_eq!(p.available(), 3);
+
+        // write pointer wrapped around
+        let p = FifoPointers::from_registers([1, 0, 30]);
+        assert_eq!(p.available(), 3);
+
+        let p = FifoPointers::from_registers([7, 0, 7]);
+        assert_eq!(p.available(), 0);
+
+        // full, rolled over a few times
+        let p = FifoPointers::from_registers([7, 3, 7]);
+        assert_eq!(p.available(), FIFO_DEPTH);
+
+        // reserved bits are ignored
+        let p = FifoPointers::from_registers([0xe5, 0xe0, 0xe2]);
+        assert_eq!(
+            p,
+            FifoPointers {
+                write: 5,
+                overflow: 0,
+                read: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_slot() {
+        assert_eq!(decode_slot(&[0x00, 0x00, 0x01]), 1);
+        assert_eq!(decode_slot(&[0x01, 0x02, 0x03]), 0x01_0203);
+        // only 18 bits are used
+        assert_eq!(decode_slot(&[0xff, 0xff, 0xff]), 0x3_ffff);
+    }
+
+    /// Two SpO2 mode samples, LED1 slot first
+    const SPO2_FIFO: [u8; 12] = [
+        0x00, 0x10, 0x00, // LED1: 4096
+        0x01, 0x00, 0x00, // LED2: 65536
+        0x00, 0x10, 0x01, // LED1: 4097
+        0x01, 0x00, 0x02, // LED2: 65538
+    ];
+
+    #[test]
+    fn test_decode_spo2_red_ir() {
+        let mut out = [FifoSample::zero(); 4];
+        let n = decode_samples(&SPO2_FIFO, LedMode::SpO2, ChannelOrder::RedIr, &mut out);
+        assert_eq!(n, 2);
+        assert_eq!(
+            out[..n],
+            [
+                FifoSample {
+                    red: 4096,
+                    ir: 65536,
+                    green: 0
+                },
+                FifoSample {
+                    red: 4097,
+                    ir: 65538,
+                    green: 0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_spo2_ir_red() {
+        let mut out = [FifoSample::zero(); 4];
+        let n = decode_samples(&SPO2_FIFO, LedMode::SpO2, ChannelOrder::IrRed, &mut out);
+        assert_eq!(n, 2);
+        assert_eq!(
+            out[..n],
+            [
+                FifoSample {
+                    red: 65536,
+                    ir: 4096,
+                    green: 0
+                },
+                FifoSample {
+                    red: 65538,
+                    ir: 4097,
+                    green: 0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_channel_order() {
+        assert_eq!(ChannelOrder::default(), ChannelOrder::DATASHEET);
+
+        // datasheet: slot 1 is LED1, the red one
+        let mut out = [FifoSample::zero(); 4];
+        decode_samples(&SPO2_FIFO, LedMode::SpO2, ChannelOrder::DATASHEET, &mut out);
+        assert_eq!((out[0].red, out[0].ir), (4096, 65536));
+
+        // whichever way the LEDs are wired, what's set as the red LED's
+        // current comes back as red: a slot reads as much light as
+        // its driver puts out
+        for order in [ChannelOrder::DATASHEET, ChannelOrder::MH_ET_LIVE] {
+            let mut fifo = Max30102Fifo::new(MockSensor::new(), LedMode::SpO2, order);
+            fifo.set_led_amplitudes(10, 20).unwrap();
+            let led1 = fifo.i2c.regs[register::LED1_PA as usize];
+            let led2 = fifo.i2c.regs[register::LED2_PA as usize];
+            fifo.i2c.push(led1 as u32, led2 as u32, INT_PPG_RDY);
+
+            let mut out = [FifoSample::zero(); 1];
+            assert_eq!(fifo.on_interrupt(&mut out).unwrap().num_samples, 1);
+            assert_eq!((out[0].red, out[0].ir), (10, 20), "{:?}", order);
+        }
+        assert_ne!(ChannelOrder::MH_ET_LIVE, ChannelOrder::DATASHEET);
+    }
+
+    #[test]
+    fn test_decode_heart_rate() {
+        let mut out = [FifoSample::zero(); 4];
+        let n = decode_samples(
+            &SPO2_FIFO,
+            LedMode::HeartRate,
+            ChannelOrder::RedIr,
+            &mut out,
+        );
+        assert_eq!(n, 4);
+        assert_eq!(
+            out.iter().map(|s| s.red).collect::<Vec<_>>(),
+            vec![4096, 65536, 4097, 65538]
+        );
+        assert!(out.iter().all(|s| s.ir == 0));
+    }
+
+    #[test]
+    fn test_decode_multi_led() {
+        let data = [
+            0x00, 0x00, 0x01, // LED1
+            0x00, 0x00, 0x02, // LED2
+            0x00, 0x00, 0x03, // LED3
+        ];
+        let mut out = [FifoSample::zero(); 4];
+        let n = decode_samples(&data, LedMode::MultiLed, ChannelOrder::IrRed, &mut out);
+        assert_eq!(n, 1);
+        assert_eq!(
+            out[0],
+            FifoSample {
+                red: 2,
+                ir: 1,
+                green: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_partial() {
+        // output buffer is too short
+        let mut out = [FifoSample::zero(); 1];
+        let n = decode_samples(&SPO2_FIFO, LedMode::SpO2, ChannelOrder::RedIr, &mut out);
+        assert_eq!(n, 1);
+        assert_eq!(out[0].red, 4096);
+
+        // incomplete trailing sample
+        let mut out = [FifoSample::zero(); 4];
+        let n = decode_samples(
+            &SPO2_FIFO[..10],
+            LedMode::SpO2,
+            ChannelOrder::RedIr,
+            &mut out,
+        );
+        assert_eq!(n, 1);
+    }
+
+    /// Register level MAX30102 stand-in, or MAX30101/MAX30105 with `green`
+    pub(crate) struct MockSensor {
+        pub(crate) regs: [u8; 0x100],
+        fifo: std::collections::VecDeque<u8>,
+        /// has the green LED and its registers
+        green: bool,
+        /// bytes per FIFO sample
+        sample_len: usize,
+    }
+
+    impl MockSensor {
+        pub(crate) fn new() -> Self {
+            let mut regs = [0; 0x100];
+            regs[register::PART_ID as usize] = 0x15;
+            MockSensor {
+                regs,
+                fifo: std::collections::VecDeque::new(),
+                green: false,
+                sample_len: 6,
+            }
+        }
+
+        pub(crate) fn with_green() -> Self {
+            MockSensor {
+                green: true,
+                ..Self::new()
+            }
+        }
+
+        /// Sensor takes SpO2 mode samples, LED1 slot first
+        pub(crate) fn push(&mut self, led1: u32, led2: u32, int_status: u8) {
+            self.push_slots(&[led1, led2], int_status);
+        }
+
+        /// Any number of slots, all samples have to have the same
+        pub(crate) fn push_slots(&mut self, slots: &[u32], int_status: u8) {
+            self.sample_len = slots.len() * 3;
+            for v in slots.iter() {
+                self.fifo.push_back((v >> 16) as u8);
+                self.fifo.push_back((v >> 8) as u8);
+                self.fifo.push_back(*v as u8);
+            }
+            let wr = &mut self.regs[register::FIFO_WR_PTR as usize];
+            *wr = (*wr + 1) & 0x1f;
+            self.regs[register::INT_STATUS_1 as usize] |= int_status;
+        }
+    }
+
+    impl WriteRead for MockSensor {
+        type Error = ();
+
+        fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            assert_eq!(address, I2C_ADDRESS);
+            let reg = bytes[0];
+            if reg == register::FIFO_DATA {
+                assert_eq!(buffer.len() % self.sample_len, 0);
+                for b in buffer.iter_mut() {
+                    *b = self.fifo.pop_front().ok_or(())?;
+                }
+                let rd = &mut self.regs[register::FIFO_RD_PTR as usize];
+                *rd = (*rd + (buffer.len() / self.sample_len) as u8) & 0x1f;
+                self.regs[register::OVF_COUNTER as usize] = 0;
+            } else {
+                let reg = reg as usize;
+                buffer.copy_from_slice(&self.regs[reg..(reg + buffer.len())]);
+                if reg == register::INT_STATUS_1 as usize {
+                    self.regs[reg] = 0;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Write for MockSensor {
+        type Error = ();
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), ()> {
+            assert_eq!(address, I2C_ADDRESS);
+            // reserved registers ignore writes
+            if self.green || bytes[0] != register::LED3_PA {
+                self.regs[bytes[0] as usize] = bytes[1];
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_samples() {
+        let mut sensor = MockSensor::new();
+        sensor.push(100, 200, 0);
+        sensor.push(101, 201, 0);
+        sensor.push(102, 202, 0);
+
+        let mut fifo = Max30102Fifo::new(sensor, LedMode::SpO2, ChannelOrder::RedIr);
+        let mut out = [FifoSample::zero(); 2];
+        assert_eq!(
+            fifo.read_samples(&mut out),
+            Ok(FifoRead {
+                num_samples: 2,
+                overflow: 0
+            })
+        );
+        assert_eq!(
+            out[1],
+            FifoSample {
+                red: 101,
+                ir: 201,
+                green: 0
+            }
+        );
+
+        // leftover sample is picked up on the next read
+        let mut out = [FifoSample::zero(); FIFO_DEPTH];
+        assert_eq!(fifo.read_samples(&mut out).unwrap().num_samples, 1);
+        assert_eq!(
+            out[0],
+            FifoSample {
+                red: 102,
+                ir: 202,
+                green: 0
+            }
+        );
+        assert_eq!(fifo.read_samples(&mut out).unwrap().num_samples, 0);
+    }
+
+    #[test]
+    fn test_read_samples_overflow() {
+        let mut sensor = MockSensor::new();
+        for i in 0..FIFO_DEPTH {
+            sensor.push(i as u32, 0, 0);
+        }
+        sensor.regs[register::OVF_COUNTER as usize] = 5;
+
+        let mut fifo = Max30102Fifo::new(sensor, LedMode::SpO2, ChannelOrder::RedIr);
+        let mut out = [FifoSample::zero(); FIFO_DEPTH];
+        assert_eq!(
+            fifo.read_samples(&mut out),
+            Ok(FifoRead {
+                num_samples: FIFO_DEPTH,
+                overflow: 5
+            })
+        );
+        assert_eq!(out[FIFO_DEPTH - 1].red, FIFO_DEPTH as u32 - 1);
+    }
+
+    #[test]
+    fn test_enable_interrupt() {
+        let mut sensor = MockSensor::new();
+        // averaging and rollover bits are set
+        sensor.regs[register::FIFO_CONFIG as usize] = 0b1001_0000;
+        sensor.regs[register::INT_STATUS_1 as usize] = 0x01; // power ready
+
+        let mut fifo = Max30102Fifo::new(sensor, LedMode::SpO2, ChannelOrder::RedIr);
+        fifo.enable_interrupt(FifoInterrupt::AlmostFull { free_slots: 15 })
+            .unwrap();
+
+        let sensor = fifo.destroy();
+        assert_eq!(sensor.regs[register::FIFO_CONFIG as usize], 0b1001_1111);
+        assert_eq!(sensor.regs[register::INT_ENABLE_1 as usize], INT_A_FULL);
+        assert_eq!(sensor.regs[register::INT_STATUS_1 as usize], 0);
+
+        let mut fifo = Max30102Fifo::new(sensor, LedMode::SpO2, ChannelOrder::RedIr);
+        fifo.enable_interrupt(FifoInterrupt::NewSample).unwrap();
+        let sensor = fifo.destroy();
+        assert_eq!(sensor.regs[register::INT_ENABLE_1 as usize], INT_PPG_RDY);
+    }
+
+    #[test]
+    fn test_set_led_amplitudes() {
+        let mut fifo = Max30102Fifo::new(MockSensor::new(), LedMode::SpO2, ChannelOrder::RedIr);
+        fifo.set_led_amplitudes(10, 20).unwrap();
+        assert_eq!(fifo.i2c.regs[register::LED1_PA as usize], 10);
+        assert_eq!(fifo.i2c.regs[register::LED2_PA as usize], 20);
+
+        let mut fifo = Max30102Fifo::new(fifo.destroy(), LedMode::SpO2, ChannelOrder::IrRed);
+        fifo.set_led_amplitudes(30, 40).unwrap();
+        assert_eq!(fifo.i2c.regs[register::LED1_PA as usize], 40);
+        assert_eq!(fifo.i2c.regs[register::LED2_PA as usize], 30);
+    }
+
+    #[test]
+    fn test_on_interrupt() {
+        let mut fifo = Max30102Fifo::new(MockSensor::new(), LedMode::SpO2, ChannelOrder::IrRed);
+        let mut out = [FifoSample::zero(); FIFO_DEPTH];
+
+        // nothing of interest pending
+        fifo.i2c.push(1, 2, 0);
+        assert_eq!(fifo.on_interrupt(&mut out).unwrap().num_samples, 0);
+
+        // new sample drains everything there is
+        fifo.i2c.push(3, 4, INT_PPG_RDY);
+        assert_eq!(fifo.on_interrupt(&mut out).unwrap().num_samples, 2);
+        assert_eq!(
+            out[1],
+            FifoSample {
+                red: 4,
+                ir: 3,
+                green: 0
+            }
+        );
+        assert_eq!(fifo.i2c.regs[register::INT_STATUS_1 as usize], 0);
+
+        for i in 0..17 {
+            fifo.i2c.push(i, i, 0);
+        }
+        fifo.i2c.regs[register::INT_STATUS_1 as usize] = INT_A_FULL;
+        assert_eq!(fifo.on_interrupt(&mut out).unwrap().num_samples, 17);
+    }
+}
@@ -0,0 +1,186 @@
+//! Streaming IIR filters, one sample at a time.
+//!
+//! Coefficients follow the RBJ "Audio EQ cookbook":
+//! <https://www.w3.org/TR/audio-eq-cookbook/>
+
+use core::f32::consts::{FRAC_1_SQRT_2, PI};
+
+/// Second order IIR section, transposed direct form II.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+
+    // filter state
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    /// Butterworth (Q = 1/sqrt(2)) low pass filter
+    pub fn low_pass(sample_rate_hz: f32, cutoff_hz: f32) -> Self {
+        let (cos_w0, alpha) = Self::w0_alpha(sample_rate_hz, cutoff_hz);
+        let b1 = 1.0 - cos_w0;
+        Self::normalized(b1 / 2.0, b1, b1 / 2.0, cos_w0, alpha)
+    }
+
+    /// Butterworth (Q = 1/sqrt(2)) high pass filter
+    pub fn high_pass(sample_rate_hz: f32, cutoff_hz: f32) -> Self {
+        let (cos_w0, alpha) = Self::w0_alpha(sample_rate_hz, cutoff_hz);
+        let b1 = -(1.0 + cos_w0);
+        Self::normalized(-b1 / 2.0, b1, -b1 / 2.0, cos_w0, alpha)
+    }
+
+    fn w0_alpha(sample_rate_hz: f32, cutoff_hz: f32) -> (f32, f32) {
+        let w0 = 2.0 * PI * cutoff_hz / sample_rate_hz;
+        (libm::cosf(w0), libm::sinf(w0) / (2.0 * FRAC_1_SQRT_2))
+    }
+
+    fn normalized(b0: f32, b1: f32, b2: f32, cos_w0: f32, alpha: f32) -> Self {
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: -2.0 * cos_w0 / a0,
+            a2: (1.0 - alpha) / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// Filter gain at 0Hz
+    pub fn dc_gain(&self) -> f32 {
+        (self.b0 + self.b1 + self.b2) / (1.0 + self.a1 + self.a2)
+    }
+
+    /// Clears filter state, as if it was fed zeroes forever
+    pub fn reset(&mut self) {
+        self.reset_to(0.0);
+    }
+
+    /// Sets filter state as if it was fed a constant input
+    /// forever, avoids a large startup transient on signals
+    /// with a big DC offset.
+    pub fn reset_to(&mut self, x: f32) {
+        let y = self.dc_gain() * x;
+        self.z1 = y - self.b0 * x;
+        self.z2 = self.b2 * x - self.a2 * y;
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
+
+/// Band pass filter, high pass followed by a low pass section.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BandPass {
+    high_pass: Biquad,
+    low_pass: Biquad,
+}
+
+impl BandPass {
+    pub fn new(sample_rate_hz: f32, low_cutoff_hz: f32, high_cutoff_hz: f32) -> Self {
+        BandPass {
+            high_pass: Biquad::high_pass(sample_rate_hz, low_cutoff_hz),
+            low_pass: Biquad::low_pass(sample_rate_hz, high_cutoff_hz),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.reset_to(0.0);
+    }
+
+    /// Sets filter state as if it was fed a constant input forever.
+    pub fn reset_to(&mut self, x: f32) {
+        self.high_pass.reset_to(x);
+        self.low_pass.reset_to(self.high_pass.dc_gain() * x);
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        self.low_pass.process(self.high_pass.process(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f32 = 25.0;
+
+    /// Steady state amplitude of a filter response to a unit sine wave
+    fn gain_at<F: FnMut(f32) -> f32>(mut filter: F, freq_hz: f32) -> f32 {
+        let settle = (20.0 * FS) as usize;
+        let measure = (20.0 * FS) as usize;
+        let mut peak: f32 = 0.0;
+        for i in 0..(settle + measure) {
+            let x = libm::sinf(2.0 * PI * freq_hz * i as f32 / FS);
+            let y = filter(x);
+            if i >= settle {
+                peak = peak.max(y.abs());
+            }
+        }
+        peak
+    }
+
+    fn assert_close(a: f32, b: f32, eps: f32) {
+        assert!((a - b).abs() < eps, "{} != {} (+/- {})", a, b, eps);
+    }
+
+    #[test]
+    fn test_low_pass_response() {
+        let mut lp = Biquad::low_pass(FS, 4.0);
+        assert_close(lp.dc_gain(), 1.0, 1e-5);
+        assert_close(gain_at(|x| lp.process(x), 0.5), 1.0, 0.02);
+        assert_close(gain_at(|x| lp.process(x), 4.0), FRAC_1_SQRT_2, 0.02);
+        assert!(gain_at(|x| lp.process(x), 10.0) < 0.1);
+    }
+
+    #[test]
+    fn test_high_pass_response() {
+        let mut hp = Biquad::high_pass(FS, 0.5);
+        assert_close(hp.dc_gain(), 0.0, 1e-5);
+        assert_close(gain_at(|x| hp.process(x), 4.0), 1.0, 0.02);
+        assert_close(gain_at(|x| hp.process(x), 0.5), FRAC_1_SQRT_2, 0.02);
+        assert!(gain_at(|x| hp.process(x), 0.05) < 0.02);
+    }
+
+    #[test]
+    fn test_band_pass_response() {
+        let mut bp = BandPass::new(FS, 0.5, 4.0);
+
+        // typical heart rates
+        for f in [1.0, 1.5, 2.0, 3.0].iter() {
+            assert!(gain_at(|x| bp.process(x), *f) > 0.8, "{}Hz", f);
+        }
+
+        // respiration / baseline wander
+        assert!(gain_at(|x| bp.process(x), 0.05) < 0.02);
+
+        // high frequency noise
+        assert!(gain_at(|x| bp.process(x), 10.0) < 0.1);
+    }
+
+    #[test]
+    fn test_reset_to_dc() {
+        let mut bp = BandPass::new(FS, 0.5, 4.0);
+        bp.reset_to(10000.0);
+        for _ in 0..100 {
+            assert_close(bp.process(10000.0), 0.0, 0.1);
+        }
+
+        let mut lp = Biquad::low_pass(FS, 4.0);
+        lp.reset_to(3.0);
+        assert_close(lp.process(3.0), 3.0, 1e-4);
+
+        lp.reset();
+        assert_close(lp.process(0.0), 0.0, 1e-6);
+    }
+}
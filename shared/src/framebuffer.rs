@@ -0,0 +1,101 @@
+//! In-memory display, host side only (`std` feature)
+//!
+//! Screens draw into it the same way they do on the LCD, for tests
+//! and screenshots.
+
+use core::convert::Infallible;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::*, primitives::Rectangle};
+use std::io;
+
+pub struct Framebuffer {
+    size: Size,
+    pixels: Vec<Rgb565>,
+}
+
+impl Framebuffer {
+    pub fn new(size: Size, color: Rgb565) -> Self {
+        Framebuffer {
+            size,
+            pixels: vec![color; (size.width * size.height) as usize],
+        }
+    }
+
+    /// `None` outside of the buffer
+    pub fn pixel(&self, p: Point) -> Option<Rgb565> {
+        self.index(p).map(|i| self.pixels[i])
+    }
+
+    /// Number of pixels of `color` in `area`
+    pub fn count(&self, area: &Rectangle, color: Rgb565) -> usize {
+        area.points()
+            .filter(|p| self.pixel(*p) == Some(color))
+            .count()
+    }
+
+    /// Binary PPM, 8 bits per channel
+    pub fn write_ppm<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.size.width, self.size.height)?;
+        for c in self.pixels.iter() {
+            // top bits repeated in the low ones, so white stays white
+            let r = c.r() << 3 | c.r() >> 2;
+            let g = c.g() << 2 | c.g() >> 4;
+            let b = c.b() << 3 | c.b() >> 2;
+            w.write_all(&[r, g, b])?;
+        }
+        Ok(())
+    }
+
+    fn index(&self, p: Point) -> Option<usize> {
+        let (w, h) = (self.size.width as i32, self.size.height as i32);
+        ((0..w).contains(&p.x) && (0..h).contains(&p.y)).then(|| (p.y * w + p.x) as usize)
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    /// Off-screen pixels are clipped, like on the LCD
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(p, color) in pixels {
+            if let Some(i) = self.index(p) {
+                self.pixels[i] = color;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::primitives::PrimitiveStyle;
+
+    #[test]
+    fn test_draw() {
+        let mut fb = Framebuffer::new(Size::new(4, 3), Rgb565::BLACK);
+        Rectangle::new(Point::new(2, 1), Size::new(4, 4))
+            .into_styled(PrimitiveStyle::with_fill(Rgb565::RED))
+            .draw(&mut fb)
+            .unwrap();
+        assert_eq!(fb.pixel(Point::new(1, 1)), Some(Rgb565::BLACK));
+        assert_eq!(fb.pixel(Point::new(3, 2)), Some(Rgb565::RED));
+        assert_eq!(fb.pixel(Point::new(4, 2)), None);
+        assert_eq!(fb.count(&fb.bounding_box(), Rgb565::RED), 4);
+
+        let mut ppm = Vec::new();
+        fb.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n4 3\n255\n"));
+        assert_eq!(ppm.len(), 11 + 4 * 3 * 3);
+        assert_eq!(&ppm[ppm.len() - 3..], &[255, 0, 0]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Heart rate variability, time domain metrics
+//!
+//! <https://www.ncbi.nlm.nih.gov/pmc/articles/PMC5624990/>
+//!
+//! NN intervals are measured in whole samples, at low sample rates
+//! (25Hz = 40ms resolution) short term metrics like pNN50 are rough.
+
+use crate::{circ::Circ, signal::Heartbeat};
+
+/// Shortest NN interval accepted, 260bpm
+pub const MIN_NN_MS: f32 = 60_000.0 / 260.0;
+
+/// Longest NN interval accepted, 40bpm
+pub const MAX_NN_MS: f32 = 60_000.0 / 40.0;
+
+/// Time domain HRV metrics, all in milliseconds except for pNN50
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HrvStats {
+    /// Number of NN intervals the stats were computed from
+    pub num_intervals: usize,
+
+    /// Mean NN interval
+    pub mean_nn: f32,
+
+    /// Standard deviation of NN intervals
+    pub sdnn: f32,
+
+    /// Root mean square of successive NN interval differences
+    pub rmssd: f32,
+
+    /// Percentage of successive NN intervals that differ by more than 50ms
+    pub pnn50: f32,
+}
+
+/// Accumulates the last `N` beat-to-beat intervals
+pub struct Hrv<const N: usize> {
+    sample_rate_hz: f32,
+
+    /// NN intervals, ms
+    intervals: Circ<f32, N>,
+
+    /// Per interval, set when it doesn't follow on from the one before,
+    /// beats in between were dropped. No successive difference across it.
+    breaks: Circ<bool, N>,
+
+    /// The next interval starts after a break
+    pending_break: bool,
+
+    last_beat_idx: Option<usize>,
+}
+
+impl<const N: usize> Hrv<N> {
+    pub fn new(sample_rate_hz: f32) -> Self {
+        Hrv {
+            sample_rate_hz,
+            intervals: Circ::new(0.0),
+            breaks: Circ::new(false),
+            pending_break: false,
+            last_beat_idx: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.sample_rate_hz);
+    }
+
+    /// Number of intervals available for stats
+    pub fn len(&self) -> usize {
+        self.intervals.total_added().min(N)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Heartbeats are expected in order, with absolute sample indices.
+    /// Intervals outside of the physiological range (missed or
+    /// spurious beats) are dropped.
+    pub fn add_heartbeat(&mut self, hb: &Heartbeat) {
+        if let Some(last_idx) = self.last_beat_idx.replace(hb.low_idx) {
+            let nn_samples = hb.low_idx.wrapping_sub(last_idx);
+            self.add_interval(nn_samples as f32 * 1000.0 / self.sample_rate_hz);
+        }
+    }
+
+    /// Adds an NN interval in ms. One out of range is dropped and
+    /// breaks the series.
+    pub fn add_interval(&mut self, nn_ms: f32) {
+        if (MIN_NN_MS..=MAX_NN_MS).contains(&nn_ms) {
+            self.intervals.add(nn_ms);
+            self.breaks.add(self.pending_break);
+            self.pending_break = false;
+        } else {
+            self.pending_break = true;
+        }
+    }
+
+    /// Intervals were lost (gap in the samples, beats dropped for poor
+    /// quality), the next one isn't a successor of the last one
+    pub fn add_break(&mut self) {
+        self.pending_break = true;
+        self.last_beat_idx = None;
+    }
+
+    /// Accumulated NN intervals in ms, oldest first
+    pub fn intervals(&self) -> impl Iterator<Item = f32> + '_ {
+        self.intervals.iter().skip(N - self.len())
+    }
+
+    /// Stats over all accumulated intervals
+    pub fn stats(&self) -> Option<HrvStats> {
+        self.stats_over(N)
+    }
+
+    /// Stats over (at most) the last `num_intervals` intervals, needs at
+    /// least 2 of them with one successive difference. RMSSD and pNN50
+    /// skip differences across breaks.
+    pub fn stats_over(&self, num_intervals: usize) -> Option<HrvStats> {
+        let n = num_intervals.min(self.len());
+        if n < 2 {
+            return None;
+        }
+
+        let nns = || self.intervals.iter().skip(N - n);
+
+        let mean_nn = nns().sum::<f32>() / n as f32;
+
+        let var = nns().map(|nn| (nn - mean_nn) * (nn - mean_nn)).sum::<f32>() / (n - 1) as f32;
+
+        let mut sum_sq_diff = 0.0;
+        let mut nn50 = 0;
+        let mut num_diffs = 0;
+        let breaks = self.breaks.iter().skip(N - n + 1);
+        for ((nn0, nn1), after_break) in nns().zip(nns().skip(1)).zip(breaks) {
+            if after_break {
+                continue;
+            }
+            num_diffs += 1;
+            let d = nn1 - nn0;
+            sum_sq_diff += d * d;
+            if d.abs() > 50.0 {
+                nn50 += 1;
+            }
+        }
+        if num_diffs == 0 {
+            return None;
+        }
+
+        Some(HrvStats {
+            num_intervals: n,
+            mean_nn,
+            sdnn: libm::sqrtf(var),
+            rmssd: libm::sqrtf(sum_sq_diff / num_diffs as f32),
+            pnn50: 100.0 * nn50 as f32 / num_diffs as f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hb(idx: usize) -> Heartbeat {
+        Heartbeat {
+            low_idx: idx,
+            ..Heartbeat::zero()
+        }
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_not_enough_data() {
+        let mut hrv = Hrv::<8>::new(25.0);
+        assert_eq!(hrv.stats(), None);
+
+        hrv.add_heartbeat(&hb(0));
+        hrv.add_heartbeat(&hb(25));
+        assert_eq!(hrv.len(), 1);
+        assert_eq!(hrv.stats(), None);
+
+        hrv.add_heartbeat(&hb(50));
+        assert_eq!(hrv.len(), 2);
+        assert!(hrv.stats().is_some());
+    }
+
+    #[test]
+    fn test_constant_rate() {
+        let mut hrv = Hrv::<8>::new(25.0);
+        for i in 0..10 {
+            hrv.add_heartbeat(&hb(100 + i * 20));
+        }
+
+        let stats = hrv.stats().unwrap();
+        assert_eq!(stats.num_intervals, 8);
+        assert_close(stats.mean_nn, 800.0);
+        assert_close(stats.sdnn, 0.0);
+        assert_close(stats.rmssd, 0.0);
+        assert_close(stats.pnn50, 0.0);
+    }
+
+    #[test]
+    fn test_known_intervals() {
+        let mut hrv = Hrv::<16>::new(25.0);
+        for nn in [800.0, 900.0, 800.0, 840.0].iter() {
+            hrv.add_interval(*nn);
+        }
+
+        let stats = hrv.stats().unwrap();
+        assert_eq!(stats.num_intervals, 4);
+        assert_close(stats.mean_nn, 835.0);
+        // deviations: -35, 65, -35, 5
+        assert_close(
+            stats.sdnn,
+            libm::sqrtf((35.0 * 35.0 * 2.0 + 65.0 * 65.0 + 25.0) / 3.0),
+        );
+        // successive differences: 100, -100, 40
+        assert_close(
+            stats.rmssd,
+            libm::sqrtf((100.0 * 100.0 * 2.0 + 40.0 * 40.0) / 3.0),
+        );
+        assert_close(stats.pnn50, 100.0 * 2.0 / 3.0);
+
+        // shorter window, last 2 intervals only
+        let stats = hrv.stats_over(2).unwrap();
+        assert_eq!(stats.num_intervals, 2);
+        assert_close(stats.mean_nn, 820.0);
+        assert_close(stats.rmssd, 40.0);
+        assert_close(stats.pnn50, 0.0);
+    }
+
+    #[test]
+    fn test_out_of_range_intervals() {
+        let mut hrv = Hrv::<16>::new(25.0);
+        hrv.add_heartbeat(&hb(0));
+        hrv.add_heartbeat(&hb(2)); // 80ms, spurious
+        hrv.add_heartbeat(&hb(22));
+        hrv.add_heartbeat(&hb(100)); // 3.1s, missed beats
+        hrv.add_heartbeat(&hb(120));
+        assert_eq!(hrv.len(), 2);
+        assert_eq!(hrv.intervals().collect::<Vec<_>>(), vec![800.0, 800.0]);
+        // the two aren't successive, no difference between them
+        assert_eq!(hrv.stats(), None);
+
+        hrv.add_heartbeat(&hb(145));
+        let stats = hrv.stats().unwrap();
+        assert_close(stats.mean_nn, 2_600.0 / 3.0);
+        assert_close(stats.rmssd, 200.0);
+        assert_close(stats.pnn50, 100.0);
+
+        hrv.reset();
+        assert!(hrv.is_empty());
+    }
+
+    #[test]
+    fn test_breaks() {
+        let mut hrv = Hrv::<16>::new(25.0);
+        for nn in [800.0, 900.0].iter() {
+            hrv.add_interval(*nn);
+        }
+        hrv.add_break();
+        for nn in [600.0, 640.0].iter() {
+            hrv.add_interval(*nn);
+        }
+
+        // 900 -> 600 crosses the break, differences: 100, 40
+        let stats = hrv.stats().unwrap();
+        assert_eq!(stats.num_intervals, 4);
+        assert_close(stats.mean_nn, 735.0);
+        assert_close(
+            stats.rmssd,
+            libm::sqrtf((100.0 * 100.0 + 40.0 * 40.0) / 2.0),
+        );
+        assert_close(stats.pnn50, 50.0);
+
+        // the break is just before the window
+        assert_eq!(hrv.stats_over(2).unwrap().rmssd, 40.0);
+        hrv.add_break();
+        hrv.add_interval(700.0);
+        assert_eq!(hrv.stats_over(2), None);
+
+        // a beat after a break starts a new interval
+        hrv.add_heartbeat(&hb(0));
+        hrv.add_break();
+        hrv.add_heartbeat(&hb(20));
+        hrv.add_heartbeat(&hb(40));
+        assert_eq!(hrv.len(), 6);
+        assert_eq!(hrv.stats_over(2), None);
+    }
+}
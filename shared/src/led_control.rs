@@ -0,0 +1,301 @@
+//! Automatic LED current control
+//!
+//! How much light comes back depends on the finger, skin and how hard
+//! it's pressed. With too little the pulse is lost in ADC noise, with too
+//! much the ADC clips. Red and IR currents are adjusted separately, each
+//! to keep its channel's DC level within a band of the ADC range.
+//!
+//! A current step makes the raw signal jump, the model has to be told,
+//! see [`crate::model::UIModel::on_led_step`].
+
+/// Full scale of the 18-bit sensor ADC
+pub const ADC_FULL_SCALE: f32 = 262_143.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LedControlConfig {
+    /// DC level band, fraction of full scale, currents are left
+    /// alone as long as the level stays within it
+    pub low: f32,
+    pub high: f32,
+    /// Level a step aims for
+    pub target: f32,
+    /// DC level is averaged over this many samples, about a second
+    /// so that the pulse itself averages out
+    pub average_samples: usize,
+    /// Samples ignored after a step, until the new current shows in the
+    /// data (samples already in the sensor FIFO, sensor averaging)
+    pub settle_samples: usize,
+    pub min_amplitude: u8,
+    pub max_amplitude: u8,
+}
+
+impl Default for LedControlConfig {
+    fn default() -> Self {
+        LedControlConfig {
+            low: 0.3,
+            high: 0.8,
+            target: 0.55,
+            average_samples: 25,
+            settle_samples: 4,
+            // 0.8mA, just enough for a pulse on a thin finger
+            min_amplitude: 4,
+            max_amplitude: 255,
+        }
+    }
+}
+
+/// LED currents, 0.2mA per step
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LedAmplitudes {
+    pub red: u8,
+    pub ir: u8,
+}
+
+impl LedAmplitudes {
+    pub fn both(amplitude: u8) -> Self {
+        LedAmplitudes {
+            red: amplitude,
+            ir: amplitude,
+        }
+    }
+}
+
+pub struct LedController {
+    config: LedControlConfig,
+    amplitudes: LedAmplitudes,
+
+    red_sum: f32,
+    ir_sum: f32,
+    count: usize,
+    /// samples left to ignore
+    settle: usize,
+}
+
+impl LedController {
+    /// `amplitude`: what both LEDs are currently set to
+    pub fn new(config: LedControlConfig, amplitude: u8) -> Self {
+        LedController {
+            config,
+            amplitudes: LedAmplitudes::both(amplitude),
+            red_sum: 0.0,
+            ir_sum: 0.0,
+            count: 0,
+            settle: config.settle_samples,
+        }
+    }
+
+    pub fn amplitudes(&self) -> LedAmplitudes {
+        self.amplitudes
+    }
+
+    /// LEDs were set to `amplitude` from outside, start over
+    pub fn reset(&mut self, amplitude: u8) {
+        *self = Self::new(self.config, amplitude);
+    }
+
+    /// Feeds one raw sample. Returns new currents, to be written to the
+    /// sensor, when a channel's DC level left its band.
+    pub fn update(&mut self, red: f32, ir: f32) -> Option<LedAmplitudes> {
+        if self.settle > 0 {
+            self.settle -= 1;
+            return None;
+        }
+
+        self.red_sum += red;
+        self.ir_sum += ir;
+        self.count += 1;
+        if self.count < self.config.average_samples {
+            return None;
+        }
+
+        let n = self.count as f32;
+        let new = LedAmplitudes {
+            red: self.step(self.amplitudes.red, self.red_sum / n),
+            ir: self.step(self.amplitudes.ir, self.ir_sum / n),
+        };
+        self.red_sum = 0.0;
+        self.ir_sum = 0.0;
+        self.count = 0;
+
+        if new == self.amplitudes {
+            return None;
+        }
+        self.amplitudes = new;
+        self.settle = self.config.settle_samples;
+        Some(new)
+    }
+
+    /// Level is roughly proportional to the current, until it clips
+    fn step(&self, amplitude: u8, dc: f32) -> u8 {
+        let level = dc / ADC_FULL_SCALE;
+        if (self.config.low..=self.config.high).contains(&level) {
+            return amplitude;
+        }
+
+        let (min, max) = (self.config.min_amplitude, self.config.max_amplitude);
+        if level <= 0.0 {
+            return max;
+        }
+        let new = libm::roundf(amplitude.max(1) as f32 * self.config.target / level);
+        new.clamp(min as f32, max as f32) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{PpgConfig, PpgSynth};
+
+    /// Sensor whose light levels scale with the LED currents. `synth` DC
+    /// levels are at amplitude `REF_AMPLITUDE`, new currents show up
+    /// `LATENCY` samples after they are set.
+    struct SimSensor {
+        synth: PpgSynth,
+        amplitudes: LedAmplitudes,
+        pending: Option<(usize, LedAmplitudes)>,
+        idx: usize,
+    }
+
+    const REF_AMPLITUDE: f32 = 100.0;
+    const LATENCY: usize = 2;
+
+    impl SimSensor {
+        fn new(cfg: PpgConfig, amplitude: u8) -> Self {
+            SimSensor {
+                synth: PpgSynth::new(cfg),
+                amplitudes: LedAmplitudes::both(amplitude),
+                pending: None,
+                idx: 0,
+            }
+        }
+
+        fn set(&mut self, amplitudes: LedAmplitudes) {
+            self.pending = Some((self.idx + LATENCY, amplitudes));
+        }
+
+        fn sample(&mut self) -> (f32, f32) {
+            if let Some((at, a)) = self.pending {
+                if self.idx >= at {
+                    self.amplitudes = a;
+                    self.pending = None;
+                }
+            }
+            self.idx += 1;
+            let s = self.synth.next().unwrap().sample;
+            let scale = |x: u32, amplitude: u8| {
+                (x as f32 * amplitude as f32 / REF_AMPLITUDE).min(ADC_FULL_SCALE)
+            };
+            (
+                scale(s.red, self.amplitudes.red),
+                scale(s.ir, self.amplitudes.ir),
+            )
+        }
+    }
+
+    /// Sample indices of the steps
+    fn run(ctrl: &mut LedController, sensor: &mut SimSensor, n: usize) -> Vec<usize> {
+        let mut steps = Vec::new();
+        for _ in 0..n {
+            let (red, ir) = sensor.sample();
+            if let Some(a) = ctrl.update(red, ir) {
+                sensor.set(a);
+                steps.push(sensor.idx);
+            }
+        }
+        steps
+    }
+
+    fn level(sensor: &mut SimSensor) -> (f32, f32) {
+        let n = 50;
+        let (mut red, mut ir) = (0.0, 0.0);
+        for _ in 0..n {
+            let (r, i) = sensor.sample();
+            red += r / ADC_FULL_SCALE / n as f32;
+            ir += i / ADC_FULL_SCALE / n as f32;
+        }
+        (red, ir)
+    }
+
+    fn finger(ir_dc: f32, r_dc: f32) -> PpgConfig {
+        PpgConfig {
+            ir_dc,
+            r_dc,
+            perfusion: 0.02,
+            noise: 20.0,
+            ..PpgConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_clipping_channel_steps_down() {
+        // IR clips at 200, red is within the band
+        let mut sensor = SimSensor::new(finger(200_000.0, 60_000.0), 200);
+        let mut ctrl = LedController::new(LedControlConfig::default(), 200);
+
+        let steps = run(&mut ctrl, &mut sensor, 25 * 20);
+        assert!(!steps.is_empty() && steps.len() <= 3, "{:?}", steps);
+        // settled for good
+        assert!(*steps.last().unwrap() < 25 * 10, "{:?}", steps);
+
+        assert_eq!(ctrl.amplitudes().red, 200);
+        assert!(ctrl.amplitudes().ir < 100);
+        let (red, ir) = level(&mut sensor);
+        assert!((0.3..=0.8).contains(&red), "{}", red);
+        assert!((0.3..=0.8).contains(&ir), "{}", ir);
+    }
+
+    #[test]
+    fn test_weak_channel_steps_up() {
+        let mut sensor = SimSensor::new(finger(120_000.0, 10_000.0), 100);
+        let mut ctrl = LedController::new(LedControlConfig::default(), 100);
+
+        let steps = run(&mut ctrl, &mut sensor, 25 * 20);
+        assert_eq!(steps.len(), 1, "{:?}", steps);
+        assert_eq!(ctrl.amplitudes(), LedAmplitudes { red: 255, ir: 100 });
+
+        // red still low at full current, that's as good as it gets
+        let (red, ir) = level(&mut sensor);
+        assert!(red < 0.3, "{}", red);
+        assert!((0.3..=0.8).contains(&ir), "{}", ir);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        // DC drifting around within the band doesn't cause steps
+        let cfg = PpgConfig {
+            wander: 0.3,
+            wander_hz: 0.2,
+            ..finger(140_000.0, 140_000.0)
+        };
+        let mut sensor = SimSensor::new(cfg, 100);
+        let mut ctrl = LedController::new(LedControlConfig::default(), 100);
+        assert_eq!(run(&mut ctrl, &mut sensor, 25 * 30), vec![]);
+    }
+
+    #[test]
+    fn test_settle() {
+        let config = LedControlConfig::default();
+        let mut ctrl = LedController::new(config, 100);
+        let wait = config.settle_samples + config.average_samples;
+
+        // nothing at all, straight to full current
+        for _ in 0..(wait - 1) {
+            assert_eq!(ctrl.update(0.0, 0.0), None);
+        }
+        assert_eq!(ctrl.update(0.0, 0.0), Some(LedAmplitudes::both(255)));
+
+        // next step only after the settle time and a full average
+        for _ in 0..(wait - 1) {
+            assert_eq!(ctrl.update(ADC_FULL_SCALE, ADC_FULL_SCALE), None);
+        }
+        let a = ctrl.update(ADC_FULL_SCALE, ADC_FULL_SCALE).unwrap();
+        assert_eq!(a, LedAmplitudes::both(140));
+
+        // clipping all the way down, stops at the minimum
+        ctrl.reset(5);
+        for _ in 0..wait {
+            ctrl.update(ADC_FULL_SCALE, ADC_FULL_SCALE);
+        }
+        assert_eq!(ctrl.amplitudes(), LedAmplitudes::both(4));
+    }
+}
@@ -1,6 +1,30 @@
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![deny(unsafe_code)]
 
+pub mod alarms;
+pub mod calibration;
 pub mod circ;
-pub mod linreg;
+pub mod console;
+pub mod crc;
+pub mod fifo;
+pub mod filter;
+#[cfg(any(test, feature = "std"))]
+pub mod framebuffer;
+pub mod hrv;
+pub mod led_control;
+pub mod link;
+pub mod model;
+pub mod ppg;
+pub mod presence;
+pub mod protocol;
+pub mod quality;
+pub mod screens;
+pub mod sensor;
+pub mod settings;
 pub mod signal;
+#[cfg(any(test, feature = "std"))]
+pub mod synth;
+pub mod timing;
+pub mod tones;
+pub mod touch;
+pub mod trends;
@@ -0,0 +1,205 @@
+//! Serial link to a host, independent of the actual transport
+//!
+//! Messages are encoded into a byte queue as they are produced and
+//! written out whenever the transport (USB serial in the firmware, an
+//! in-memory pipe in tests) has room for them.
+//!
+//! Console text shares the stream, each reply is terminated like a frame
+//! so a frame decoder on the other end drops it and stays in sync.
+
+use crate::protocol::{Message, FRAME_END, MAX_FRAME_LEN};
+use heapless::Deque;
+
+/// Non-blocking byte stream
+pub trait Transport {
+    type Error;
+
+    /// Writes some of `data`, 0 when there is no room right now
+    fn write(&mut self, data: &[u8]) -> Result<usize, Self::Error>;
+
+    /// Reads whatever is available, 0 when there is nothing
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Encoded frames waiting for the transport
+pub struct TxQueue<const N: usize> {
+    bytes: Deque<u8, N>,
+    dropped: usize,
+}
+
+impl<const N: usize> TxQueue<N> {
+    pub fn new() -> Self {
+        TxQueue {
+            bytes: Deque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Messages and replies dropped so far, the queue was full (nobody reading)
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Queues a whole frame or nothing at all, so that the
+    /// stream never has a partial frame in it
+    pub fn push(&mut self, msg: &Message) -> bool {
+        let mut frame = [0; MAX_FRAME_LEN];
+        match msg.encode(&mut frame) {
+            Ok(n) => self.push_bytes(&[&frame[..n]]),
+            Err(_) => {
+                self.dropped = self.dropped.wrapping_add(1);
+                false
+            }
+        }
+    }
+
+    /// Queues a console reply, whole or nothing like frames
+    pub fn push_text(&mut self, text: &str) -> bool {
+        self.push_bytes(&[text.as_bytes(), &[FRAME_END]])
+    }
+
+    fn push_bytes(&mut self, parts: &[&[u8]]) -> bool {
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        if len > N - self.bytes.len() {
+            self.dropped = self.dropped.wrapping_add(1);
+            return false;
+        }
+        for b in parts.iter().flat_map(|p| p.iter()) {
+            let _ = self.bytes.push_back(*b);
+        }
+        true
+    }
+
+    /// Writes out as much as the transport takes, returns the number of bytes
+    pub fn flush<T: Transport>(&mut self, transport: &mut T) -> Result<usize, T::Error> {
+        let mut total = 0;
+        while !self.bytes.is_empty() {
+            let n = transport.write(self.bytes.as_slices().0)?;
+            if n == 0 {
+                break;
+            }
+            for _ in 0..n {
+                self.bytes.pop_front();
+            }
+            total += n;
+        }
+        Ok(total)
+    }
+}
+
+impl<const N: usize> Default for TxQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One end of an in-memory serial line, takes up to
+    /// `max_write` bytes per write, like a USB packet
+    struct Pipe {
+        sent: Vec<u8>,
+        max_write: usize,
+        room: usize,
+    }
+
+    impl Transport for Pipe {
+        type Error = ();
+
+        fn write(&mut self, data: &[u8]) -> Result<usize, ()> {
+            let n = data.len().min(self.max_write).min(self.room);
+            self.sent.extend_from_slice(&data[..n]);
+            self.room -= n;
+            Ok(n)
+        }
+
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize, ()> {
+            Ok(0)
+        }
+    }
+
+    fn decode_all(bytes: &[u8]) -> Vec<Message> {
+        bytes
+            .split(|b| *b == FRAME_END)
+            .filter(|f| !f.is_empty())
+            .map(|f| Message::decode(&mut f.to_vec()).unwrap())
+            .collect()
+    }
+
+    fn sample(seq: u32) -> Message {
+        Message::Sample {
+            seq,
+            time_ms: seq * 40,
+            red: 1000 + seq,
+            ir: 2000 + seq,
+        }
+    }
+
+    #[test]
+    fn test_through_pipe() {
+        let mut q = TxQueue::<256>::new();
+        let mut pipe = Pipe {
+            sent: Vec::new(),
+            max_write: 64,
+            room: usize::MAX,
+        };
+
+        let msgs: Vec<Message> = (1..=30).map(sample).collect();
+        for chunk in msgs.chunks(7) {
+            for m in chunk.iter() {
+                assert!(q.push(m));
+            }
+            q.flush(&mut pipe).unwrap();
+        }
+        assert!(q.is_empty());
+        assert_eq!(decode_all(&pipe.sent), msgs);
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let mut q = TxQueue::<100>::new();
+        let mut pipe = Pipe {
+            sent: Vec::new(),
+            max_write: 64,
+            room: 0,
+        };
+
+        // host not reading, whole frames are dropped once the queue is full
+        let msgs: Vec<Message> = (1..=10).map(sample).collect();
+        let queued = msgs.iter().filter(|m| q.push(m)).count();
+        assert!(queued > 0 && queued < 10);
+        assert_eq!(q.dropped(), 10 - queued);
+        assert_eq!(q.flush(&mut pipe), Ok(0));
+
+        pipe.room = usize::MAX;
+        q.flush(&mut pipe).unwrap();
+        assert_eq!(decode_all(&pipe.sent), msgs[..queued]);
+    }
+
+    #[test]
+    fn test_text_between_frames() {
+        let mut q = TxQueue::<256>::new();
+        let mut pipe = Pipe {
+            sent: Vec::new(),
+            max_write: 64,
+            room: usize::MAX,
+        };
+
+        assert!(q.push(&sample(1)));
+        assert!(q.push_text("ok\r\n"));
+        assert!(q.push(&sample(2)));
+        q.flush(&mut pipe).unwrap();
+
+        let frames: Vec<&[u8]> = pipe.sent.split(|b| *b == FRAME_END).collect();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[1], b"ok\r\n");
+        assert_eq!(decode_all(frames[0]), [sample(1)]);
+        assert_eq!(decode_all(frames[2]), [sample(2)]);
+    }
+}
@@ -0,0 +1,879 @@
+//! UI model: heart rate, SpO2 and signal quality from raw sensor samples
+//!
+//! Window size is a const generic, everything else that depends on the
+//! sensor setup is in [`ModelConfig`].
+
+use crate::{
+    calibration::{Spo2, Spo2Calibration},
+    circ::Circ,
+    fifo::FifoSample,
+    filter::BandPass,
+    hrv::{Hrv, HrvStats},
+    presence::{FingerDetector, FingerEvent, FingerState},
+    quality::{QualityLimits, SignalQuality, SignalQualityIndex},
+    signal::{Heartbeat, HeartbeatDetector},
+    timing::{seq_gap, SampleStamp},
+};
+use heapless::Vec;
+
+use heapless::binary_heap::{BinaryHeap, Max};
+
+/// Number of samples to use as an input into heart rate / SPO2 calculations
+pub const MAX30102_NUM_SAMPLES: usize = 160;
+/// Sensor configuration is in the firmware's board.rs
+pub const MAX30102_SAMPLE_RATE_HZ: u32 = 25;
+
+/// Number of beat-to-beat intervals in HRV stats, about a minute at rest
+pub const HRV_NUM_INTERVALS: usize = 64;
+
+/// Heartbeats kept per window, enough for 6.4s at 300bpm
+const MAX_HEARTBEATS: usize = 32;
+
+/// Model parameters, defaults match the firmware sensor setup
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ModelConfig {
+    pub sample_rate_hz: f32,
+
+    /// Band pass filter cutoffs, roughly 30 to 240 bpm,
+    /// keeps respiration/baseline wander and high frequency noise out.
+    pub filter_low_hz: f32,
+    pub filter_high_hz: f32,
+
+    /// Heartbeat has to exceed this fraction of the recent beat amplitude
+    pub heartbeat_threshold_ratio: f32,
+    /// Per-sample decay of the beat amplitude envelope
+    pub heartbeat_envelope_decay: f32,
+
+    /// Raw IR levels for finger placement/removal
+    pub finger_on_dc: f32,
+    pub finger_off_dc: f32,
+    pub finger_debounce_samples: usize,
+    /// Time after placement before anything is reported
+    pub finger_settle_samples: usize,
+
+    pub quality_limits: QualityLimits,
+
+    /// SpO2 temperature compensation, relative change of the R ratio
+    /// per °C of sensor die temperature above `spo2_reference_temp_c`.
+    /// LED wavelengths drift with temperature and the ratio with them.
+    /// Off (0) by default, the calibration curve comes without it.
+    pub spo2_ratio_temp_coeff: f32,
+    pub spo2_reference_temp_c: f32,
+
+    /// SpO2 from the (temperature compensated) R ratio
+    pub spo2_calibration: Spo2Calibration,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        ModelConfig {
+            sample_rate_hz: MAX30102_SAMPLE_RATE_HZ as f32,
+            filter_low_hz: 0.5,
+            filter_high_hz: 4.0,
+            heartbeat_threshold_ratio: 0.25,
+            // halves in ~4s
+            heartbeat_envelope_decay: 0.993,
+            // ambient light alone is much lower
+            finger_on_dc: 20_000.0,
+            finger_off_dc: 10_000.0,
+            finger_debounce_samples: 5,
+            // wait for the sample window to fill up with data from the new finger
+            finger_settle_samples: MAX30102_NUM_SAMPLES,
+            quality_limits: QualityLimits::default(),
+            spo2_ratio_temp_coeff: 0.0,
+            spo2_reference_temp_c: 25.0,
+            spo2_calibration: Spo2Calibration::default(),
+        }
+    }
+}
+
+/// Median of beat-to-beat intervals, extremes from missed or spurious
+/// beats are ignored. `beat_times_ms` are in order.
+pub fn median_interval_ms(beat_times_ms: impl Iterator<Item = u32>) -> Option<u32> {
+    let mut hb_dist: BinaryHeap<u32, Max, MAX_HEARTBEATS> = BinaryHeap::new();
+    let mut last_hb_ms: Option<u32> = None;
+    for hb_ms in beat_times_ms {
+        if let Some(lhb) = last_hb_ms {
+            let _ = hb_dist.push(hb_ms.wrapping_sub(lhb));
+        }
+        last_hb_ms = Some(hb_ms);
+    }
+
+    // pick a value in the middle
+    for _ in 0..(hb_dist.len() / 2) {
+        hb_dist.pop();
+    }
+    hb_dist.pop().filter(|d| *d > 0)
+}
+
+/// Mean of the beat-to-beat intervals within 20% of the median, finer
+/// than the sample period the beat times are quantized to.
+/// `beat_times_ms` are in order.
+pub fn mean_interval_ms(beat_times_ms: impl Iterator<Item = u32> + Clone) -> Option<f32> {
+    let median = median_interval_ms(beat_times_ms.clone())? as f32;
+    let (sum, count) = beat_times_ms
+        .clone()
+        .zip(beat_times_ms.skip(1))
+        .map(|(t0, t1)| t1.wrapping_sub(t0) as f32)
+        .filter(|d| (d - median).abs() <= 0.2 * median)
+        .fold((0.0, 0), |(sum, count), d| (sum + d, count + 1));
+    Some(sum / count as f32)
+}
+
+/// Single sample read from a sensor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Max3012Sample {
+    pub r: f32,
+    pub ir: f32,
+
+    /// Sensor sample counter, gaps mean lost samples, 0 means no sample
+    pub seq: u32,
+
+    /// Monotonic time the sample was taken, ms
+    pub time_ms: u32,
+}
+
+impl Max3012Sample {
+    pub fn new(s: FifoSample, stamp: SampleStamp) -> Self {
+        Max3012Sample {
+            r: s.red as f32,
+            ir: s.ir as f32,
+            seq: stamp.seq,
+            time_ms: stamp.time_ms,
+        }
+    }
+
+    pub fn zero() -> Self {
+        Max3012Sample::new(FifoSample::zero(), SampleStamp::zero())
+    }
+
+    /// A `zero` left by clearing a buffer, not a sensor sample
+    pub fn is_placeholder(&self) -> bool {
+        self.seq == 0
+    }
+}
+
+/// A chunk of sample data, represents one of the
+/// channels (red or infrared)
+pub struct Max3012SampleData<const N: usize> {
+    /// "AC" component of R/IR signal sample
+    /// (band pass filtered sensor value)
+    pub ac: [f32; N],
+
+    /// "DC" mean of the sample
+    dc_mean: f32,
+
+    /// for scale, to display raw data
+    pub ac_max: f32,
+    pub ac_min: f32,
+
+    config: ModelConfig,
+
+    filter: BandPass,
+
+    /// filtered samples, fed one at a time
+    filtered: Circ<f32, N>,
+
+    /// time of each filtered sample, ms
+    times: Circ<u32, N>,
+
+    /// time of the last beat that made it into `hrv`
+    last_beat_ms: Option<u32>,
+
+    detector: HeartbeatDetector,
+
+    /// Heartbeats within the current window, absolute sample indices
+    pub heartbeats: Vec<Heartbeat, MAX_HEARTBEATS>,
+
+    pub heart_rate_bpm: Option<f32>,
+
+    /// Beat-to-beat intervals, survives the sample window
+    pub hrv: Hrv<HRV_NUM_INTERVALS>,
+
+    pub quality: SignalQualityIndex,
+
+    // Part of SPO2 formula, AC/DC
+    ac_over_dc: f32,
+}
+
+impl<const N: usize> Max3012SampleData<N> {
+    pub fn new(config: ModelConfig) -> Self {
+        Max3012SampleData {
+            ac: [0.0; N],
+            dc_mean: 0.0,
+
+            ac_max: 1.0,
+            ac_min: 0.0,
+
+            config,
+
+            filter: BandPass::new(
+                config.sample_rate_hz,
+                config.filter_low_hz,
+                config.filter_high_hz,
+            ),
+
+            filtered: Circ::new(0.0),
+            times: Circ::new(0),
+            last_beat_ms: None,
+
+            detector: HeartbeatDetector::new(
+                config.heartbeat_threshold_ratio,
+                config.heartbeat_envelope_decay,
+            ),
+
+            heartbeats: Vec::new(),
+
+            heart_rate_bpm: None,
+
+            hrv: Hrv::new(config.sample_rate_hz),
+
+            quality: SignalQualityIndex::none(),
+
+            ac_over_dc: 1.0,
+        }
+    }
+
+    /// Position of an absolute sample index in the `ac` window,
+    /// `idx` has to be within the window
+    pub fn window_idx(&self, idx: usize) -> usize {
+        idx + N - self.detector.sample_count()
+    }
+
+    /// Time of an absolute sample index within the window, ms
+    pub fn sample_time_ms(&self, idx: usize) -> u32 {
+        self.times.get(self.window_idx(idx))
+    }
+
+    /// Samples were lost, the next beat interval can't be trusted
+    pub fn on_gap(&mut self) {
+        self.break_beats();
+    }
+
+    /// The next beat interval doesn't follow on from the last one
+    fn break_beats(&mut self) {
+        self.last_beat_ms = None;
+        self.hrv.add_break();
+    }
+
+    /// Clears all estimates, as if no samples were ever seen
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    /// Feeds a single new raw sample, taken at `time_ms`,
+    /// through the filter and heartbeat detector
+    pub fn add_sample(&mut self, x: f32, time_ms: u32) {
+        if self.detector.sample_count() == 0 {
+            self.filter.reset_to(x);
+        }
+
+        let ac = self.filter.process(x);
+        self.filtered.add(ac);
+        self.times.add(time_ms);
+
+        if let Some(hb) = self.detector.update(ac) {
+            // a high held through a long run of rejected wiggles
+            // can be older than the window, there's no time for it
+            if hb.high_idx < self.detector.sample_count().saturating_sub(N) {
+                self.break_beats();
+                return;
+            }
+
+            // don't let noise into beat-to-beat intervals
+            if self.quality.quality >= SignalQuality::Acceptable {
+                // timed at the bottom of the drop, which is sharp,
+                // the high can be anywhere on a slow decline before it
+                let beat_ms = self.sample_time_ms(hb.low_idx);
+                if let Some(last) = self.last_beat_ms {
+                    self.hrv.add_interval(beat_ms.wrapping_sub(last) as f32);
+                }
+                self.last_beat_ms = Some(beat_ms);
+            } else {
+                self.break_beats();
+            }
+            if self.heartbeats.is_full() {
+                self.heartbeats.remove(0);
+            }
+            let _ = self.heartbeats.push(hb);
+        }
+    }
+
+    /// Updates window stats, `data` is the latest window of raw samples,
+    /// the new ones are expected to be passed to `add_sample` first.
+    pub fn update_window(&mut self, data: &[f32; N]) {
+        self.dc_mean = data.iter().sum::<f32>() / N as f32;
+
+        // forget heartbeats that slid out of the window
+        let window_start = self.detector.sample_count().saturating_sub(N);
+        self.heartbeats.retain(|hb| hb.high_idx >= window_start);
+
+        self.ac_max = f32::MIN;
+        self.ac_min = f32::MAX;
+        for (i, ac) in self.filtered.iter().enumerate() {
+            self.ac[i] = ac;
+            self.ac_max = self.ac_max.max(ac);
+            self.ac_min = self.ac_min.min(ac);
+        }
+
+        let mut hb_window_idx: Vec<usize, MAX_HEARTBEATS> = Vec::new();
+        for hb in self.heartbeats.iter() {
+            let _ = hb_window_idx.push(self.window_idx(hb.low_idx));
+        }
+        self.quality =
+            SignalQualityIndex::assess(data, &self.ac, &hb_window_idx, &self.config.quality_limits);
+
+        self.ac_over_dc = 0.0;
+        for hb in self.heartbeats.iter() {
+            self.ac_over_dc += hb.high_value - hb.low_value;
+        }
+        self.ac_over_dc = self.ac_over_dc / self.heartbeats.len() as f32 / self.dc_mean;
+
+        self.heart_rate_bpm = mean_interval_ms(
+            self.heartbeats
+                .iter()
+                .map(|hb| self.sample_time_ms(hb.low_idx)),
+        )
+        .map(|hbd| 60_000.0 / hbd);
+    }
+}
+
+pub struct UIModel<const N: usize> {
+    pub r: Max3012SampleData<N>,
+    pub ir: Max3012SampleData<N>,
+    finger: FingerDetector,
+    config: ModelConfig,
+
+    /// Sensor die temperature, °C
+    temperature_c: Option<f32>,
+
+    /// Samples lost so far, from gaps in sample sequence numbers
+    pub sensor_overflow: u32,
+
+    last_seq: Option<u32>,
+}
+
+impl<const N: usize> UIModel<N> {
+    pub fn new() -> Self {
+        Self::with_config(ModelConfig::default())
+    }
+
+    pub fn with_config(config: ModelConfig) -> Self {
+        UIModel {
+            r: Max3012SampleData::new(config),
+            ir: Max3012SampleData::new(config),
+            finger: FingerDetector::new(
+                config.finger_on_dc,
+                config.finger_off_dc,
+                config.finger_debounce_samples,
+                config.finger_settle_samples,
+            ),
+            config,
+            temperature_c: None,
+            sensor_overflow: 0,
+            last_seq: None,
+        }
+    }
+
+    /// Only the last `num_new` samples in the window haven't been seen
+    /// before. Returns the latest finger state change, on removal
+    /// all estimates are reset and sample buffers should be cleared.
+    pub fn update_from_samples(
+        &mut self,
+        samples: &[Max3012Sample; N],
+        num_new: usize,
+    ) -> Option<FingerEvent> {
+        let mut event = None;
+
+        let new_start = N - num_new.min(N);
+
+        for s in samples[new_start..].iter() {
+            // buffer was cleared since the last look, whatever was wiped
+            // wasn't lost by the sensor, the sequence starts over
+            if s.is_placeholder() {
+                self.last_seq = None;
+                continue;
+            }
+            if let Some(last) = self.last_seq {
+                let gap = seq_gap(last, s.seq);
+                if gap > 0 {
+                    self.sensor_overflow = self.sensor_overflow.saturating_add(gap);
+                    self.r.on_gap();
+                    self.ir.on_gap();
+                }
+            }
+            self.last_seq = Some(s.seq);
+
+            if let Some(e) = self.finger.update(s.ir) {
+                if e == FingerEvent::Removed {
+                    self.r.reset();
+                    self.ir.reset();
+                }
+                event = Some(e);
+            }
+
+            // don't compute anything on ambient noise
+            if self.finger.state() != FingerState::Absent {
+                self.r.add_sample(s.r, s.time_ms);
+                self.ir.add_sample(s.ir, s.time_ms);
+            }
+        }
+
+        if self.finger.state() != FingerState::Absent {
+            let mut oxi_r_samples = [0.0; N];
+            let mut oxi_ir_samples = [0.0; N];
+            for (i, s) in samples.iter().enumerate() {
+                oxi_r_samples[i] = s.r;
+                oxi_ir_samples[i] = s.ir;
+            }
+            self.r.update_window(&oxi_r_samples);
+            self.ir.update_window(&oxi_ir_samples);
+        }
+
+        event
+    }
+
+    /// LED currents were changed, raw levels jump. Estimates start over,
+    /// nothing is reported until the window holds only samples taken
+    /// with the new currents.
+    pub fn on_led_step(&mut self) {
+        self.finger.resettle();
+        self.r.reset();
+        self.ir.reset();
+    }
+
+    /// Takes effect on the next `spo2` call, nothing else depends on it
+    pub fn set_spo2_calibration(&mut self, calibration: Spo2Calibration) {
+        self.config.spo2_calibration = calibration;
+    }
+
+    /// Latest die temperature reading
+    pub fn set_temperature(&mut self, celsius: Option<f32>) {
+        self.temperature_c = celsius;
+    }
+
+    pub fn temperature_c(&self) -> Option<f32> {
+        self.temperature_c
+    }
+
+    pub fn finger_state(&self) -> FingerState {
+        self.finger.state()
+    }
+
+    /// Worst of the two channels, nothing is usable until
+    /// the finger is settled on the sensor
+    pub fn quality(&self) -> SignalQuality {
+        match self.finger.state() {
+            FingerState::Absent => SignalQuality::NoContact,
+            FingerState::Settling => SignalQuality::Poor,
+            FingerState::Present => self.r.quality.quality.min(self.ir.quality.quality),
+        }
+    }
+
+    /// Heart rate from a channel with a usable signal, IR preferred
+    pub fn heart_rate_bpm(&self) -> Option<f32> {
+        if self.finger.state() != FingerState::Present {
+            return None;
+        }
+        [&self.ir, &self.r]
+            .iter()
+            .filter(|d| d.quality.quality >= SignalQuality::Acceptable)
+            .find_map(|d| d.heart_rate_bpm)
+    }
+
+    /// Channel with enough intervals for stats, IR preferred
+    pub fn hrv(&self) -> Option<&Hrv<HRV_NUM_INTERVALS>> {
+        if self.quality() < SignalQuality::Acceptable {
+            return None;
+        }
+        [&self.ir.hrv, &self.r.hrv]
+            .iter()
+            .copied()
+            .find(|hrv| hrv.len() >= 2)
+    }
+
+    pub fn hrv_stats(&self) -> Option<HrvStats> {
+        self.hrv().and_then(Hrv::stats)
+    }
+
+    /// Temperature compensated R ratio SpO2 is calculated from,
+    /// what calibration curves are fitted to
+    pub fn spo2_ratio(&self) -> Option<f32> {
+        if self.quality() < SignalQuality::Acceptable {
+            return None;
+        }
+        let r_acdc = self.r.ac_over_dc;
+        let ir_acdc = self.ir.ac_over_dc;
+        let mut ratio = r_acdc / ir_acdc;
+        if let Some(t) = self.temperature_c {
+            ratio /=
+                1.0 + self.config.spo2_ratio_temp_coeff * (t - self.config.spo2_reference_temp_c);
+        }
+        Some(ratio)
+    }
+
+    /// Needs a usable signal on both channels, [`Spo2::Invalid`]
+    /// if the R ratio is outside of the calibration curve
+    pub fn spo2(&self) -> Option<Spo2> {
+        self.spo2_ratio()
+            .map(|ratio| self.config.spo2_calibration.spo2(ratio))
+    }
+}
+
+impl<const N: usize> Default for UIModel<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calibration::LookupTable,
+        synth::{spo2_to_ratio, PpgConfig, PpgSynth},
+        timing::SampleStamper,
+    };
+
+    const N: usize = MAX30102_NUM_SAMPLES;
+
+    /// Runs the model over a synthetic signal a second at a time,
+    /// like the firmware idle loop. `period_ms` is the sample spacing
+    /// as seen by the MCU clock.
+    fn run(model: &mut UIModel<N>, cfg: PpgConfig, seconds: usize, period_ms: u32) {
+        run_checked(model, cfg, seconds, period_ms, |_, _| ());
+    }
+
+    /// `run`, calls `check` after every second with the seconds so far
+    fn run_checked(
+        model: &mut UIModel<N>,
+        cfg: PpgConfig,
+        seconds: usize,
+        period_ms: u32,
+        mut check: impl FnMut(&UIModel<N>, usize),
+    ) {
+        let mut window: Circ<Max3012Sample, N> = Circ::new(Max3012Sample::zero());
+        let mut stamper = SampleStamper::new(1000 / period_ms);
+        let mut chunk = 0;
+        for (i, s) in PpgSynth::new(cfg).take(25 * seconds).enumerate() {
+            let stamp = stamper.stamp(i as u32 * period_ms, 0, 1).next().unwrap();
+            window.add(Max3012Sample::new(s.sample, stamp));
+            chunk += 1;
+            if chunk == 25 {
+                let mut samples = [Max3012Sample::zero(); N];
+                for (i, s) in window.iter().enumerate() {
+                    samples[i] = s;
+                }
+                model.update_from_samples(&samples, chunk);
+                chunk = 0;
+                check(model, (i + 1) / 25);
+            }
+        }
+    }
+
+    #[test]
+    fn test_median_interval() {
+        assert_eq!(median_interval_ms([].iter().copied()), None);
+        assert_eq!(median_interval_ms([100].iter().copied()), None);
+        assert_eq!(median_interval_ms([100, 900].iter().copied()), Some(800));
+
+        // missed beat (1400ms) and a spurious one (100ms) don't move it
+        let beats = [0, 800, 1610, 3010, 3800, 3900, 4700];
+        assert_eq!(median_interval_ms(beats.iter().copied()), Some(800));
+
+        // even number of intervals, the lower middle one
+        let beats = [0, 100, 300, 600, 1000];
+        assert_eq!(median_interval_ms(beats.iter().copied()), Some(200));
+    }
+
+    #[test]
+    fn test_mean_interval() {
+        assert_eq!(mean_interval_ms([100].iter().copied()), None);
+
+        // 160bpm sampled at 25Hz, 9 and 10 sample intervals
+        let beats = [0, 400, 760, 1120, 1520, 1880, 2240, 2640, 3000];
+        assert_eq!(mean_interval_ms(beats.iter().copied()), Some(375.0));
+
+        // missed and spurious beats are left out
+        let beats = [0, 800, 1600, 3000, 3800, 3900, 4700];
+        assert_eq!(mean_interval_ms(beats.iter().copied()), Some(800.0));
+    }
+
+    #[test]
+    fn test_heart_rate_and_spo2() {
+        for (bpm, spo2) in [(50.0, 98.0), (75.0, 95.0), (120.0, 90.0)] {
+            let mut model = UIModel::<N>::new();
+            let cfg = PpgConfig {
+                heart_rate_bpm: bpm,
+                spo2,
+                noise: 10.0,
+                ..PpgConfig::default()
+            };
+            run(&mut model, cfg, 20, 40);
+
+            assert_eq!(model.finger_state(), FingerState::Present);
+            let hr = model.heart_rate_bpm().unwrap();
+            assert!((hr - bpm).abs() <= 2.0, "{} {}", bpm, hr);
+            let model_spo2 = model.spo2().and_then(Spo2::value).unwrap();
+            assert!((model_spo2 - spo2).abs() < 2.0, "{} {}", spo2, model_spo2);
+        }
+    }
+
+    #[test]
+    fn test_heart_rate_range() {
+        let rates = [40.0, 60.0, 100.0, 160.0, 220.0, 260.0];
+        let spo2s = [99.0, 97.0, 94.0, 90.0, 85.0, 80.0];
+        for (bpm, spo2) in rates.iter().copied().zip(spo2s.iter().copied()) {
+            let mut model = UIModel::<N>::new();
+            let cfg = PpgConfig {
+                heart_rate_bpm: bpm,
+                spo2,
+                resp_depth: 0.2,
+                wander: 0.005,
+                noise: 20.0,
+                ..PpgConfig::default()
+            };
+            // every second once the window is full of settled samples
+            run_checked(&mut model, cfg, 30, 40, |model, s| {
+                if s < 15 {
+                    return;
+                }
+                let hr = model.heart_rate_bpm().unwrap();
+                assert!((hr - bpm).abs() <= 2.0, "{} bpm at {}s: {}", bpm, s, hr);
+
+                // synthesizer ratio is the ground truth, before calibration,
+                // peak-to-peak of a few noisy beats is off by a bit
+                let ratio = model.spo2_ratio().unwrap();
+                let expected = spo2_to_ratio(spo2);
+                assert!(
+                    (ratio / expected - 1.0).abs() < 0.07,
+                    "{} bpm at {}s: ratio {}, expected {}",
+                    bpm,
+                    s,
+                    ratio,
+                    expected
+                );
+                let model_spo2 = model.spo2().and_then(Spo2::value).unwrap();
+                assert!(
+                    (model_spo2 - spo2).abs() <= 2.0,
+                    "{} bpm at {}s: SpO2 {}, expected {}",
+                    bpm,
+                    s,
+                    model_spo2,
+                    spo2
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn test_heart_rate_from_sample_time() {
+        // sensor clock runs 25% slow compared to the MCU,
+        // samples are 50ms apart instead of 40ms
+        let mut model = UIModel::<N>::new();
+        let cfg = PpgConfig {
+            heart_rate_bpm: 80.0,
+            ..PpgConfig::default()
+        };
+        run(&mut model, cfg, 20, 50);
+        let hr = model.heart_rate_bpm().unwrap();
+        assert!((hr - 64.0).abs() < 2.0, "{}", hr);
+    }
+
+    #[test]
+    fn test_heartbeat_thresholding() {
+        // noise wiggles and the dicrotic bump don't count as beats
+        let mut model = UIModel::<N>::new();
+        let cfg = PpgConfig {
+            heart_rate_bpm: 60.0,
+            noise: 30.0,
+            ..PpgConfig::default()
+        };
+        run(&mut model, cfg, 20, 40);
+        let beats = model.ir.heartbeats.len();
+        assert!((6..=7).contains(&beats), "{}", beats);
+
+        // a much weaker signal is picked up again once the envelope decays
+        let cfg = PpgConfig {
+            perfusion: 0.001,
+            noise: 3.0,
+            ..cfg
+        };
+        run(&mut model, cfg, 20, 40);
+        let beats = model.ir.heartbeats.len();
+        assert!((6..=7).contains(&beats), "{}", beats);
+    }
+
+    #[test]
+    fn test_no_finger() {
+        let mut model = UIModel::<N>::new();
+        let cfg = PpgConfig {
+            ir_dc: 500.0,
+            r_dc: 400.0,
+            ..PpgConfig::default()
+        };
+        run(&mut model, cfg, 10, 40);
+        assert_eq!(model.finger_state(), FingerState::Absent);
+        assert_eq!(model.quality(), SignalQuality::NoContact);
+        assert_eq!(model.heart_rate_bpm(), None);
+        assert_eq!(model.spo2(), None);
+        assert!(model.ir.heartbeats.is_empty());
+    }
+
+    #[test]
+    fn test_led_step() {
+        let mut model = UIModel::<N>::new();
+        let cfg = PpgConfig {
+            heart_rate_bpm: 75.0,
+            ..PpgConfig::default()
+        };
+        run(&mut model, cfg, 20, 40);
+        assert!(model.heart_rate_bpm().is_some());
+
+        model.on_led_step();
+        assert_eq!(model.finger_state(), FingerState::Settling);
+        assert_eq!(model.heart_rate_bpm(), None);
+
+        // IR current halved, window still has the old level in it
+        let cfg = PpgConfig {
+            ir_dc: 50_000.0,
+            ..cfg
+        };
+        run(&mut model, cfg, 6, 40);
+        assert_eq!(model.finger_state(), FingerState::Settling);
+        assert_eq!(model.spo2(), None);
+
+        run(&mut model, cfg, 10, 40);
+        assert_eq!(model.finger_state(), FingerState::Present);
+        let hr = model.heart_rate_bpm().unwrap();
+        assert!((hr - 75.0).abs() < 4.0, "{}", hr);
+        let spo2 = model.spo2().and_then(Spo2::value).unwrap();
+        assert!((spo2 - cfg.spo2).abs() < 2.0, "{}", spo2);
+    }
+
+    #[test]
+    fn test_spo2_temperature() {
+        let config = ModelConfig {
+            spo2_ratio_temp_coeff: 0.01,
+            ..ModelConfig::default()
+        };
+        let mut model = UIModel::<N>::with_config(config);
+        run(&mut model, PpgConfig::default(), 20, 40);
+        let ratio = model.r.ac_over_dc / model.ir.ac_over_dc;
+        let spo2 = model.spo2().and_then(Spo2::value).unwrap();
+        let calibration = Spo2Calibration::default();
+        assert_eq!(Some(spo2), calibration.spo2(ratio).value());
+
+        model.set_temperature(Some(25.0));
+        assert_eq!(model.spo2(), Some(Spo2::Valid(spo2)));
+
+        // 10°C warmer, ratio reads 10% high
+        model.set_temperature(Some(35.0));
+        assert_eq!(model.temperature_c(), Some(35.0));
+        assert!((model.spo2_ratio().unwrap() - ratio / 1.1).abs() < 1e-6);
+        let compensated = model.spo2().and_then(Spo2::value).unwrap();
+        let expected = calibration.spo2(ratio / 1.1).value().unwrap();
+        assert!((compensated - expected).abs() < 1e-3);
+        assert!(compensated > spo2);
+    }
+
+    #[test]
+    fn test_spo2_invalid() {
+        // calibrated for low saturations only, a healthy finger is off the table
+        let table = LookupTable::new(&[(1.2, 75.0), (2.0, 55.0)]).unwrap();
+        let config = ModelConfig {
+            spo2_calibration: Spo2Calibration::Lookup(table),
+            ..ModelConfig::default()
+        };
+        let mut model = UIModel::<N>::with_config(config);
+        run(&mut model, PpgConfig::default(), 20, 40);
+        assert!(model.heart_rate_bpm().is_some());
+        assert_eq!(model.spo2(), Some(Spo2::Invalid));
+    }
+
+    #[test]
+    fn test_window_idx() {
+        let mut data = Max3012SampleData::<8>::new(ModelConfig::default());
+        for i in 0..20 {
+            data.add_sample(1000.0, i * 40);
+        }
+        assert_eq!(data.window_idx(19), 7);
+        assert_eq!(data.window_idx(12), 0);
+        assert_eq!(data.sample_time_ms(12), 480);
+    }
+
+    #[test]
+    fn test_beat_older_than_window() {
+        // close to pass-through filter, so the detector sees the input shape
+        let config = ModelConfig {
+            filter_low_hz: 0.01,
+            filter_high_hz: 12.0,
+            ..ModelConfig::default()
+        };
+        let mut data = Max3012SampleData::<8>::new(config);
+        let mut xs = vec![0.0; 20];
+        xs.extend([100.0, 75.0, 50.0, 25.0, 0.0, 0.0, 100.0]);
+        // peak held through wiggles too small to be beats,
+        // until it's out of the window
+        xs.extend((0..20).map(|i| if i % 2 == 0 { 80.0 } else { 81.0 }));
+        xs.extend([0.0, 0.0, 0.0]);
+        for (i, x) in xs.into_iter().enumerate() {
+            data.add_sample(x, i as u32 * 40);
+        }
+        assert_eq!(data.heartbeats.len(), 1);
+        assert_eq!(data.heartbeats[0].high_idx, 20);
+        assert_eq!(data.last_beat_ms, None);
+    }
+
+    #[test]
+    fn test_buffer_cleared() {
+        // the firmware clears the shared buffer after the idle loop copied it,
+        // samples added in between are wiped before the model sees them
+        let mut model = UIModel::<N>::new();
+        let mut buf: Circ<Max3012Sample, N> = Circ::new(Max3012Sample::zero());
+        let mut stamper = SampleStamper::new(25);
+        let mut synth = PpgSynth::new(PpgConfig::default());
+        let mut seen = 0;
+        let mut t = 0;
+        let mut feed = |buf: &mut Circ<_, N>, n: usize, lost: usize| {
+            synth.by_ref().take(lost).for_each(drop);
+            for stamp in stamper.stamp(t, lost, n) {
+                buf.add(Max3012Sample::new(synth.next().unwrap().sample, stamp));
+            }
+            t += 40 * n as u32;
+        };
+        let mut update = |model: &mut UIModel<N>, buf: &Circ<_, N>| {
+            let mut samples = [Max3012Sample::zero(); N];
+            for (i, s) in buf.iter().enumerate() {
+                samples[i] = s;
+            }
+            let num_new = buf.total_added() - seen;
+            seen = buf.total_added();
+            model.update_from_samples(&samples, num_new);
+        };
+
+        for _ in 0..10 {
+            feed(&mut buf, 25, 0);
+            update(&mut model, &buf);
+        }
+        assert!(model.heart_rate_bpm().is_some());
+
+        feed(&mut buf, 5, 0);
+        buf.clear(Max3012Sample::zero());
+        feed(&mut buf, 20, 0);
+        update(&mut model, &buf);
+        assert_eq!(model.sensor_overflow, 0);
+
+        // real losses after that still count
+        feed(&mut buf, 25, 3);
+        update(&mut model, &buf);
+        assert_eq!(model.sensor_overflow, 3);
+        for _ in 0..10 {
+            feed(&mut buf, 25, 0);
+            update(&mut model, &buf);
+        }
+        assert_eq!(model.sensor_overflow, 3);
+        let hr = model.heart_rate_bpm().unwrap();
+        assert!((hr - 60.0).abs() < 4.0, "{}", hr);
+    }
+}
@@ -0,0 +1,476 @@
+//! PPG sensor parts
+//!
+//! The firmware only needs a few things from a sensor, [`PpgSensor`]
+//! has them. MAX30101, MAX30102 and MAX30105 share the register map and
+//! the part ID, they differ in LEDs: the MAX30102 has red and IR, the
+//! other two add a green one. [`Max3010x`] drives any of them, which
+//! part it is comes from the caller, the part ID can't tell.
+//!
+//! Register values are written directly, the whole configuration every
+//! time, so nothing depends on what the sensor was left with. The
+//! `max3010x` crate this replaces only has the MAX30102, no multi-LED
+//! mode or slot setup, and its part type is fixed at compile time.
+//! FIFO reads went around it already.
+
+use core::fmt;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+use crate::{
+    fifo::{
+        register, ChannelOrder, FifoInterrupt, FifoRead, FifoSample, LedMode, Max30102Fifo,
+        FIFO_A_FULL_MASK,
+    },
+    led_control::LedAmplitudes,
+    sensor::{AdcRange, PulseWidth, SampleAveraging, SampleRate, SensorSettings},
+};
+
+/// PART_ID of the whole MAX3010x family, MAX30100 excepted
+pub const MAX3010X_PART_ID: u8 = 0x15;
+
+/// MODE_CONFIG modes
+const MODE_SPO2: u8 = 0b011;
+const MODE_MULTI_LED: u8 = 0b111;
+
+/// FIFO_CONFIG rollover bit
+const FIFO_ROLLOVER_EN: u8 = 1 << 4;
+
+/// MULTI_LED_CTRL slot sources
+const SLOT_LED1: u8 = 1;
+const SLOT_LED2: u8 = 2;
+const SLOT_LED3: u8 = 3;
+
+/// TEMP_CONFIG bit, self clears when the conversion is done
+const TEMP_EN: u8 = 1 << 0;
+
+/// What the firmware needs from a sensor
+pub trait PpgSensor {
+    type Error;
+
+    fn part(&self) -> Part;
+
+    /// Applies all settings, LEDs at `settings.led_amplitude`.
+    /// FIFO is cleared, samples start over.
+    fn configure(&mut self, settings: &SensorSettings) -> Result<(), Self::Error>;
+
+    /// Red and IR LED currents
+    fn set_led_amplitudes(&mut self, amplitudes: LedAmplitudes) -> Result<(), Self::Error>;
+
+    /// Enables one of the FIFO interrupts, disables the rest
+    fn enable_interrupt(&mut self, int: FifoInterrupt) -> Result<(), Self::Error>;
+
+    /// To be called when INT pin goes low, reads pending samples
+    fn on_interrupt(&mut self, out: &mut [FifoSample]) -> Result<FifoRead, Self::Error>;
+
+    /// Starts a die temperature conversion, it takes about 30ms
+    fn start_temperature(&mut self) -> Result<(), Self::Error>;
+
+    /// Die temperature, °C, `None` until a started conversion is done
+    fn read_temperature(&mut self) -> Result<Option<f32>, Self::Error>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Part {
+    /// Red and IR LEDs
+    Max30102,
+    /// Red, IR and green LEDs, MAX30105 too
+    Max30101,
+}
+
+impl Part {
+    /// Green samples come along in multi-LED mode
+    pub fn led_mode(self) -> LedMode {
+        match self {
+            Part::Max30102 => LedMode::SpO2,
+            Part::Max30101 => LedMode::MultiLed,
+        }
+    }
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Part::Max30102 => write!(f, "MAX30102"),
+            Part::Max30101 => write!(f, "MAX30101/5"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DetectError<E> {
+    I2C(E),
+    /// Something else answers at the address, PART_ID value
+    UnknownPart(u8),
+}
+
+/// Any of the MAX3010x parts
+pub struct Max3010x<I2C> {
+    fifo: Max30102Fifo<I2C>,
+    part: Part,
+    temperature_pending: bool,
+}
+
+impl<I2C, E> Max3010x<I2C>
+where
+    I2C: WriteRead<Error = E> + Write<Error = E>,
+{
+    /// Checks there's a MAX3010x at the address, `part` is which one
+    /// the module has. `order` is how the module has the LEDs wired,
+    /// see [`ChannelOrder`]. Sensor is left as it is, to be configured next.
+    pub fn new(i2c: I2C, part: Part, order: ChannelOrder) -> Result<Self, DetectError<E>> {
+        let mut fifo = Max30102Fifo::new(i2c, part.led_mode(), order);
+        let id = fifo
+            .read_register(register::PART_ID)
+            .map_err(DetectError::I2C)?;
+        if id != MAX3010X_PART_ID {
+            return Err(DetectError::UnknownPart(id));
+        }
+        Ok(Max3010x {
+            fifo,
+            part,
+            temperature_pending: false,
+        })
+    }
+
+    /// Like [`Max3010x::new`], with the part guessed from whether
+    /// `LED3_PA` keeps a written value. That register is documented for
+    /// the MAX30101/MAX30105 only, it's reserved on the MAX30102 and
+    /// what it reads back there isn't specified. Opt-in, for modules
+    /// the guess was checked on, otherwise the part has to be given.
+    pub fn detect(i2c: I2C, order: ChannelOrder) -> Result<Self, DetectError<E>> {
+        let mut sensor = Self::new(i2c, Part::Max30102, order)?;
+        if has_green_led(&mut sensor.fifo).map_err(DetectError::I2C)? {
+            sensor = Self::new(sensor.destroy(), Part::Max30101, order)?;
+        }
+        Ok(sensor)
+    }
+
+    pub fn destroy(self) -> I2C {
+        self.fifo.destroy()
+    }
+}
+
+impl<I2C, E> PpgSensor for Max3010x<I2C>
+where
+    I2C: WriteRead<Error = E> + Write<Error = E>,
+{
+    type Error = E;
+
+    fn part(&self) -> Part {
+        self.part
+    }
+
+    fn configure(&mut self, settings: &SensorSettings) -> Result<(), E> {
+        let fifo = &mut self.fifo;
+
+        // interrupt level is set up separately
+        let a_full = fifo.read_register(register::FIFO_CONFIG)? & FIFO_A_FULL_MASK;
+        fifo.write_register(
+            register::FIFO_CONFIG,
+            averaging_bits(settings.averaging) << 5 | FIFO_ROLLOVER_EN | a_full,
+        )?;
+        fifo.write_register(
+            register::SPO2_CONFIG,
+            adc_range_bits(settings.adc_range) << 5
+                | sample_rate_bits(settings.sample_rate) << 2
+                | pulse_width_bits(settings.pulse_width),
+        )?;
+
+        match self.part {
+            Part::Max30102 => fifo.write_register(register::MODE_CONFIG, MODE_SPO2)?,
+            Part::Max30101 => {
+                fifo.write_register(register::MULTI_LED_CTRL1, SLOT_LED2 << 4 | SLOT_LED1)?;
+                fifo.write_register(register::MULTI_LED_CTRL2, SLOT_LED3)?;
+                fifo.write_register(register::MODE_CONFIG, MODE_MULTI_LED)?;
+                fifo.write_register(register::LED3_PA, settings.led_amplitude)?;
+            }
+        }
+        fifo.set_led_amplitudes(settings.led_amplitude, settings.led_amplitude)?;
+
+        for reg in [
+            register::FIFO_WR_PTR,
+            register::OVF_COUNTER,
+            register::FIFO_RD_PTR,
+        ] {
+            fifo.write_register(reg, 0)?;
+        }
+
+        // a stale flag would keep INT low, no falling edge would ever come
+        fifo.read_interrupt_status()?;
+        Ok(())
+    }
+
+    fn set_led_amplitudes(&mut self, amplitudes: LedAmplitudes) -> Result<(), E> {
+        self.fifo.set_led_amplitudes(amplitudes.red, amplitudes.ir)
+    }
+
+    fn enable_interrupt(&mut self, int: FifoInterrupt) -> Result<(), E> {
+        self.fifo.enable_interrupt(int)
+    }
+
+    fn on_interrupt(&mut self, out: &mut [FifoSample]) -> Result<FifoRead, E> {
+        self.fifo.on_interrupt(out)
+    }
+
+    fn start_temperature(&mut self) -> Result<(), E> {
+        self.fifo.write_register(register::TEMP_CONFIG, TEMP_EN)?;
+        self.temperature_pending = true;
+        Ok(())
+    }
+
+    fn read_temperature(&mut self) -> Result<Option<f32>, E> {
+        if !self.temperature_pending
+            || self.fifo.read_register(register::TEMP_CONFIG)? & TEMP_EN != 0
+        {
+            return Ok(None);
+        }
+        self.temperature_pending = false;
+
+        let int = self.fifo.read_register(register::TEMP_INT)? as i8;
+        let frac = self.fifo.read_register(register::TEMP_FRAC)? & 0x0f;
+        Ok(Some(int as f32 + frac as f32 * 0.0625))
+    }
+}
+
+/// Part ID is the same, parts with a green LED keep what's written to
+/// its current register. Relies on the MAX30102 not doing the same with
+/// the reserved register at that address, see [`Max3010x::detect`].
+fn has_green_led<I2C, E>(fifo: &mut Max30102Fifo<I2C>) -> Result<bool, E>
+where
+    I2C: WriteRead<Error = E> + Write<Error = E>,
+{
+    let probe = 0x5a;
+    fifo.write_register(register::LED3_PA, probe)?;
+    let green = fifo.read_register(register::LED3_PA)? == probe;
+    fifo.write_register(register::LED3_PA, 0)?;
+    Ok(green)
+}
+
+fn averaging_bits(sa: SampleAveraging) -> u8 {
+    match sa {
+        SampleAveraging::Sa1 => 0,
+        SampleAveraging::Sa2 => 1,
+        SampleAveraging::Sa4 => 2,
+        SampleAveraging::Sa8 => 3,
+        SampleAveraging::Sa16 => 4,
+        SampleAveraging::Sa32 => 5,
+    }
+}
+
+fn adc_range_bits(r: AdcRange) -> u8 {
+    match r {
+        AdcRange::Fs2k => 0,
+        AdcRange::Fs4k => 1,
+        AdcRange::Fs8k => 2,
+        AdcRange::Fs16k => 3,
+    }
+}
+
+fn sample_rate_bits(sr: SampleRate) -> u8 {
+    match sr {
+        SampleRate::Sps50 => 0,
+        SampleRate::Sps100 => 1,
+        SampleRate::Sps200 => 2,
+        SampleRate::Sps400 => 3,
+        SampleRate::Sps800 => 4,
+        SampleRate::Sps1000 => 5,
+        SampleRate::Sps1600 => 6,
+        SampleRate::Sps3200 => 7,
+    }
+}
+
+fn pulse_width_bits(pw: PulseWidth) -> u8 {
+    match pw {
+        PulseWidth::Us69 => 0,
+        PulseWidth::Us118 => 1,
+        PulseWidth::Us215 => 2,
+        PulseWidth::Us411 => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fifo::tests::MockSensor;
+
+    fn reg(sensor: &mut Max3010x<MockSensor>, r: u8) -> u8 {
+        sensor.fifo.i2c().regs[r as usize]
+    }
+
+    /// Nothing answers at the address
+    struct NoDevice;
+
+    impl WriteRead for NoDevice {
+        type Error = ();
+
+        fn write_read(&mut self, _: u8, _: &[u8], _: &mut [u8]) -> Result<(), ()> {
+            Err(())
+        }
+    }
+
+    impl Write for NoDevice {
+        type Error = ();
+
+        fn write(&mut self, _: u8, _: &[u8]) -> Result<(), ()> {
+            Err(())
+        }
+    }
+
+    #[test]
+    fn test_part_id() {
+        // MAX30101, MAX30102 and MAX30105 all read 0x15
+        for (mock, part) in [
+            (MockSensor::new(), Part::Max30102),
+            (MockSensor::with_green(), Part::Max30101),
+        ] {
+            assert_eq!(mock.regs[register::PART_ID as usize], MAX3010X_PART_ID);
+            let mut sensor = Max3010x::new(mock, part, ChannelOrder::RedIr).unwrap();
+            assert_eq!(sensor.part(), part);
+            // left as it was, to be configured
+            assert_eq!(reg(&mut sensor, register::MODE_CONFIG), 0);
+        }
+
+        // MAX30100, and the MAX86150 with a different register map
+        for id in [0x11, 0x1e] {
+            let mut mock = MockSensor::new();
+            mock.regs[register::PART_ID as usize] = id;
+            assert!(matches!(
+                Max3010x::new(mock, Part::Max30102, ChannelOrder::RedIr),
+                Err(DetectError::UnknownPart(i)) if i == id
+            ));
+        }
+
+        assert!(matches!(
+            Max3010x::new(NoDevice, Part::Max30102, ChannelOrder::RedIr),
+            Err(DetectError::I2C(()))
+        ));
+    }
+
+    #[test]
+    fn test_detect() {
+        let sensor = Max3010x::detect(MockSensor::new(), ChannelOrder::IrRed).unwrap();
+        assert_eq!(sensor.part(), Part::Max30102);
+
+        let mut sensor = Max3010x::detect(MockSensor::with_green(), ChannelOrder::RedIr).unwrap();
+        assert_eq!(sensor.part(), Part::Max30101);
+        assert_eq!(reg(&mut sensor, register::LED3_PA), 0);
+
+        let mut mock = MockSensor::new();
+        mock.regs[register::PART_ID as usize] = 0x11;
+        assert!(matches!(
+            Max3010x::detect(mock, ChannelOrder::RedIr),
+            Err(DetectError::UnknownPart(0x11))
+        ));
+    }
+
+    #[test]
+    fn test_configure() {
+        let mut mock = MockSensor::new();
+        // interrupt level and a stale sample, from before
+        mock.regs[register::FIFO_CONFIG as usize] = 0x0f;
+        mock.push(1, 2, 0x40);
+
+        let mut sensor = Max3010x::new(mock, Part::Max30102, ChannelOrder::IrRed).unwrap();
+        sensor.configure(&SensorSettings::default()).unwrap();
+
+        // 16 samples averaged, rollover
+        assert_eq!(reg(&mut sensor, register::FIFO_CONFIG), 0b1001_1111);
+        // 16384nA, 400sps, 411us
+        assert_eq!(reg(&mut sensor, register::SPO2_CONFIG), 0b0110_1111);
+        assert_eq!(reg(&mut sensor, register::MODE_CONFIG), MODE_SPO2);
+        assert_eq!(reg(&mut sensor, register::LED1_PA), 200);
+        assert_eq!(reg(&mut sensor, register::LED2_PA), 200);
+        assert_eq!(reg(&mut sensor, register::FIFO_WR_PTR), 0);
+        assert_eq!(reg(&mut sensor, register::INT_STATUS_1), 0);
+
+        sensor
+            .set_led_amplitudes(LedAmplitudes { red: 10, ir: 20 })
+            .unwrap();
+        assert_eq!(reg(&mut sensor, register::LED1_PA), 20);
+        assert_eq!(reg(&mut sensor, register::LED2_PA), 10);
+    }
+
+    #[test]
+    fn test_multi_led() {
+        let mut sensor = Max3010x::new(
+            MockSensor::with_green(),
+            Part::Max30101,
+            ChannelOrder::RedIr,
+        )
+        .unwrap();
+        let settings = SensorSettings {
+            led_amplitude: 50,
+            ..SensorSettings::default()
+        };
+        sensor.configure(&settings).unwrap();
+        assert_eq!(reg(&mut sensor, register::MODE_CONFIG), MODE_MULTI_LED);
+        assert_eq!(reg(&mut sensor, register::MULTI_LED_CTRL1), 0x21);
+        assert_eq!(reg(&mut sensor, register::MULTI_LED_CTRL2), 0x03);
+        assert_eq!(reg(&mut sensor, register::LED3_PA), 50);
+
+        sensor.fifo.i2c().push_slots(&[100, 200, 300], 0x40);
+        let mut out = [FifoSample::zero(); 4];
+        assert_eq!(sensor.on_interrupt(&mut out).unwrap().num_samples, 1);
+        assert_eq!(
+            out[0],
+            FifoSample {
+                red: 100,
+                ir: 200,
+                green: 300
+            }
+        );
+    }
+
+    #[test]
+    fn test_slots_per_part() {
+        // MAX30101 and MAX30105 have the same LED slots, LED1 red,
+        // LED2 IR, LED3 green
+        let mut sensor = Max3010x::new(
+            MockSensor::with_green(),
+            Part::Max30101,
+            ChannelOrder::IrRed,
+        )
+        .unwrap();
+        sensor.configure(&SensorSettings::default()).unwrap();
+        assert_eq!(
+            reg(&mut sensor, register::MULTI_LED_CTRL1) & 0x07,
+            SLOT_LED1
+        );
+        assert_eq!(reg(&mut sensor, register::MULTI_LED_CTRL1) >> 4, SLOT_LED2);
+        assert_eq!(
+            reg(&mut sensor, register::MULTI_LED_CTRL2) & 0x07,
+            SLOT_LED3
+        );
+        assert_eq!(reg(&mut sensor, register::MULTI_LED_CTRL2) >> 4, 0);
+        assert_eq!(sensor.part().led_mode(), LedMode::MultiLed);
+        assert_eq!(format!("{}", sensor.part()), "MAX30101/5");
+
+        // MAX30102 runs in SpO2 mode, slots aren't used
+        let mut sensor =
+            Max3010x::new(MockSensor::new(), Part::Max30102, ChannelOrder::IrRed).unwrap();
+        sensor.configure(&SensorSettings::default()).unwrap();
+        assert_eq!(reg(&mut sensor, register::MODE_CONFIG), MODE_SPO2);
+        assert_eq!(reg(&mut sensor, register::MULTI_LED_CTRL1), 0);
+        assert_eq!(reg(&mut sensor, register::LED3_PA), 0);
+        assert_eq!(sensor.part().led_mode(), LedMode::SpO2);
+        assert_eq!(format!("{}", sensor.part()), "MAX30102");
+    }
+
+    #[test]
+    fn test_temperature() {
+        let mut sensor =
+            Max3010x::new(MockSensor::new(), Part::Max30102, ChannelOrder::IrRed).unwrap();
+        assert_eq!(sensor.read_temperature(), Ok(None));
+
+        sensor.start_temperature().unwrap();
+        assert_eq!(reg(&mut sensor, register::TEMP_CONFIG), TEMP_EN);
+        assert_eq!(sensor.read_temperature(), Ok(None));
+
+        // conversion done, -5 + 0.75
+        let regs = &mut sensor.fifo.i2c().regs;
+        regs[register::TEMP_CONFIG as usize] = 0;
+        regs[register::TEMP_INT as usize] = -5i8 as u8;
+        regs[register::TEMP_FRAC as usize] = 12;
+        assert_eq!(sensor.read_temperature(), Ok(Some(-4.25)));
+        assert_eq!(sensor.read_temperature(), Ok(None));
+    }
+}
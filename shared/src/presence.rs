@@ -0,0 +1,182 @@
+//! Finger presence detection from the raw IR DC level
+//!
+//! Ambient light alone gives a low IR reading, a finger on the sensor
+//! reflects most of the IR LED light back. Separate on/off levels and a
+//! short debounce keep the state from flickering around the threshold.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FingerState {
+    Absent,
+    /// Finger just placed, signal is not stable yet
+    Settling,
+    Present,
+}
+
+/// State transitions
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FingerEvent {
+    Placed,
+    Settled,
+    Removed,
+}
+
+pub struct FingerDetector {
+    on_level: f32,
+    off_level: f32,
+    debounce_samples: usize,
+    settle_samples: usize,
+
+    state: FingerState,
+
+    /// consecutive samples on the other side of the threshold
+    debounce_cnt: usize,
+    /// samples since placement
+    settle_cnt: usize,
+}
+
+impl FingerDetector {
+    /// `on_level`/`off_level`: raw IR levels for placement/removal,
+    /// `debounce_samples`: how long a level has to hold for a state change,
+    /// `settle_samples`: time after placement before the signal is usable.
+    pub fn new(
+        on_level: f32,
+        off_level: f32,
+        debounce_samples: usize,
+        settle_samples: usize,
+    ) -> Self {
+        FingerDetector {
+            on_level,
+            off_level,
+            debounce_samples,
+            settle_samples,
+            state: FingerState::Absent,
+            debounce_cnt: 0,
+            settle_cnt: 0,
+        }
+    }
+
+    pub fn state(&self) -> FingerState {
+        self.state
+    }
+
+    /// Signal changed under a placed finger (LED currents),
+    /// wait for it to settle again
+    pub fn resettle(&mut self) {
+        if self.state != FingerState::Absent {
+            self.state = FingerState::Settling;
+            self.settle_cnt = 0;
+        }
+    }
+
+    pub fn update(&mut self, ir: f32) -> Option<FingerEvent> {
+        let crossed = match self.state {
+            FingerState::Absent => ir >= self.on_level,
+            FingerState::Settling | FingerState::Present => ir < self.off_level,
+        };
+
+        if crossed {
+            self.debounce_cnt += 1;
+        } else {
+            self.debounce_cnt = 0;
+        }
+
+        if self.debounce_cnt >= self.debounce_samples {
+            self.debounce_cnt = 0;
+            self.settle_cnt = 0;
+            return match self.state {
+                FingerState::Absent => {
+                    self.state = FingerState::Settling;
+                    Some(FingerEvent::Placed)
+                }
+                FingerState::Settling | FingerState::Present => {
+                    self.state = FingerState::Absent;
+                    Some(FingerEvent::Removed)
+                }
+            };
+        }
+
+        if self.state == FingerState::Settling {
+            self.settle_cnt += 1;
+            if self.settle_cnt >= self.settle_samples {
+                self.state = FingerState::Present;
+                return Some(FingerEvent::Settled);
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(fd: &mut FingerDetector, level: f32, n: usize) -> Vec<FingerEvent> {
+        (0..n).filter_map(|_| fd.update(level)).collect()
+    }
+
+    #[test]
+    fn test_place_settle_remove() {
+        let mut fd = FingerDetector::new(20_000.0, 10_000.0, 3, 10);
+        assert_eq!(fd.state(), FingerState::Absent);
+        assert_eq!(feed(&mut fd, 500.0, 100), vec![]);
+
+        assert_eq!(feed(&mut fd, 50_000.0, 3), vec![FingerEvent::Placed]);
+        assert_eq!(fd.state(), FingerState::Settling);
+
+        assert_eq!(feed(&mut fd, 50_000.0, 9), vec![]);
+        assert_eq!(feed(&mut fd, 50_000.0, 1), vec![FingerEvent::Settled]);
+        assert_eq!(fd.state(), FingerState::Present);
+
+        assert_eq!(feed(&mut fd, 500.0, 3), vec![FingerEvent::Removed]);
+        assert_eq!(fd.state(), FingerState::Absent);
+    }
+
+    #[test]
+    fn test_debounce() {
+        let mut fd = FingerDetector::new(20_000.0, 10_000.0, 3, 10);
+        for _ in 0..10 {
+            assert_eq!(feed(&mut fd, 50_000.0, 2), vec![]);
+            assert_eq!(feed(&mut fd, 500.0, 1), vec![]);
+        }
+        assert_eq!(fd.state(), FingerState::Absent);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut fd = FingerDetector::new(20_000.0, 10_000.0, 1, 1);
+
+        // between the levels, nothing changes in either state
+        assert_eq!(feed(&mut fd, 15_000.0, 10), vec![]);
+        assert_eq!(
+            feed(&mut fd, 25_000.0, 2),
+            vec![FingerEvent::Placed, FingerEvent::Settled]
+        );
+        assert_eq!(feed(&mut fd, 15_000.0, 10), vec![]);
+        assert_eq!(fd.state(), FingerState::Present);
+    }
+
+    #[test]
+    fn test_removed_while_settling() {
+        let mut fd = FingerDetector::new(20_000.0, 10_000.0, 2, 10);
+        assert_eq!(feed(&mut fd, 50_000.0, 5), vec![FingerEvent::Placed]);
+        assert_eq!(feed(&mut fd, 500.0, 2), vec![FingerEvent::Removed]);
+
+        // settling starts over
+        assert_eq!(feed(&mut fd, 50_000.0, 11), vec![FingerEvent::Placed]);
+        assert_eq!(feed(&mut fd, 50_000.0, 1), vec![FingerEvent::Settled]);
+    }
+
+    #[test]
+    fn test_resettle() {
+        let mut fd = FingerDetector::new(20_000.0, 10_000.0, 1, 10);
+        fd.resettle();
+        assert_eq!(fd.state(), FingerState::Absent);
+
+        assert_eq!(feed(&mut fd, 50_000.0, 11).len(), 2);
+        fd.resettle();
+        assert_eq!(fd.state(), FingerState::Settling);
+        assert_eq!(feed(&mut fd, 50_000.0, 9), vec![]);
+        assert_eq!(feed(&mut fd, 50_000.0, 1), vec![FingerEvent::Settled]);
+    }
+}
@@ -0,0 +1,589 @@
+//! Binary streaming protocol, device to host
+//!
+//! Each message is a frame: `version, type, body, crc16` (CRC over
+//! everything before it, little endian), COBS encoded and terminated
+//! with a 0 byte. A receiver that joins mid-stream drops everything up
+//! to the first 0 and is in sync from there on.
+//!
+//! Multi-byte fields are little endian. Missing values (no heart rate
+//! yet) are sent as NaN. SpO2 has a status byte ahead of its value, no
+//! reading yet and one out of the calibrated range are told apart.
+
+use crate::{
+    calibration::Spo2, crc::crc16, hrv::HrvStats, presence::FingerState, quality::SignalQuality,
+};
+
+/// Bumped on any incompatible change to the frame or message layout
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Frame delimiter
+pub const FRAME_END: u8 = 0;
+
+/// Longest message payload: version, type, body and CRC
+pub const MAX_PAYLOAD_LEN: usize = 2 + 22 + 2;
+
+/// Longest encoded frame, COBS overhead and the delimiter included
+pub const MAX_FRAME_LEN: usize = MAX_PAYLOAD_LEN + MAX_PAYLOAD_LEN / 254 + 2;
+
+mod msg_type {
+    pub const SAMPLE: u8 = 1;
+    pub const VITALS: u8 = 2;
+    pub const BEAT: u8 = 3;
+    pub const HRV: u8 = 4;
+    pub const TEMPERATURE: u8 = 5;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Message {
+    /// Raw sensor sample, gaps in `seq` mean lost samples
+    Sample {
+        seq: u32,
+        time_ms: u32,
+        red: u32,
+        ir: u32,
+    },
+
+    /// Model output, once per display update
+    Vitals {
+        time_ms: u32,
+        heart_rate_bpm: Option<f32>,
+        /// `Invalid` when the R ratio is outside the calibrated range
+        spo2: Option<Spo2>,
+        quality: SignalQuality,
+        finger: FingerState,
+    },
+
+    /// Heartbeat detected, time of the bottom of the beat's drop
+    Beat { time_ms: u32 },
+
+    /// HRV stats over the accumulated intervals, sent with `Vitals`
+    /// while there are any. The interval count is capped at 65535.
+    Hrv { time_ms: u32, stats: HrvStats },
+
+    /// Sensor die temperature, °C
+    Temperature { time_ms: u32, celsius: f32 },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// Output buffer can't hold the frame
+    BufferTooSmall,
+    /// Not a valid COBS frame, or too short
+    Framing,
+    Crc,
+    /// Sent by an incompatible firmware version
+    Version(u8),
+    UnknownMessage(u8),
+    /// Message body doesn't match its type
+    Length,
+    /// Field value out of range
+    Value,
+}
+
+impl Message {
+    /// Encodes a complete frame, delimiter included, returns its length
+    pub fn encode(&self, out: &mut [u8]) -> Result<usize, ProtocolError> {
+        let mut payload = [0u8; MAX_PAYLOAD_LEN];
+        let mut w = Writer {
+            buf: &mut payload,
+            len: 0,
+        };
+        w.u8(PROTOCOL_VERSION);
+        match *self {
+            Message::Sample {
+                seq,
+                time_ms,
+                red,
+                ir,
+            } => {
+                w.u8(msg_type::SAMPLE);
+                w.u32(seq);
+                w.u32(time_ms);
+                w.u32(red);
+                w.u32(ir);
+            }
+            Message::Vitals {
+                time_ms,
+                heart_rate_bpm,
+                spo2,
+                quality,
+                finger,
+            } => {
+                w.u8(msg_type::VITALS);
+                w.u32(time_ms);
+                w.f32(heart_rate_bpm.unwrap_or(f32::NAN));
+                w.u8(spo2_status_to_u8(spo2));
+                w.f32(spo2.and_then(Spo2::value).unwrap_or(f32::NAN));
+                w.u8(quality_to_u8(quality));
+                w.u8(finger_to_u8(finger));
+            }
+            Message::Beat { time_ms } => {
+                w.u8(msg_type::BEAT);
+                w.u32(time_ms);
+            }
+            Message::Hrv { time_ms, stats } => {
+                w.u8(msg_type::HRV);
+                w.u32(time_ms);
+                w.u16(stats.num_intervals.min(u16::MAX as usize) as u16);
+                w.f32(stats.mean_nn);
+                w.f32(stats.sdnn);
+                w.f32(stats.rmssd);
+                w.f32(stats.pnn50);
+            }
+            Message::Temperature { time_ms, celsius } => {
+                w.u8(msg_type::TEMPERATURE);
+                w.u32(time_ms);
+                w.f32(celsius);
+            }
+        }
+        let len = w.len;
+        let crc = crc16(&payload[..len]);
+        payload[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+
+        let n = cobs_encode(&payload[..len + 2], out)?;
+        *out.get_mut(n).ok_or(ProtocolError::BufferTooSmall)? = FRAME_END;
+        Ok(n + 1)
+    }
+
+    /// Decodes a single frame, without the delimiter. `frame` is
+    /// decoded in place.
+    pub fn decode(frame: &mut [u8]) -> Result<Self, ProtocolError> {
+        let len = cobs_decode(frame)?;
+        if len < 4 {
+            return Err(ProtocolError::Framing);
+        }
+        let (payload, crc) = frame[..len].split_at(len - 2);
+        if crc16(payload).to_le_bytes() != crc {
+            return Err(ProtocolError::Crc);
+        }
+        if payload[0] != PROTOCOL_VERSION {
+            return Err(ProtocolError::Version(payload[0]));
+        }
+
+        let mut r = Reader {
+            buf: &payload[2..],
+            pos: 0,
+        };
+        let msg = match payload[1] {
+            msg_type::SAMPLE => Message::Sample {
+                seq: r.u32()?,
+                time_ms: r.u32()?,
+                red: r.u32()?,
+                ir: r.u32()?,
+            },
+            msg_type::VITALS => Message::Vitals {
+                time_ms: r.u32()?,
+                heart_rate_bpm: r.f32_opt()?,
+                spo2: spo2_from(r.u8()?, r.f32()?)?,
+                quality: quality_from_u8(r.u8()?)?,
+                finger: finger_from_u8(r.u8()?)?,
+            },
+            msg_type::BEAT => Message::Beat { time_ms: r.u32()? },
+            msg_type::HRV => Message::Hrv {
+                time_ms: r.u32()?,
+                stats: HrvStats {
+                    num_intervals: r.u16()? as usize,
+                    mean_nn: r.f32()?,
+                    sdnn: r.f32()?,
+                    rmssd: r.f32()?,
+                    pnn50: r.f32()?,
+                },
+            },
+            msg_type::TEMPERATURE => Message::Temperature {
+                time_ms: r.u32()?,
+                celsius: r.f32()?,
+            },
+            t => return Err(ProtocolError::UnknownMessage(t)),
+        };
+        if r.pos != r.buf.len() {
+            return Err(ProtocolError::Length);
+        }
+        Ok(msg)
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn bytes(&mut self, b: &[u8]) {
+        self.buf[self.len..self.len + b.len()].copy_from_slice(b);
+        self.len += b.len();
+    }
+
+    fn u8(&mut self, x: u8) {
+        self.bytes(&[x]);
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes(&x.to_le_bytes());
+    }
+
+    fn f32(&mut self, x: f32) {
+        self.bytes(&x.to_le_bytes());
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        let b = self
+            .buf
+            .get(self.pos..self.pos + N)
+            .ok_or(ProtocolError::Length)?;
+        self.pos += N;
+        let mut out = [0; N];
+        out.copy_from_slice(b);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, ProtocolError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, ProtocolError> {
+        Ok(f32::from_le_bytes(self.bytes()?))
+    }
+
+    fn f32_opt(&mut self) -> Result<Option<f32>, ProtocolError> {
+        let x = self.f32()?;
+        Ok(if x.is_nan() { None } else { Some(x) })
+    }
+}
+
+fn quality_to_u8(q: SignalQuality) -> u8 {
+    match q {
+        SignalQuality::NoContact => 0,
+        SignalQuality::Motion => 1,
+        SignalQuality::Poor => 2,
+        SignalQuality::Acceptable => 3,
+        SignalQuality::Good => 4,
+    }
+}
+
+fn quality_from_u8(x: u8) -> Result<SignalQuality, ProtocolError> {
+    Ok(match x {
+        0 => SignalQuality::NoContact,
+        1 => SignalQuality::Motion,
+        2 => SignalQuality::Poor,
+        3 => SignalQuality::Acceptable,
+        4 => SignalQuality::Good,
+        _ => return Err(ProtocolError::Value),
+    })
+}
+
+fn spo2_status_to_u8(spo2: Option<Spo2>) -> u8 {
+    match spo2 {
+        None => 0,
+        Some(Spo2::Valid(_)) => 1,
+        Some(Spo2::Invalid) => 2,
+    }
+}
+
+fn spo2_from(status: u8, value: f32) -> Result<Option<Spo2>, ProtocolError> {
+    Ok(match status {
+        0 => None,
+        1 if !value.is_nan() => Some(Spo2::Valid(value)),
+        2 => Some(Spo2::Invalid),
+        _ => return Err(ProtocolError::Value),
+    })
+}
+
+fn finger_to_u8(f: FingerState) -> u8 {
+    match f {
+        FingerState::Absent => 0,
+        FingerState::Settling => 1,
+        FingerState::Present => 2,
+    }
+}
+
+fn finger_from_u8(x: u8) -> Result<FingerState, ProtocolError> {
+    Ok(match x {
+        0 => FingerState::Absent,
+        1 => FingerState::Settling,
+        2 => FingerState::Present,
+        _ => return Err(ProtocolError::Value),
+    })
+}
+
+/// Consistent Overhead Byte Stuffing, output has no 0 bytes.
+/// Returns the encoded length, the delimiter is up to the caller.
+pub fn cobs_encode(data: &[u8], out: &mut [u8]) -> Result<usize, ProtocolError> {
+    let mut code_idx = 0;
+    let mut len = 1;
+    let mut code = 1u8;
+
+    for b in data.iter() {
+        if *b == 0 {
+            *out.get_mut(code_idx).ok_or(ProtocolError::BufferTooSmall)? = code;
+            code_idx = len;
+            len += 1;
+            code = 1;
+        } else {
+            *out.get_mut(len).ok_or(ProtocolError::BufferTooSmall)? = *b;
+            len += 1;
+            code += 1;
+            if code == 0xff {
+                *out.get_mut(code_idx).ok_or(ProtocolError::BufferTooSmall)? = code;
+                code_idx = len;
+                len += 1;
+                code = 1;
+            }
+        }
+    }
+    *out.get_mut(code_idx).ok_or(ProtocolError::BufferTooSmall)? = code;
+    Ok(len)
+}
+
+/// Decodes COBS in place, returns the decoded length
+pub fn cobs_decode(buf: &mut [u8]) -> Result<usize, ProtocolError> {
+    let mut r = 0;
+    let mut w = 0;
+    while r < buf.len() {
+        let code = buf[r];
+        if code == 0 || r + code as usize > buf.len() {
+            return Err(ProtocolError::Framing);
+        }
+        r += 1;
+        for _ in 1..code {
+            buf[w] = buf[r];
+            w += 1;
+            r += 1;
+        }
+        if code != 0xff && r < buf.len() {
+            buf[w] = 0;
+            w += 1;
+        }
+    }
+    Ok(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cobs_round_trip(data: &[u8]) -> Vec<u8> {
+        let mut enc = vec![0; data.len() + data.len() / 254 + 2];
+        let n = cobs_encode(data, &mut enc).unwrap();
+        enc.truncate(n);
+        assert!(!enc.contains(&0), "{:?}", enc);
+
+        let mut dec = enc.clone();
+        let n = cobs_decode(&mut dec).unwrap();
+        assert_eq!(&dec[..n], data);
+        enc
+    }
+
+    #[test]
+    fn test_cobs() {
+        assert_eq!(cobs_round_trip(&[]), vec![1]);
+        assert_eq!(cobs_round_trip(&[0]), vec![1, 1]);
+        assert_eq!(
+            cobs_round_trip(&[0x11, 0x22, 0, 0x33]),
+            vec![3, 0x11, 0x22, 2, 0x33]
+        );
+        assert_eq!(cobs_round_trip(&[0x11, 0, 0, 0]), vec![2, 0x11, 1, 1, 1]);
+
+        let long: Vec<u8> = (1..=255).collect();
+        let enc = cobs_round_trip(&long);
+        assert_eq!(enc[0], 0xff);
+        assert_eq!(enc.len(), 257);
+        let long: Vec<u8> = (0..600).map(|i| (i % 7) as u8).collect();
+        cobs_round_trip(&long);
+    }
+
+    #[test]
+    fn test_cobs_errors() {
+        let mut buf = [3, 1];
+        assert_eq!(cobs_decode(&mut buf), Err(ProtocolError::Framing));
+        let mut buf = [2, 1, 0];
+        assert_eq!(cobs_decode(&mut buf), Err(ProtocolError::Framing));
+        let mut out = [0; 3];
+        assert_eq!(
+            cobs_encode(&[1, 2, 3], &mut out),
+            Err(ProtocolError::BufferTooSmall)
+        );
+    }
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Sample {
+                seq: 1,
+                time_ms: 0,
+                red: 0,
+                ir: 262_143,
+            },
+            Message::Sample {
+                seq: u32::MAX,
+                time_ms: 0x0100_0000,
+                red: 80_000,
+                ir: 100_000,
+            },
+            Message::Vitals {
+                time_ms: 12_345,
+                heart_rate_bpm: Some(72.5),
+                spo2: Some(Spo2::Valid(97.25)),
+                quality: SignalQuality::Good,
+                finger: FingerState::Present,
+            },
+            Message::Vitals {
+                time_ms: 0,
+                heart_rate_bpm: None,
+                spo2: None,
+                quality: SignalQuality::NoContact,
+                finger: FingerState::Absent,
+            },
+            Message::Vitals {
+                time_ms: 30_000,
+                heart_rate_bpm: Some(64.0),
+                spo2: Some(Spo2::Invalid),
+                quality: SignalQuality::Acceptable,
+                finger: FingerState::Present,
+            },
+            Message::Beat { time_ms: 999 },
+            Message::Hrv {
+                time_ms: 60_000,
+                stats: HrvStats {
+                    num_intervals: 64,
+                    mean_nn: 812.5,
+                    sdnn: 41.25,
+                    rmssd: 30.0,
+                    pnn50: 12.5,
+                },
+            },
+            Message::Temperature {
+                time_ms: 5_000,
+                celsius: 31.5625,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for msg in messages() {
+            let mut frame = [0xaa; MAX_FRAME_LEN];
+            let n = msg.encode(&mut frame).unwrap();
+            assert_eq!(frame[n - 1], FRAME_END);
+            assert!(!frame[..n - 1].contains(&FRAME_END));
+            assert_eq!(Message::decode(&mut frame[..n - 1]), Ok(msg));
+        }
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let msg = messages()[2];
+        let mut frame = [0; MAX_FRAME_LEN];
+        let n = msg.encode(&mut frame).unwrap();
+
+        // every single bit error is caught, by COBS or the CRC
+        let mut crc_errors = 0;
+        for i in 0..(n - 1) {
+            for bit in 0..8 {
+                let mut bad = frame;
+                bad[i] ^= 1 << bit;
+                match Message::decode(&mut bad[..n - 1]) {
+                    Err(ProtocolError::Crc) => crc_errors += 1,
+                    Err(_) => (),
+                    Ok(m) => panic!("{} {} {:?}", i, bit, m),
+                }
+            }
+        }
+        assert!(crc_errors > 0);
+
+        assert_eq!(
+            Message::decode(&mut frame[..2]),
+            Err(ProtocolError::Framing)
+        );
+
+        let mut small = [0; 8];
+        assert_eq!(msg.encode(&mut small), Err(ProtocolError::BufferTooSmall));
+    }
+
+    #[test]
+    fn test_spo2_status() {
+        let vitals = |spo2| Message::Vitals {
+            time_ms: 0,
+            heart_rate_bpm: None,
+            spo2,
+            quality: SignalQuality::Poor,
+            finger: FingerState::Present,
+        };
+        let encode = |msg: Message| {
+            let mut frame = [0; MAX_FRAME_LEN];
+            let n = msg.encode(&mut frame).unwrap();
+            frame[..n - 1].to_vec()
+        };
+        // out of range isn't sent as a missing reading
+        assert_ne!(encode(vitals(None)), encode(vitals(Some(Spo2::Invalid))));
+
+        let frame = |status: u8, value: f32| {
+            let mut p = vec![PROTOCOL_VERSION, msg_type::VITALS, 0, 0, 0, 0];
+            p.extend_from_slice(&f32::NAN.to_le_bytes());
+            p.push(status);
+            p.extend_from_slice(&value.to_le_bytes());
+            p.extend_from_slice(&[2, 2]);
+            p.extend_from_slice(&crc16(&p).to_le_bytes());
+            let mut out = vec![0; MAX_FRAME_LEN];
+            let n = cobs_encode(&p, &mut out).unwrap();
+            out.truncate(n);
+            out
+        };
+        assert_eq!(
+            Message::decode(&mut frame(1, 95.5)),
+            Ok(vitals(Some(Spo2::Valid(95.5))))
+        );
+        assert_eq!(
+            Message::decode(&mut frame(1, f32::NAN)),
+            Err(ProtocolError::Value)
+        );
+        assert_eq!(
+            Message::decode(&mut frame(3, f32::NAN)),
+            Err(ProtocolError::Value)
+        );
+    }
+
+    #[test]
+    fn test_version_and_type() {
+        let frame = |payload: &[u8]| {
+            let mut p = payload.to_vec();
+            p.extend_from_slice(&crc16(payload).to_le_bytes());
+            let mut out = vec![0; MAX_FRAME_LEN];
+            let n = cobs_encode(&p, &mut out).unwrap();
+            out.truncate(n);
+            out
+        };
+
+        let mut f = frame(&[PROTOCOL_VERSION + 1, msg_type::BEAT, 0, 0, 0, 0]);
+        assert_eq!(
+            Message::decode(&mut f),
+            Err(ProtocolError::Version(PROTOCOL_VERSION + 1))
+        );
+        let mut f = frame(&[PROTOCOL_VERSION, 42]);
+        assert_eq!(
+            Message::decode(&mut f),
+            Err(ProtocolError::UnknownMessage(42))
+        );
+        let mut f = frame(&[PROTOCOL_VERSION, msg_type::BEAT, 0, 0, 0]);
+        assert_eq!(Message::decode(&mut f), Err(ProtocolError::Length));
+        let mut f = frame(&[PROTOCOL_VERSION, msg_type::BEAT, 0, 0, 0, 0, 0]);
+        assert_eq!(Message::decode(&mut f), Err(ProtocolError::Length));
+    }
+}
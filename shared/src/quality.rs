@@ -0,0 +1,300 @@
+//! Signal quality index of a PPG channel window
+
+/// Overall signal quality, ordered from worst to best,
+/// so it can be compared against a minimum.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SignalQuality {
+    /// Nothing on the sensor, ambient light only
+    NoContact,
+    /// Large, irregular swings or ADC saturation
+    Motion,
+    /// Something is there, but beats are hard to make out
+    Poor,
+    Acceptable,
+    Good,
+}
+
+/// Thresholds that map quality metrics onto [`SignalQuality`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QualityLimits {
+    /// Raw ADC counts at full scale
+    pub adc_max: f32,
+    /// Raw DC level below which there is no finger on the sensor
+    pub min_contact_dc: f32,
+    /// Perfusion index range (%), outside of it the signal is not usable
+    pub min_perfusion: f32,
+    pub max_perfusion: f32,
+    /// Perfusion index above which we assume motion artifacts
+    pub motion_perfusion: f32,
+    /// Fraction of clipped samples that indicates saturation
+    pub max_clipped: f32,
+    /// Beat regularity and template correlation needed
+    /// for Acceptable and Good quality respectively
+    pub acceptable_score: f32,
+    pub good_score: f32,
+}
+
+impl Default for QualityLimits {
+    fn default() -> Self {
+        QualityLimits {
+            // MAX30102 ADC is 18 bit at the longest pulse width
+            adc_max: 262_143.0,
+            min_contact_dc: 10_000.0,
+            min_perfusion: 0.02,
+            max_perfusion: 10.0,
+            motion_perfusion: 20.0,
+            max_clipped: 0.02,
+            acceptable_score: 0.5,
+            good_score: 0.85,
+        }
+    }
+}
+
+/// Quality metrics of a single channel window
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SignalQualityIndex {
+    /// Raw sample mean
+    pub dc: f32,
+    /// AC peak-to-peak over DC, %
+    pub perfusion: f32,
+    /// Fraction of samples at the ADC limits
+    pub clipped: f32,
+    /// 1 - coefficient of variation of beat intervals, 0..1
+    pub regularity: f32,
+    /// Mean correlation of individual beats with the average beat shape
+    pub template_corr: f32,
+    pub quality: SignalQuality,
+}
+
+/// Longest beat template, 2.56s at 25Hz covers the slowest heart rates
+const MAX_TEMPLATE_LEN: usize = 64;
+
+impl SignalQualityIndex {
+    pub fn none() -> Self {
+        SignalQualityIndex {
+            dc: 0.0,
+            perfusion: 0.0,
+            clipped: 0.0,
+            regularity: 0.0,
+            template_corr: 0.0,
+            quality: SignalQuality::NoContact,
+        }
+    }
+
+    /// `raw`: sensor values, `ac`: filtered signal over the same window,
+    /// `beats`: positions of detected heartbeats in the window, in order.
+    pub fn assess<const N: usize>(
+        raw: &[f32; N],
+        ac: &[f32; N],
+        beats: &[usize],
+        limits: &QualityLimits,
+    ) -> Self {
+        let mut dc = 0.0;
+        let mut clipped = 0;
+        for x in raw.iter() {
+            dc += x;
+            if *x >= limits.adc_max || *x <= 0.0 {
+                clipped += 1;
+            }
+        }
+        let dc = dc / N as f32;
+        let clipped = clipped as f32 / N as f32;
+
+        let (ac_min, ac_max) = ac
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), x| (lo.min(*x), hi.max(*x)));
+        let perfusion = if dc > 0.0 {
+            100.0 * (ac_max - ac_min) / dc
+        } else {
+            0.0
+        };
+
+        let regularity = regularity(beats);
+        let template_corr = template_corr(ac, beats);
+
+        let quality = if dc < limits.min_contact_dc {
+            SignalQuality::NoContact
+        } else if clipped > limits.max_clipped || perfusion > limits.motion_perfusion {
+            SignalQuality::Motion
+        } else if perfusion < limits.min_perfusion
+            || perfusion > limits.max_perfusion
+            || regularity < limits.acceptable_score
+            || template_corr < limits.acceptable_score
+        {
+            SignalQuality::Poor
+        } else if regularity < limits.good_score || template_corr < limits.good_score {
+            SignalQuality::Acceptable
+        } else {
+            SignalQuality::Good
+        };
+
+        SignalQualityIndex {
+            dc,
+            perfusion,
+            clipped,
+            regularity,
+            template_corr,
+            quality,
+        }
+    }
+}
+
+/// Beat intervals, iterator over positions differences
+fn intervals(beats: &[usize]) -> impl Iterator<Item = f32> + '_ {
+    beats
+        .iter()
+        .zip(beats.iter().skip(1))
+        .map(|(b0, b1)| (b1 - b0) as f32)
+}
+
+fn regularity(beats: &[usize]) -> f32 {
+    if beats.len() < 3 {
+        return 0.0;
+    }
+
+    let n = (beats.len() - 1) as f32;
+    let mean = intervals(beats).sum::<f32>() / n;
+    let var = intervals(beats)
+        .map(|i| (i - mean) * (i - mean))
+        .sum::<f32>()
+        / n;
+
+    (1.0 - libm::sqrtf(var) / mean).max(0.0)
+}
+
+/// Beats are cut into segments as long as the shortest beat interval,
+/// averaged into a template, then each segment is compared to it.
+fn template_corr<const N: usize>(ac: &[f32; N], beats: &[usize]) -> f32 {
+    let len = intervals(beats)
+        .fold(MAX_TEMPLATE_LEN, |l, i| l.min(i as usize))
+        .min(N);
+
+    let segments = || {
+        beats
+            .iter()
+            .filter(move |b| **b + len <= N)
+            .map(move |b| &ac[*b..(*b + len)])
+    };
+
+    let num_segments = segments().count();
+    if len < 2 || num_segments < 2 {
+        return 0.0;
+    }
+
+    let mut template = [0.0; MAX_TEMPLATE_LEN];
+    for seg in segments() {
+        for (t, x) in template.iter_mut().zip(seg.iter()) {
+            *t += x / num_segments as f32;
+        }
+    }
+    let template = &template[..len];
+
+    segments()
+        .map(|seg| correlation(seg, template))
+        .sum::<f32>()
+        / num_segments as f32
+}
+
+/// Pearson correlation coefficient
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let mean_a = a.iter().sum::<f32>() / n;
+    let mean_b = b.iter().sum::<f32>() / n;
+
+    let mut cov = 0.0;
+    let mut var_a = 0.0;
+    let mut var_b = 0.0;
+    for (x, y) in a.iter().zip(b.iter()) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+    }
+
+    let d = libm::sqrtf(var_a * var_b);
+    if d > 0.0 {
+        cov / d
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::PI;
+
+    const N: usize = 100;
+
+    /// Sine "heartbeats" with a given period, on top of a DC level
+    fn window(dc: f32, amplitude: f32, period: usize) -> ([f32; N], [f32; N], Vec<usize>) {
+        let mut raw = [0.0; N];
+        let mut ac = [0.0; N];
+        for i in 0..N {
+            ac[i] = amplitude * libm::sinf(2.0 * PI * i as f32 / period as f32);
+            raw[i] = dc + ac[i];
+        }
+        let beats = (0..N).step_by(period).collect();
+        (raw, ac, beats)
+    }
+
+    #[test]
+    fn test_good_signal() {
+        let (raw, ac, beats) = window(100_000.0, 500.0, 20);
+        let sqi = SignalQualityIndex::assess(&raw, &ac, &beats, &QualityLimits::default());
+        assert_eq!(sqi.quality, SignalQuality::Good);
+        assert!((sqi.perfusion - 1.0).abs() < 0.01, "{:?}", sqi);
+        assert!((sqi.regularity - 1.0).abs() < 1e-5, "{:?}", sqi);
+        assert!((sqi.template_corr - 1.0).abs() < 1e-5, "{:?}", sqi);
+        assert_eq!(sqi.clipped, 0.0);
+    }
+
+    #[test]
+    fn test_no_contact() {
+        let (raw, ac, beats) = window(500.0, 50.0, 20);
+        let sqi = SignalQualityIndex::assess(&raw, &ac, &beats, &QualityLimits::default());
+        assert_eq!(sqi.quality, SignalQuality::NoContact);
+    }
+
+    #[test]
+    fn test_clipped() {
+        let (mut raw, ac, beats) = window(100_000.0, 500.0, 20);
+        for x in raw.iter_mut().take(10) {
+            *x = 262_143.0;
+        }
+        let sqi = SignalQualityIndex::assess(&raw, &ac, &beats, &QualityLimits::default());
+        assert_eq!(sqi.clipped, 0.1);
+        assert_eq!(sqi.quality, SignalQuality::Motion);
+    }
+
+    #[test]
+    fn test_motion() {
+        let (raw, ac, beats) = window(100_000.0, 15_000.0, 20);
+        let sqi = SignalQualityIndex::assess(&raw, &ac, &beats, &QualityLimits::default());
+        assert_eq!(sqi.quality, SignalQuality::Motion);
+    }
+
+    #[test]
+    fn test_irregular() {
+        let (raw, ac, _) = window(100_000.0, 500.0, 20);
+        let beats = [0, 5, 30, 35, 70, 72, 99];
+        let sqi = SignalQualityIndex::assess(&raw, &ac, &beats, &QualityLimits::default());
+        assert!(sqi.regularity < 0.5, "{:?}", sqi);
+        assert_eq!(sqi.quality, SignalQuality::Poor);
+
+        let sqi = SignalQualityIndex::assess(&raw, &ac, &[], &QualityLimits::default());
+        assert_eq!(sqi.quality, SignalQuality::Poor);
+    }
+
+    #[test]
+    fn test_correlation() {
+        assert!((correlation(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]) - 1.0).abs() < 1e-6);
+        assert!((correlation(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(correlation(&[1.0, 1.0, 1.0], &[3.0, 2.0, 1.0]), 0.0);
+    }
+
+    #[test]
+    fn test_ordering() {
+        assert!(SignalQuality::NoContact < SignalQuality::Poor);
+        assert!(SignalQuality::Good > SignalQuality::Acceptable);
+    }
+}
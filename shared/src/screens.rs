@@ -0,0 +1,1431 @@
+//! Display screens
+//!
+//! The UI is a few full screen pages picked from a tab bar along the
+//! bottom, with the alarm banner just above it. Screens draw onto any
+//! `Rgb565` [`DrawTarget`], the LCD on the board or a framebuffer on a
+//! PC, laid out for [`SCREEN_SIZE`], the panel in landscape.
+//!
+//! Every pixel drawn goes over the LCD bus on its own, so screens keep
+//! track of what they have drawn and only redraw what changed. The live
+//! waveform is the exception, it's redrawn every time.
+
+use core::{convert::TryFrom, fmt::Write};
+
+use embedded_graphics::{
+    mono_font::{
+        ascii::{FONT_10X20, FONT_6X12},
+        MonoFont, MonoTextStyle, MonoTextStyleBuilder,
+    },
+    pixelcolor::Rgb565,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use heapless::String;
+
+use crate::{
+    alarms::{Alarm, AlarmPriority},
+    calibration::Spo2,
+    hrv::HrvStats,
+    model::{Max3012SampleData, UIModel, HRV_NUM_INTERVALS, MAX30102_NUM_SAMPLES},
+    presence::FingerState,
+    quality::SignalQuality,
+    settings::{AlarmLimits, Theme},
+    touch::TouchPoint,
+    trends::{Trends, TREND_NUM_POINTS},
+};
+
+/// Landscape panel
+pub const SCREEN_SIZE: Size = Size::new(320, 240);
+
+const TAB_BAR_HEIGHT: u32 = 28;
+const BANNER_HEIGHT: u32 = 16;
+
+/// What screens get to draw on, the top of the screen
+pub const CONTENT_SIZE: Size = Size::new(
+    SCREEN_SIZE.width,
+    SCREEN_SIZE.height - TAB_BAR_HEIGHT - BANNER_HEIGHT,
+);
+
+const BANNER_AREA: Rectangle = Rectangle::new(
+    Point::new(0, CONTENT_SIZE.height as i32),
+    Size::new(SCREEN_SIZE.width, BANNER_HEIGHT),
+);
+
+const TAB_BAR_AREA: Rectangle = Rectangle::new(
+    Point::new(0, (CONTENT_SIZE.height + BANNER_HEIGHT) as i32),
+    Size::new(SCREEN_SIZE.width, TAB_BAR_HEIGHT),
+);
+
+const TAB_WIDTH: u32 = SCREEN_SIZE.width / ScreenId::ALL.len() as u32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub background: Rgb565,
+    pub foreground: Rgb565,
+    pub heart_rate: Rgb565,
+    pub spo2: Rgb565,
+    /// Axes, limits and other things in the background
+    pub dim: Rgb565,
+}
+
+impl From<Theme> for Palette {
+    fn from(theme: Theme) -> Self {
+        match theme {
+            Theme::Dark => Palette {
+                background: Rgb565::BLACK,
+                foreground: Rgb565::YELLOW,
+                heart_rate: Rgb565::GREEN,
+                spo2: Rgb565::CYAN,
+                dim: Rgb565::new(12, 24, 12),
+            },
+            Theme::Light => Palette {
+                background: Rgb565::WHITE,
+                foreground: Rgb565::BLACK,
+                heart_rate: Rgb565::new(0, 36, 0),
+                spo2: Rgb565::new(0, 28, 18),
+                dim: Rgb565::new(20, 40, 20),
+            },
+        }
+    }
+}
+
+/// What the screens show, gathered for every draw
+pub struct Frame<'a> {
+    pub model: &'a UIModel<MAX30102_NUM_SAMPLES>,
+    pub trends: &'a Trends<TREND_NUM_POINTS>,
+    /// Highest priority alarm that is on
+    pub alarm: Option<Alarm>,
+    pub alarm_limits: AlarmLimits,
+    pub pulse_tone: bool,
+    /// Screen rotation, CCW quarter turns
+    pub rotation: u8,
+}
+
+/// Asked for on screen, up to the firmware to carry out
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Acknowledge,
+    PulseTone(bool),
+    AlarmLimits(AlarmLimits),
+    /// CCW quarter turns
+    Rotate(u8),
+    CalibrateTouch,
+    Save,
+}
+
+/// A page of the UI
+pub trait Screen {
+    /// Draws what changed since the last call, within [`CONTENT_SIZE`]
+    fn draw<D>(&mut self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>;
+
+    /// Display was cleared, the next `draw` starts over
+    fn reset(&mut self);
+
+    /// Tap within [`CONTENT_SIZE`]
+    fn on_tap(&mut self, _point: Point, _frame: &Frame<'_>) -> Option<Action> {
+        None
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ScreenId {
+    Waveform,
+    Numerics,
+    Trends,
+    Hrv,
+    Settings,
+}
+
+impl ScreenId {
+    /// Tab bar order
+    pub const ALL: [ScreenId; 5] = [
+        ScreenId::Waveform,
+        ScreenId::Numerics,
+        ScreenId::Trends,
+        ScreenId::Hrv,
+        ScreenId::Settings,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    /// Tab label
+    pub fn name(self) -> &'static str {
+        match self {
+            ScreenId::Waveform => "WAVE",
+            ScreenId::Numerics => "BIG",
+            ScreenId::Trends => "TREND",
+            ScreenId::Hrv => "HRV",
+            ScreenId::Settings => "SETUP",
+        }
+    }
+
+    /// By tab label, any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|id| id.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Screens, the tab bar and the alarm banner
+pub struct Ui {
+    palette: Palette,
+    current: ScreenId,
+    waveform: WaveformScreen,
+    numerics: NumericsScreen,
+    trends: TrendScreen,
+    hrv: HrvScreen,
+    settings: SettingsScreen,
+    /// Clear the display and draw everything on the next `draw`
+    stale: bool,
+    /// Alarm the banner shows
+    banner: Option<Alarm>,
+    /// Where the current press started
+    press: Option<Point>,
+}
+
+impl Ui {
+    pub fn new(theme: Theme) -> Self {
+        let palette = Palette::from(theme);
+        Ui {
+            palette,
+            current: ScreenId::Waveform,
+            waveform: WaveformScreen::new(palette),
+            numerics: NumericsScreen::new(palette),
+            trends: TrendScreen::new(palette),
+            hrv: HrvScreen::new(palette),
+            settings: SettingsScreen::new(palette),
+            stale: true,
+            banner: None,
+            press: None,
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn screen(&self) -> ScreenId {
+        self.current
+    }
+
+    pub fn show(&mut self, id: ScreenId) {
+        if id != self.current {
+            self.current = id;
+            self.stale = true;
+        }
+    }
+
+    pub fn next(&mut self) {
+        self.show(self.current.next());
+    }
+
+    /// Something else was drawn over it, or the rotation changed
+    pub fn redraw(&mut self) {
+        self.stale = true;
+    }
+
+    /// Latest touch position, `None` when not pressed. A tap is a press
+    /// and release, it counts where the press started.
+    pub fn touch(&mut self, point: Option<TouchPoint>, frame: &Frame<'_>) -> Option<Action> {
+        match (point, self.press) {
+            (Some(p), None) => {
+                self.press = Some(Point::new(p.x, p.y));
+                None
+            }
+            (None, Some(p)) => {
+                self.press = None;
+                self.tap(p, frame)
+            }
+            _ => None,
+        }
+    }
+
+    fn tap(&mut self, point: Point, frame: &Frame<'_>) -> Option<Action> {
+        if TAB_BAR_AREA.contains(point) {
+            let tab = point.x as usize / TAB_WIDTH as usize;
+            self.show(ScreenId::ALL[tab.min(ScreenId::ALL.len() - 1)]);
+            None
+        } else if BANNER_AREA.contains(point) {
+            frame.alarm.map(|_| Action::Acknowledge)
+        } else {
+            match self.current {
+                ScreenId::Waveform => self.waveform.on_tap(point, frame),
+                ScreenId::Numerics => self.numerics.on_tap(point, frame),
+                ScreenId::Trends => self.trends.on_tap(point, frame),
+                ScreenId::Hrv => self.hrv.on_tap(point, frame),
+                ScreenId::Settings => self.settings.on_tap(point, frame),
+            }
+        }
+    }
+
+    pub fn draw<D>(&mut self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        if self.stale {
+            display.clear(self.palette.background)?;
+            self.draw_tab_bar(display)?;
+            match self.current {
+                ScreenId::Waveform => self.waveform.reset(),
+                ScreenId::Numerics => self.numerics.reset(),
+                ScreenId::Trends => self.trends.reset(),
+                ScreenId::Hrv => self.hrv.reset(),
+                ScreenId::Settings => self.settings.reset(),
+            }
+            self.banner = None;
+            self.stale = false;
+        }
+
+        match self.current {
+            ScreenId::Waveform => self.waveform.draw(frame, display)?,
+            ScreenId::Numerics => self.numerics.draw(frame, display)?,
+            ScreenId::Trends => self.trends.draw(frame, display)?,
+            ScreenId::Hrv => self.hrv.draw(frame, display)?,
+            ScreenId::Settings => self.settings.draw(frame, display)?,
+        }
+
+        if frame.alarm != self.banner {
+            self.banner = frame.alarm;
+            match frame.alarm {
+                Some(alarm) => draw_alarm_banner(alarm, display)?,
+                None => display.fill_solid(&BANNER_AREA, self.palette.background)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Current screen's tab highlighted
+    fn draw_tab_bar<D>(&mut self, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        for (i, id) in ScreenId::ALL.iter().enumerate() {
+            let area = Rectangle::new(
+                TAB_BAR_AREA.top_left + Point::new((i as u32 * TAB_WIDTH) as i32, 0),
+                Size::new(TAB_WIDTH, TAB_BAR_HEIGHT),
+            );
+            let selected = *id == self.current;
+            draw_button(id.name(), &area, selected, &self.palette, display)?;
+        }
+        Ok(())
+    }
+}
+
+/// Colored by priority, the usual red, yellow and cyan
+fn draw_alarm_banner<D>(alarm: Alarm, display: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let color = match alarm.priority() {
+        AlarmPriority::High => Rgb565::RED,
+        AlarmPriority::Medium => Rgb565::YELLOW,
+        AlarmPriority::Low => Rgb565::CYAN,
+    };
+    display.fill_solid(&BANNER_AREA, color)?;
+
+    let mut text: String<40> = String::new();
+    let _ = write!(text, "{}", alarm.kind);
+    if alarm.latched {
+        let _ = write!(text, " LATCHED");
+    }
+    if alarm.silenced {
+        let _ = write!(text, " (SILENCED)");
+    }
+    let style = text_style(&FONT_6X12, Rgb565::BLACK, color);
+    Text::with_baseline(
+        &text,
+        BANNER_AREA.top_left + Point::new(10, BANNER_HEIGHT as i32 / 2),
+        style,
+        Baseline::Middle,
+    )
+    .draw(display)?;
+    Ok(())
+}
+
+fn text_style(
+    font: &'static MonoFont<'static>,
+    color: Rgb565,
+    background: Rgb565,
+) -> MonoTextStyle<'static, Rgb565> {
+    MonoTextStyleBuilder::new()
+        .font(font)
+        .text_color(color)
+        .background_color(background)
+        .build()
+}
+
+/// Outlined, or filled when `selected`, label in the middle
+fn draw_button<D>(
+    label: &str,
+    area: &Rectangle,
+    selected: bool,
+    palette: &Palette,
+    display: &mut D,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let (fill, text) = if selected {
+        (palette.foreground, palette.background)
+    } else {
+        (palette.background, palette.foreground)
+    };
+    area.into_styled(
+        PrimitiveStyleBuilder::new()
+            .fill_color(fill)
+            .stroke_color(palette.foreground)
+            .stroke_width(1)
+            .build(),
+    )
+    .draw(display)?;
+    Text::with_text_style(
+        label,
+        area.center(),
+        text_style(&FONT_10X20, text, fill),
+        TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build(),
+    )
+    .draw(display)?;
+    Ok(())
+}
+
+fn quality_text(model: &UIModel<MAX30102_NUM_SAMPLES>) -> &'static str {
+    match (model.finger_state(), model.quality()) {
+        (FingerState::Absent, _) | (_, SignalQuality::NoContact) => "NO FINGER",
+        (FingerState::Settling, _) => "SETTLING",
+        (_, SignalQuality::Motion) => "MOTION",
+        (_, SignalQuality::Poor) => "POOR",
+        (_, SignalQuality::Acceptable) => "OK",
+        (_, SignalQuality::Good) => "GOOD",
+    }
+}
+
+/// `value` scaled from `range` onto `area`'s height, bottom up and clamped
+fn plot_y(value: f32, range: (f32, f32), area: &Rectangle) -> i32 {
+    let (low, high) = range;
+    let h = (area.size.height - 1) as f32;
+    let y = ((value - low) / (high - low) * h).clamp(0.0, h);
+    area.top_left.y + (h - y) as i32
+}
+
+/// Text that is only redrawn when it changes, the old one is erased
+/// with the style's background first
+struct Label<const N: usize> {
+    position: Point,
+    text: String<N>,
+    /// Where the text on screen is, `None` if there is none
+    area: Option<Rectangle>,
+}
+
+impl<const N: usize> Label<N> {
+    fn new(position: Point) -> Self {
+        Label {
+            position,
+            text: String::new(),
+            area: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.text.clear();
+        self.area = None;
+    }
+
+    fn draw<D>(
+        &mut self,
+        text: &str,
+        style: MonoTextStyle<'_, Rgb565>,
+        display: &mut D,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        if self.area.is_some() && self.text.as_str() == text {
+            return Ok(());
+        }
+        if let (Some(area), Some(background)) = (self.area, style.background_color) {
+            display.fill_solid(&area, background)?;
+        }
+        let t = Text::new(text, self.position, style);
+        self.area = Some(t.bounding_box());
+        self.text.clear();
+        // too long to remember, redrawn every time
+        let _ = self.text.push_str(text);
+        t.draw(display)?;
+        Ok(())
+    }
+}
+
+const WAVEFORM_TEXT_HEIGHT: u32 = 32;
+const GRAPH_AREA: Rectangle = Rectangle::new(
+    Point::new(0, WAVEFORM_TEXT_HEIGHT as i32),
+    Size::new(
+        CONTENT_SIZE.width,
+        CONTENT_SIZE.height - WAVEFORM_TEXT_HEIGHT,
+    ),
+);
+
+/// Readings in a couple of lines of text over both live waveforms,
+/// heartbeats marked
+pub struct WaveformScreen {
+    palette: Palette,
+    heart_rate: Label<16>,
+    spo2: Label<16>,
+    quality: Label<16>,
+    lost: Label<16>,
+    hrv: Label<48>,
+    temperature: Label<16>,
+}
+
+impl WaveformScreen {
+    pub fn new(palette: Palette) -> Self {
+        WaveformScreen {
+            palette,
+            heart_rate: Label::new(Point::new(10, 10)),
+            spo2: Label::new(Point::new(100, 10)),
+            quality: Label::new(Point::new(200, 10)),
+            lost: Label::new(Point::new(260, 10)),
+            hrv: Label::new(Point::new(10, 24)),
+            temperature: Label::new(Point::new(260, 24)),
+        }
+    }
+
+    fn draw_samples<D>(
+        &self,
+        samples: &Max3012SampleData<MAX30102_NUM_SAMPLES>,
+        color: Rgb565,
+        display: &mut D,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        // flat, nothing to scale
+        if samples.ac_max <= samples.ac_min {
+            return Ok(());
+        }
+        let range = (samples.ac_min, samples.ac_max);
+        let line_style = PrimitiveStyle::with_stroke(color, 1);
+
+        let sx = |i: usize| (i * GRAPH_AREA.size.width as usize / MAX30102_NUM_SAMPLES) as i32;
+        let sy = |s: f32| plot_y(s, range, &GRAPH_AREA);
+
+        let mut p0_opt: Option<Point> = None;
+        for (i, acs) in samples.ac.iter().enumerate() {
+            let p = Point::new(sx(i), sy(*acs));
+            if let Some(p0) = p0_opt {
+                Line::new(p0, p).into_styled(line_style).draw(display)?;
+            }
+            p0_opt = Some(p);
+        }
+
+        let peak_style = PrimitiveStyleBuilder::new()
+            .stroke_color(color)
+            .fill_color(self.palette.foreground)
+            .stroke_width(1)
+            .build();
+
+        let hb_cir =
+            |i, v| Circle::with_center(Point::new(sx(i), sy(v)), 5).into_styled(peak_style);
+
+        for hb in &samples.heartbeats {
+            hb_cir(samples.window_idx(hb.high_idx), hb.high_value).draw(display)?;
+            hb_cir(samples.window_idx(hb.low_idx), hb.low_value).draw(display)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Screen for WaveformScreen {
+    fn draw<D>(&mut self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let model = frame.model;
+        let style = text_style(&FONT_6X12, self.palette.foreground, self.palette.background);
+
+        let mut sbuf: String<48> = String::new();
+        let _ = match model.heart_rate_bpm() {
+            Some(hr) => write!(sbuf, "HR {:>5.1}", hr),
+            None => write!(sbuf, "HR   ---"),
+        };
+        self.heart_rate.draw(&sbuf, style, display)?;
+
+        sbuf.clear();
+        let _ = match model.spo2() {
+            Some(Spo2::Valid(spo2)) => write!(sbuf, "SPO2 {:>5.1}", spo2),
+            // off the calibration curve, not a number to go by
+            Some(Spo2::Invalid) => write!(sbuf, "SPO2   ???"),
+            None => write!(sbuf, "SPO2   ---"),
+        };
+        self.spo2.draw(&sbuf, style, display)?;
+
+        self.quality.draw(quality_text(model), style, display)?;
+
+        sbuf.clear();
+        if model.sensor_overflow > 0 {
+            let _ = write!(sbuf, "LOST{:>4}", model.sensor_overflow.min(9999));
+        }
+        self.lost.draw(&sbuf, style, display)?;
+
+        sbuf.clear();
+        let _ = match model.hrv_stats() {
+            Some(hrv) => write!(
+                sbuf,
+                "NN {:>4.0} SDNN {:>3.0} RMSSD {:>3.0} pNN50 {:>3.0}%",
+                hrv.mean_nn, hrv.sdnn, hrv.rmssd, hrv.pnn50
+            ),
+            None => write!(sbuf, "HRV --"),
+        };
+        self.hrv.draw(&sbuf, style, display)?;
+
+        sbuf.clear();
+        if let Some(t) = model.temperature_c() {
+            let _ = write!(sbuf, "{:>6.1}C", t);
+        }
+        self.temperature.draw(&sbuf, style, display)?;
+
+        display.fill_solid(&GRAPH_AREA, self.palette.background)?;
+        self.draw_samples(&model.r, Rgb565::RED, display)?;
+        self.draw_samples(&model.ir, Rgb565::BLUE, display)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.heart_rate.reset();
+        self.spo2.reset();
+        self.quality.reset();
+        self.lost.reset();
+        self.hrv.reset();
+        self.temperature.reset();
+    }
+}
+
+const DIGITS: usize = 3;
+const DIGIT_SIZE: Size = Size::new(40, 88);
+const DIGIT_GAP: u32 = 10;
+const SEGMENT_WIDTH: u32 = 8;
+/// Top left of the digits, within each half of the screen
+const DIGITS_OFFSET: Point = Point::new(
+    (SCREEN_SIZE.width as i32 / 2
+        - (DIGITS as u32 * (DIGIT_SIZE.width + DIGIT_GAP) - DIGIT_GAP) as i32)
+        / 2,
+    40,
+);
+
+/// Seven segment digit, segments a to g in bits 0 to 6. Digits, `-`,
+/// `?` and anything else as blank.
+fn segments(c: char) -> u8 {
+    match c {
+        '0' => 0x3f,
+        '1' => 0x06,
+        '2' => 0x5b,
+        '3' => 0x4f,
+        '4' => 0x66,
+        '5' => 0x6d,
+        '6' => 0x7d,
+        '7' => 0x07,
+        '8' => 0x7f,
+        '9' => 0x6f,
+        '-' => 0x40,
+        '?' => 0x53,
+        _ => 0,
+    }
+}
+
+/// Digit built out of rectangles, big enough to read across the room
+fn draw_digit<D>(
+    c: char,
+    top_left: Point,
+    color: Rgb565,
+    background: Rgb565,
+    display: &mut D,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = Rgb565>,
+{
+    let (w, h, t) = (DIGIT_SIZE.width, DIGIT_SIZE.height, SEGMENT_WIDTH);
+    let (wi, ti) = (w as i32, t as i32);
+    // middle segment centered, verticals in between the horizontals
+    let middle = (h / 2 - t / 2) as i32;
+    let upper = (middle - ti) as u32;
+    let lower = h - t - (middle as u32 + t);
+    let segment = |x: i32, y: i32, w: u32, h: u32| {
+        Rectangle::new(top_left + Point::new(x, y), Size::new(w, h))
+    };
+    let rects = [
+        segment(ti, 0, w - 2 * t, t),
+        segment(wi - ti, ti, t, upper),
+        segment(wi - ti, middle + ti, t, lower),
+        segment(ti, (h - t) as i32, w - 2 * t, t),
+        segment(0, middle + ti, t, lower),
+        segment(0, ti, t, upper),
+        segment(ti, middle, w - 2 * t, t),
+    ];
+
+    display.fill_solid(&Rectangle::new(top_left, DIGIT_SIZE), background)?;
+    let lit = segments(c);
+    for (i, rect) in rects.iter().enumerate() {
+        if lit & (1 << i) != 0 {
+            display.fill_solid(rect, color)?;
+        }
+    }
+    Ok(())
+}
+
+/// Right aligned, blanks in front
+fn digits(text: &str) -> [char; DIGITS] {
+    let mut d = [' '; DIGITS];
+    for (slot, c) in d.iter_mut().rev().zip(text.chars().rev()) {
+        *slot = c;
+    }
+    d
+}
+
+/// Heart rate and SpO2 in big digits, side by side
+pub struct NumericsScreen {
+    palette: Palette,
+    /// Digits on screen, `None` after a reset
+    heart_rate: Option<[char; DIGITS]>,
+    spo2: Option<[char; DIGITS]>,
+    heart_rate_limits: Label<24>,
+    spo2_limit: Label<24>,
+    quality: Label<16>,
+}
+
+impl NumericsScreen {
+    pub fn new(palette: Palette) -> Self {
+        let half = SCREEN_SIZE.width as i32 / 2;
+        let limits_y = DIGITS_OFFSET.y + DIGIT_SIZE.height as i32 + 20;
+        NumericsScreen {
+            palette,
+            heart_rate: None,
+            spo2: None,
+            heart_rate_limits: Label::new(Point::new(DIGITS_OFFSET.x, limits_y)),
+            spo2_limit: Label::new(Point::new(half + DIGITS_OFFSET.x, limits_y)),
+            quality: Label::new(Point::new(DIGITS_OFFSET.x, CONTENT_SIZE.height as i32 - 14)),
+        }
+    }
+
+    /// Only the digits that changed
+    fn draw_digits<D>(
+        &self,
+        shown: Option<[char; DIGITS]>,
+        new: [char; DIGITS],
+        origin: Point,
+        color: Rgb565,
+        display: &mut D,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        for (i, c) in new.iter().enumerate() {
+            if shown.map_or(true, |s| s[i] != *c) {
+                let x = (i as u32 * (DIGIT_SIZE.width + DIGIT_GAP)) as i32;
+                let top_left = origin + Point::new(x, 0);
+                draw_digit(*c, top_left, color, self.palette.background, display)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Screen for NumericsScreen {
+    fn draw<D>(&mut self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let background = self.palette.background;
+        let hr_origin = DIGITS_OFFSET;
+        let spo2_origin = DIGITS_OFFSET + Point::new(SCREEN_SIZE.width as i32 / 2, 0);
+
+        if self.heart_rate.is_none() {
+            let label_y = DIGITS_OFFSET.y - 12;
+            let style = text_style(&FONT_10X20, self.palette.heart_rate, background);
+            Text::new("HR bpm", Point::new(hr_origin.x, label_y), style).draw(display)?;
+            let style = text_style(&FONT_10X20, self.palette.spo2, background);
+            Text::new("SpO2 %", Point::new(spo2_origin.x, label_y), style).draw(display)?;
+        }
+
+        let mut sbuf: String<16> = String::new();
+        let _ = match frame.model.heart_rate_bpm() {
+            Some(hr) => write!(sbuf, "{:.0}", hr),
+            None => write!(sbuf, "---"),
+        };
+        let hr = digits(&sbuf);
+        self.draw_digits(
+            self.heart_rate,
+            hr,
+            hr_origin,
+            self.palette.heart_rate,
+            display,
+        )?;
+        self.heart_rate = Some(hr);
+
+        sbuf.clear();
+        let _ = match frame.model.spo2() {
+            Some(Spo2::Valid(spo2)) => write!(sbuf, "{:.0}", spo2),
+            Some(Spo2::Invalid) => write!(sbuf, "???"),
+            None => write!(sbuf, "---"),
+        };
+        let spo2 = digits(&sbuf);
+        self.draw_digits(self.spo2, spo2, spo2_origin, self.palette.spo2, display)?;
+        self.spo2 = Some(spo2);
+
+        let style = text_style(&FONT_6X12, self.palette.dim, background);
+        let limits = frame.alarm_limits;
+        sbuf.clear();
+        let _ = write!(sbuf, "ALARM {}-{}", limits.hr_low_bpm, limits.hr_high_bpm);
+        self.heart_rate_limits.draw(&sbuf, style, display)?;
+        sbuf.clear();
+        let _ = write!(sbuf, "ALARM {}", limits.spo2_low);
+        self.spo2_limit.draw(&sbuf, style, display)?;
+
+        let style = text_style(&FONT_10X20, self.palette.foreground, background);
+        self.quality
+            .draw(quality_text(frame.model), style, display)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.heart_rate = None;
+        self.spo2 = None;
+        self.heart_rate_limits.reset();
+        self.spo2_limit.reset();
+        self.quality.reset();
+    }
+}
+
+const TREND_PLOT_X: i32 = 44;
+const HEART_RATE_PLOT: Rectangle = Rectangle::new(
+    Point::new(TREND_PLOT_X, 8),
+    Size::new(TREND_NUM_POINTS as u32, 76),
+);
+const SPO2_PLOT: Rectangle = Rectangle::new(
+    Point::new(TREND_PLOT_X, 100),
+    Size::new(TREND_NUM_POINTS as u32, 64),
+);
+const HEART_RATE_TREND_RANGE: (u8, u8) = (40, 180);
+const SPO2_TREND_RANGE: (u8, u8) = (80, 100);
+
+/// Heart rate and SpO2 over the last `TREND_NUM_POINTS` intervals,
+/// newest on the right, alarm limits as lines
+pub struct TrendScreen {
+    palette: Palette,
+    /// Trend points and limits plotted, `None` after a reset
+    plotted: Option<(usize, AlarmLimits)>,
+}
+
+impl TrendScreen {
+    pub fn new(palette: Palette) -> Self {
+        TrendScreen {
+            palette,
+            plotted: None,
+        }
+    }
+
+    /// Name and range labels on the left, time along the bottom
+    fn draw_axes<D>(
+        &self,
+        trends: &Trends<TREND_NUM_POINTS>,
+        display: &mut D,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let background = self.palette.background;
+        let dim = text_style(&FONT_6X12, self.palette.dim, background);
+        let right = TextStyleBuilder::new().alignment(Alignment::Right).build();
+        let plots = [
+            (
+                &HEART_RATE_PLOT,
+                "HR",
+                HEART_RATE_TREND_RANGE,
+                self.palette.heart_rate,
+            ),
+            (&SPO2_PLOT, "SPO2", SPO2_TREND_RANGE, self.palette.spo2),
+        ];
+        let mut sbuf: String<16> = String::new();
+        for (area, name, (low, high), color) in plots.iter() {
+            let left = TREND_PLOT_X - 4;
+            let bottom = area.top_left.y + area.size.height as i32 - 1;
+            sbuf.clear();
+            let _ = write!(sbuf, "{}", high);
+            Text::with_text_style(&sbuf, Point::new(left, area.top_left.y + 8), dim, right)
+                .draw(display)?;
+            sbuf.clear();
+            let _ = write!(sbuf, "{}", low);
+            Text::with_text_style(&sbuf, Point::new(left, bottom), dim, right).draw(display)?;
+            let style = text_style(&FONT_6X12, *color, background);
+            let middle = area.center() + Point::new(0, 4);
+            Text::new(name, Point::new(2, middle.y), style).draw(display)?;
+        }
+
+        let minutes = TREND_NUM_POINTS as u32 * trends.interval_ms() / 60_000;
+        let y = CONTENT_SIZE.height as i32 - 8;
+        sbuf.clear();
+        let _ = write!(sbuf, "-{} min", minutes);
+        Text::new(&sbuf, Point::new(TREND_PLOT_X, y), dim).draw(display)?;
+        let plot_right = TREND_PLOT_X + TREND_NUM_POINTS as i32 - 1;
+        Text::with_text_style("now", Point::new(plot_right, y), dim, right).draw(display)?;
+        Ok(())
+    }
+
+    fn draw_plot<D>(
+        &self,
+        area: &Rectangle,
+        range: (u8, u8),
+        limits: &[u16],
+        values: impl Iterator<Item = Option<u8>>,
+        color: Rgb565,
+        display: &mut D,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let range = (range.0 as f32, range.1 as f32);
+        let right = area.top_left.x + area.size.width as i32 - 1;
+        display.fill_solid(area, self.palette.background)?;
+
+        let limit_style = PrimitiveStyle::with_stroke(self.palette.dim, 1);
+        for limit in limits {
+            let y = plot_y(*limit as f32, range, area);
+            Line::new(Point::new(area.top_left.x, y), Point::new(right, y))
+                .into_styled(limit_style)
+                .draw(display)?;
+        }
+
+        let line_style = PrimitiveStyle::with_stroke(color, 1);
+        let mut p0_opt: Option<Point> = None;
+        for (x, value) in (area.top_left.x..).zip(values) {
+            let p = value.map(|v| Point::new(x, plot_y(v as f32, range, area)));
+            match (p0_opt, p) {
+                (Some(p0), Some(p)) => Line::new(p0, p).into_styled(line_style).draw(display)?,
+                (None, Some(p)) => Pixel(p, color).draw(display)?,
+                _ => (),
+            }
+            p0_opt = p;
+        }
+        Ok(())
+    }
+}
+
+impl Screen for TrendScreen {
+    fn draw<D>(&mut self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let trends = frame.trends;
+        let limits = frame.alarm_limits;
+        let plotted = Some((trends.total_added(), limits));
+        if plotted == self.plotted {
+            return Ok(());
+        }
+        if self.plotted.is_none() {
+            self.draw_axes(trends, display)?;
+        }
+        self.plotted = plotted;
+
+        // newest on the right edge, empty on the left until there are enough
+        let blank = TREND_NUM_POINTS - trends.len();
+        let points = || core::iter::repeat(None).take(blank);
+        self.draw_plot(
+            &HEART_RATE_PLOT,
+            HEART_RATE_TREND_RANGE,
+            &[limits.hr_low_bpm, limits.hr_high_bpm],
+            points().chain(trends.points().map(|p| p.heart_rate_bpm)),
+            self.palette.heart_rate,
+            display,
+        )?;
+        self.draw_plot(
+            &SPO2_PLOT,
+            SPO2_TREND_RANGE,
+            &[limits.spo2_low as u16],
+            points().chain(trends.points().map(|p| p.spo2)),
+            self.palette.spo2,
+            display,
+        )
+    }
+
+    fn reset(&mut self) {
+        self.plotted = None;
+    }
+}
+
+const TACHOGRAM_AREA: Rectangle = Rectangle::new(Point::new(48, 108), Size::new(256, 76));
+
+/// Time domain HRV stats and a tachogram, the NN intervals in a row
+pub struct HrvScreen {
+    palette: Palette,
+    stats: [Label<24>; 4],
+    /// Stats on screen, `None` after a reset
+    shown: Option<Option<HrvStats>>,
+}
+
+impl HrvScreen {
+    pub fn new(palette: Palette) -> Self {
+        let label = |line: i32| Label::new(Point::new(8, 20 + line * 22));
+        HrvScreen {
+            palette,
+            stats: [label(0), label(1), label(2), label(3)],
+            shown: None,
+        }
+    }
+
+    /// Scaled to the intervals there are, 200ms at least
+    fn draw_tachogram<D>(&self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let area = &TACHOGRAM_AREA;
+        // labels on the left too
+        let labels = Rectangle::new(
+            Point::new(0, area.top_left.y),
+            Size::new(area.top_left.x as u32 + area.size.width, area.size.height),
+        );
+        display.fill_solid(&labels, self.palette.background)?;
+        area.into_styled(PrimitiveStyle::with_stroke(self.palette.dim, 1))
+            .draw(display)?;
+
+        let hrv = match frame.model.hrv() {
+            Some(hrv) => hrv,
+            None => return Ok(()),
+        };
+        let (mut low, mut high) = hrv
+            .intervals()
+            .fold((f32::MAX, f32::MIN), |(l, h), nn| (l.min(nn), h.max(nn)));
+        let middle = (low + high) / 2.0;
+        low = low.min(middle - 100.0);
+        high = high.max(middle + 100.0);
+
+        let dim = text_style(&FONT_6X12, self.palette.dim, self.palette.background);
+        let right = TextStyleBuilder::new().alignment(Alignment::Right).build();
+        let mut sbuf: String<8> = String::new();
+        let _ = write!(sbuf, "{:.0}", high);
+        let left = area.top_left.x - 4;
+        Text::with_text_style(&sbuf, Point::new(left, area.top_left.y + 8), dim, right)
+            .draw(display)?;
+        sbuf.clear();
+        let _ = write!(sbuf, "{:.0}", low);
+        let bottom = area.top_left.y + area.size.height as i32 - 1;
+        Text::with_text_style(&sbuf, Point::new(left, bottom), dim, right).draw(display)?;
+
+        let inner = area.offset(-2);
+        // a full set of intervals spans the width
+        let span = inner.size.width as usize - 1;
+        let line_style = PrimitiveStyle::with_stroke(self.palette.foreground, 1);
+        let dot_style = PrimitiveStyle::with_fill(self.palette.heart_rate);
+        let mut p0_opt: Option<Point> = None;
+        for (i, nn) in hrv.intervals().enumerate() {
+            let x = inner.top_left.x + (i * span / (HRV_NUM_INTERVALS - 1)) as i32;
+            let p = Point::new(x, plot_y(nn, (low, high), &inner));
+            if let Some(p0) = p0_opt {
+                Line::new(p0, p).into_styled(line_style).draw(display)?;
+            }
+            Circle::with_center(p, 3)
+                .into_styled(dot_style)
+                .draw(display)?;
+            p0_opt = Some(p);
+        }
+        Ok(())
+    }
+}
+
+impl Screen for HrvScreen {
+    fn draw<D>(&mut self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let stats = frame.model.hrv_stats();
+        if self.shown == Some(stats) {
+            return Ok(());
+        }
+        self.shown = Some(stats);
+
+        let style = text_style(
+            &FONT_10X20,
+            self.palette.foreground,
+            self.palette.background,
+        );
+        let mut sbuf: String<24> = String::new();
+        for (i, label) in self.stats.iter_mut().enumerate() {
+            sbuf.clear();
+            let _ = match (i, stats) {
+                (0, Some(s)) => write!(sbuf, "NN    {:>4.0} ms  n {}", s.mean_nn, s.num_intervals),
+                (1, Some(s)) => write!(sbuf, "SDNN  {:>4.0} ms", s.sdnn),
+                (2, Some(s)) => write!(sbuf, "RMSSD {:>4.0} ms", s.rmssd),
+                (_, Some(s)) => write!(sbuf, "pNN50 {:>4.0} %", s.pnn50),
+                (0, None) => write!(sbuf, "HRV --"),
+                (_, None) => Ok(()),
+            };
+            label.draw(&sbuf, style, display)?;
+        }
+        self.draw_tachogram(frame, display)
+    }
+
+    fn reset(&mut self) {
+        for label in self.stats.iter_mut() {
+            label.reset();
+        }
+        self.shown = None;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Control {
+    PulseTone,
+    HeartRateLow(i16),
+    HeartRateHigh(i16),
+    Spo2Low(i16),
+    Flip,
+    CalibrateTouch,
+    Save,
+}
+
+const ROW_PITCH: i32 = 32;
+const ROW_HEIGHT: u32 = 28;
+
+const fn button(x: i32, row: i32, width: u32) -> Rectangle {
+    Rectangle::new(
+        Point::new(x, 2 + row * ROW_PITCH),
+        Size::new(width, ROW_HEIGHT),
+    )
+}
+
+/// Value on the left of each row, buttons on the right
+const CONTROLS: [(Control, Rectangle); 10] = [
+    (Control::PulseTone, button(200, 0, 110)),
+    (Control::HeartRateLow(-5), button(200, 1, 50)),
+    (Control::HeartRateLow(5), button(260, 1, 50)),
+    (Control::HeartRateHigh(-5), button(200, 2, 50)),
+    (Control::HeartRateHigh(5), button(260, 2, 50)),
+    (Control::Spo2Low(-1), button(200, 3, 50)),
+    (Control::Spo2Low(1), button(260, 3, 50)),
+    (Control::Flip, button(200, 4, 110)),
+    (Control::CalibrateTouch, button(10, 5, 150)),
+    (Control::Save, button(200, 5, 110)),
+];
+
+/// Pulse tone, alarm limits, rotation and touch calibration.
+/// Changes take effect right away, `SAVE` keeps them.
+pub struct SettingsScreen {
+    palette: Palette,
+    /// Pulse tone, limits and rotation on screen, `None` after a reset
+    shown: Option<(bool, AlarmLimits, u8)>,
+}
+
+impl SettingsScreen {
+    pub fn new(palette: Palette) -> Self {
+        SettingsScreen {
+            palette,
+            shown: None,
+        }
+    }
+}
+
+fn adjust<T: Into<i32> + TryFrom<i32> + Copy>(value: T, by: i16) -> Option<T> {
+    T::try_from(value.into() + by as i32).ok()
+}
+
+impl Screen for SettingsScreen {
+    fn draw<D>(&mut self, frame: &Frame<'_>, display: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let limits = frame.alarm_limits;
+        let shown = Some((frame.pulse_tone, limits, frame.rotation));
+        if shown == self.shown {
+            return Ok(());
+        }
+        self.shown = shown;
+
+        let content = Rectangle::new(Point::zero(), CONTENT_SIZE);
+        display.fill_solid(&content, self.palette.background)?;
+
+        let style = text_style(
+            &FONT_10X20,
+            self.palette.foreground,
+            self.palette.background,
+        );
+        let mut sbuf: String<24> = String::new();
+        for row in 0..5 {
+            sbuf.clear();
+            let _ = match row {
+                0 => write!(sbuf, "Pulse tone"),
+                1 => write!(sbuf, "HR low   {:>3}", limits.hr_low_bpm),
+                2 => write!(sbuf, "HR high  {:>3}", limits.hr_high_bpm),
+                3 => write!(sbuf, "SpO2 low {:>3}", limits.spo2_low),
+                _ => write!(sbuf, "Rotation {:>3}", frame.rotation as u32 * 90),
+            };
+            let y = 2 + row * ROW_PITCH + ROW_HEIGHT as i32 / 2;
+            Text::with_baseline(&sbuf, Point::new(10, y), style, Baseline::Middle).draw(display)?;
+        }
+
+        for (control, area) in CONTROLS.iter() {
+            let label = match control {
+                Control::PulseTone if frame.pulse_tone => "ON",
+                Control::PulseTone => "OFF",
+                Control::HeartRateLow(by) | Control::HeartRateHigh(by) | Control::Spo2Low(by)
+                    if *by < 0 =>
+                {
+                    "-"
+                }
+                Control::HeartRateLow(_) | Control::HeartRateHigh(_) | Control::Spo2Low(_) => "+",
+                Control::Flip => "FLIP",
+                Control::CalibrateTouch => "TOUCH CAL",
+                Control::Save => "SAVE",
+            };
+            let selected = *control == Control::PulseTone && frame.pulse_tone;
+            draw_button(label, area, selected, &self.palette, display)?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.shown = None;
+    }
+
+    fn on_tap(&mut self, point: Point, frame: &Frame<'_>) -> Option<Action> {
+        let (control, _) = CONTROLS.iter().find(|(_, area)| area.contains(point))?;
+        let mut limits = frame.alarm_limits;
+        match *control {
+            Control::PulseTone => return Some(Action::PulseTone(!frame.pulse_tone)),
+            Control::HeartRateLow(by) => limits.hr_low_bpm = adjust(limits.hr_low_bpm, by)?,
+            Control::HeartRateHigh(by) => limits.hr_high_bpm = adjust(limits.hr_high_bpm, by)?,
+            Control::Spo2Low(by) => limits.spo2_low = adjust(limits.spo2_low, by)?,
+            // landscape only, the other way up
+            Control::Flip => return Some(Action::Rotate(if frame.rotation == 1 { 3 } else { 1 })),
+            Control::CalibrateTouch => return Some(Action::CalibrateTouch),
+            Control::Save => return Some(Action::Save),
+        }
+        // past the end of the range, or low over high
+        limits.is_valid().then_some(Action::AlarmLimits(limits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        alarms::AlarmKind,
+        circ::Circ,
+        framebuffer::Framebuffer,
+        model::Max3012Sample,
+        synth::{PpgConfig, PpgSynth},
+        timing::SampleStamper,
+        trends::TREND_INTERVAL_MS,
+    };
+
+    /// Steady 75bpm, fed a second at a time like the firmware does
+    fn model(seconds: usize) -> UIModel<MAX30102_NUM_SAMPLES> {
+        let cfg = PpgConfig {
+            heart_rate_bpm: 75.0,
+            hrv_ms: 30.0,
+            ..PpgConfig::default()
+        };
+        let mut stamper = SampleStamper::new(25);
+        let mut window: Circ<_, MAX30102_NUM_SAMPLES> = Circ::new(Max3012Sample::zero());
+        let mut model = UIModel::new();
+        for (i, s) in PpgSynth::new(cfg).take(25 * seconds).enumerate() {
+            let stamp = stamper.stamp(i as u32 * 40, 0, 1).next().unwrap();
+            window.add(Max3012Sample::new(s.sample, stamp));
+            if i % 25 == 24 {
+                let mut samples = [Max3012Sample::zero(); MAX30102_NUM_SAMPLES];
+                for (j, s) in window.iter().enumerate() {
+                    samples[j] = s;
+                }
+                model.update_from_samples(&samples, 25);
+            }
+        }
+        model
+    }
+
+    fn frame<'a>(
+        model: &'a UIModel<MAX30102_NUM_SAMPLES>,
+        trends: &'a Trends<TREND_NUM_POINTS>,
+    ) -> Frame<'a> {
+        Frame {
+            model,
+            trends,
+            alarm: None,
+            alarm_limits: AlarmLimits::default(),
+            pulse_tone: true,
+            rotation: 1,
+        }
+    }
+
+    fn tap(ui: &mut Ui, x: i32, y: i32, frame: &Frame<'_>) -> Option<Action> {
+        assert_eq!(ui.touch(Some(TouchPoint { x, y }), frame), None);
+        ui.touch(None, frame)
+    }
+
+    #[test]
+    fn test_screen_ids() {
+        let mut id = ScreenId::Waveform;
+        for expected in ScreenId::ALL.iter().skip(1).chain(ScreenId::ALL.iter()) {
+            id = id.next();
+            assert_eq!(id, *expected);
+        }
+        assert_eq!(ScreenId::from_name("trend"), Some(ScreenId::Trends));
+        assert_eq!(ScreenId::from_name("SETUP"), Some(ScreenId::Settings));
+        assert_eq!(ScreenId::from_name("foo"), None);
+    }
+
+    #[test]
+    fn test_draw() {
+        let model = model(20);
+        assert!(model.heart_rate_bpm().is_some());
+        assert!(model.hrv_stats().is_some());
+        let mut trends = Trends::new(TREND_INTERVAL_MS);
+        for s in 0..600 {
+            let spo2 = if s < 300 { 97.0 } else { 88.0 };
+            trends.update(s * 1000, Some(75.0), Some(spo2));
+        }
+        let mut frame = frame(&model, &trends);
+        frame.alarm = Some(Alarm {
+            kind: AlarmKind::Spo2Low,
+            latched: true,
+            silenced: false,
+        });
+
+        let palette = Palette::from(Theme::Dark);
+        let mut ui = Ui::new(Theme::Dark);
+        for id in ScreenId::ALL.iter() {
+            let mut fb = Framebuffer::new(SCREEN_SIZE, Rgb565::WHITE);
+            ui.show(*id);
+            ui.draw(&frame, &mut fb).unwrap();
+
+            // the display is cleared first, tab bar and banner always there
+            let content = Rectangle::new(Point::zero(), CONTENT_SIZE);
+            assert_eq!(fb.count(&fb.bounding_box(), Rgb565::WHITE), 0, "{:?}", id);
+            assert!(fb.count(&content, palette.background) > 0, "{:?}", id);
+            assert!(fb.count(&BANNER_AREA, Rgb565::RED) > 0, "{:?}", id);
+            let tab = ScreenId::ALL.iter().position(|i| i == id).unwrap() as i32;
+            let tab_corner = TAB_BAR_AREA.top_left + Point::new(tab * TAB_WIDTH as i32 + 2, 2);
+            assert_eq!(fb.pixel(tab_corner), Some(palette.foreground), "{:?}", id);
+
+            let colors: &[Rgb565] = match id {
+                ScreenId::Waveform => &[Rgb565::RED, Rgb565::BLUE],
+                ScreenId::Numerics | ScreenId::Trends => &[palette.heart_rate, palette.spo2],
+                ScreenId::Hrv => &[palette.heart_rate],
+                ScreenId::Settings => &[palette.foreground],
+            };
+            for c in colors {
+                assert!(fb.count(&content, *c) > 0, "{:?} {:?}", id, c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_redraw() {
+        let model = model(10);
+        let trends = Trends::new(TREND_INTERVAL_MS);
+        let frame = frame(&model, &trends);
+        let mut ui = Ui::new(Theme::Light);
+        ui.show(ScreenId::Numerics);
+        let mut fb = Framebuffer::new(SCREEN_SIZE, Rgb565::MAGENTA);
+        ui.draw(&frame, &mut fb).unwrap();
+
+        // nothing changed, nothing drawn
+        let mut fb = Framebuffer::new(SCREEN_SIZE, Rgb565::MAGENTA);
+        ui.draw(&frame, &mut fb).unwrap();
+        assert_eq!(fb.count(&fb.bounding_box(), Rgb565::MAGENTA), 240 * 320);
+
+        ui.redraw();
+        ui.draw(&frame, &mut fb).unwrap();
+        assert_eq!(fb.count(&fb.bounding_box(), Rgb565::MAGENTA), 0);
+    }
+
+    #[test]
+    fn test_touch() {
+        let model = UIModel::new();
+        let trends = Trends::new(TREND_INTERVAL_MS);
+        let mut frame = frame(&model, &trends);
+        let mut ui = Ui::new(Theme::Dark);
+        let tab_y = TAB_BAR_AREA.center().y;
+
+        // held down, nothing until let go
+        let settings_tab = TouchPoint { x: 300, y: tab_y };
+        assert_eq!(ui.touch(Some(settings_tab), &frame), None);
+        assert_eq!(ui.touch(Some(TouchPoint { x: 10, y: 10 }), &frame), None);
+        assert_eq!(ui.screen(), ScreenId::Waveform);
+        assert_eq!(ui.touch(None, &frame), None);
+        assert_eq!(ui.screen(), ScreenId::Settings);
+        assert_eq!(tap(&mut ui, 70, tab_y, &frame), None);
+        assert_eq!(ui.screen(), ScreenId::Numerics);
+
+        // banner, only with an alarm on
+        let banner_y = BANNER_AREA.center().y;
+        assert_eq!(tap(&mut ui, 100, banner_y, &frame), None);
+        frame.alarm = Some(Alarm {
+            kind: AlarmKind::HeartRateHigh,
+            latched: false,
+            silenced: false,
+        });
+        assert_eq!(
+            tap(&mut ui, 100, banner_y, &frame),
+            Some(Action::Acknowledge)
+        );
+
+        ui.show(ScreenId::Settings);
+        let at = |control| {
+            let (_, area) = CONTROLS.iter().find(|(c, _)| *c == control).unwrap();
+            area.center()
+        };
+        let mut tap_control = |control, frame: &Frame<'_>| {
+            let p = at(control);
+            tap(&mut ui, p.x, p.y, frame)
+        };
+        assert_eq!(
+            tap_control(Control::PulseTone, &frame),
+            Some(Action::PulseTone(false))
+        );
+        assert_eq!(
+            tap_control(Control::HeartRateHigh(5), &frame),
+            Some(Action::AlarmLimits(AlarmLimits {
+                hr_high_bpm: 125,
+                ..AlarmLimits::default()
+            }))
+        );
+        assert_eq!(tap_control(Control::Flip, &frame), Some(Action::Rotate(3)));
+        assert_eq!(tap_control(Control::Save, &frame), Some(Action::Save));
+
+        // no further than the console allows
+        frame.alarm_limits.spo2_low = 99;
+        assert_eq!(tap_control(Control::Spo2Low(1), &frame), None);
+        frame.alarm_limits.hr_low_bpm = 115;
+        assert_eq!(tap_control(Control::HeartRateLow(5), &frame), None);
+        assert_eq!(
+            tap_control(Control::HeartRateLow(-5), &frame),
+            Some(Action::AlarmLimits(AlarmLimits {
+                hr_low_bpm: 110,
+                spo2_low: 99,
+                ..AlarmLimits::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn test_digits() {
+        assert_eq!(digits("72"), [' ', '7', '2']);
+        assert_eq!(digits("---"), ['-', '-', '-']);
+        assert_eq!(digits("100"), ['1', '0', '0']);
+
+        let mut fb = Framebuffer::new(DIGIT_SIZE, Rgb565::WHITE);
+        draw_digit('8', Point::zero(), Rgb565::RED, Rgb565::BLACK, &mut fb).unwrap();
+        let all = fb.count(&fb.bounding_box(), Rgb565::RED);
+        draw_digit('1', Point::zero(), Rgb565::RED, Rgb565::BLACK, &mut fb).unwrap();
+        let one = fb.count(&fb.bounding_box(), Rgb565::RED);
+        assert_eq!(fb.count(&fb.bounding_box(), Rgb565::WHITE), 0);
+        assert!(one > 0 && one < all / 3, "{} {}", one, all);
+    }
+}
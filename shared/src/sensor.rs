@@ -0,0 +1,299 @@
+//! MAX3010x acquisition settings
+//!
+//! Mirrors the sensor's register options, the firmware maps them onto the
+//! driver. Living here they can be parsed, checked and stored without it.
+
+use core::fmt;
+
+/// LED pulse width, also sets the ADC resolution (15 to 18 bits)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PulseWidth {
+    Us69,
+    Us118,
+    Us215,
+    Us411,
+}
+
+impl PulseWidth {
+    pub const ALL: [PulseWidth; 4] = [Self::Us69, Self::Us118, Self::Us215, Self::Us411];
+
+    pub fn us(self) -> u16 {
+        match self {
+            Self::Us69 => 69,
+            Self::Us118 => 118,
+            Self::Us215 => 215,
+            Self::Us411 => 411,
+        }
+    }
+
+    pub fn from_us(us: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|pw| pw.us() == us)
+    }
+}
+
+/// Sensor internal sample rate, before averaging
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleRate {
+    Sps50,
+    Sps100,
+    Sps200,
+    Sps400,
+    Sps800,
+    Sps1000,
+    Sps1600,
+    Sps3200,
+}
+
+impl SampleRate {
+    pub const ALL: [SampleRate; 8] = [
+        Self::Sps50,
+        Self::Sps100,
+        Self::Sps200,
+        Self::Sps400,
+        Self::Sps800,
+        Self::Sps1000,
+        Self::Sps1600,
+        Self::Sps3200,
+    ];
+
+    pub fn sps(self) -> u16 {
+        match self {
+            Self::Sps50 => 50,
+            Self::Sps100 => 100,
+            Self::Sps200 => 200,
+            Self::Sps400 => 400,
+            Self::Sps800 => 800,
+            Self::Sps1000 => 1000,
+            Self::Sps1600 => 1600,
+            Self::Sps3200 => 3200,
+        }
+    }
+
+    pub fn from_sps(sps: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|sr| sr.sps() == sps)
+    }
+
+    /// Longest pulse that fits in a sample period with both LEDs on,
+    /// datasheet table 11, `None` if the rate is too fast for SpO2 mode.
+    pub fn max_pulse_width(self) -> Option<PulseWidth> {
+        match self {
+            Self::Sps50 | Self::Sps100 | Self::Sps200 | Self::Sps400 => Some(PulseWidth::Us411),
+            Self::Sps800 | Self::Sps1000 => Some(PulseWidth::Us215),
+            Self::Sps1600 => Some(PulseWidth::Us69),
+            Self::Sps3200 => None,
+        }
+    }
+}
+
+/// Samples averaged by the sensor into each FIFO sample
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleAveraging {
+    Sa1,
+    Sa2,
+    Sa4,
+    Sa8,
+    Sa16,
+    Sa32,
+}
+
+impl SampleAveraging {
+    pub const ALL: [SampleAveraging; 6] = [
+        Self::Sa1,
+        Self::Sa2,
+        Self::Sa4,
+        Self::Sa8,
+        Self::Sa16,
+        Self::Sa32,
+    ];
+
+    pub fn count(self) -> u16 {
+        match self {
+            Self::Sa1 => 1,
+            Self::Sa2 => 2,
+            Self::Sa4 => 4,
+            Self::Sa8 => 8,
+            Self::Sa16 => 16,
+            Self::Sa32 => 32,
+        }
+    }
+
+    pub fn from_count(count: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|sa| sa.count() == count)
+    }
+}
+
+/// ADC full scale, nA
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdcRange {
+    Fs2k,
+    Fs4k,
+    Fs8k,
+    Fs16k,
+}
+
+impl AdcRange {
+    pub const ALL: [AdcRange; 4] = [Self::Fs2k, Self::Fs4k, Self::Fs8k, Self::Fs16k];
+
+    pub fn full_scale_na(self) -> u16 {
+        match self {
+            Self::Fs2k => 2048,
+            Self::Fs4k => 4096,
+            Self::Fs8k => 8192,
+            Self::Fs16k => 16384,
+        }
+    }
+
+    pub fn from_full_scale_na(na: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|r| r.full_scale_na() == na)
+    }
+}
+
+/// Slowest and fastest FIFO sample rate the model can work with,
+/// Hz. Below, the band pass doesn't fit under Nyquist, above, the
+/// sample window gets shorter than the slowest heartbeat.
+pub const MIN_SAMPLE_RATE_HZ: u32 = 10;
+pub const MAX_SAMPLE_RATE_HZ: u32 = 50;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SettingsError {
+    /// Pulse width doesn't fit in the sample period
+    PulseWidth,
+    /// FIFO sample rate (sample rate over averaging) is not a whole
+    /// number of Hz or out of the model's range
+    SampleRate,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::PulseWidth => write!(f, "pulse width too long for sample rate"),
+            SettingsError::SampleRate => write!(
+                f,
+                "rate/avg must be a whole {}..{}Hz",
+                MIN_SAMPLE_RATE_HZ, MAX_SAMPLE_RATE_HZ
+            ),
+        }
+    }
+}
+
+/// Everything the sensor is configured with, in SpO2 mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SensorSettings {
+    /// Both LEDs, 0.2mA per step, where automatic control starts from
+    pub led_amplitude: u8,
+    pub pulse_width: PulseWidth,
+    pub sample_rate: SampleRate,
+    pub averaging: SampleAveraging,
+    pub adc_range: AdcRange,
+}
+
+impl Default for SensorSettings {
+    /// 25Hz, widest pulse and range for the best SNR
+    fn default() -> Self {
+        SensorSettings {
+            led_amplitude: 200,
+            pulse_width: PulseWidth::Us411,
+            sample_rate: SampleRate::Sps400,
+            averaging: SampleAveraging::Sa16,
+            adc_range: AdcRange::Fs16k,
+        }
+    }
+}
+
+impl SensorSettings {
+    /// Rate samples come out of the FIFO, Hz, see [`SensorSettings::validate`]
+    pub fn sample_rate_hz(&self) -> u32 {
+        (self.sample_rate.sps() / self.averaging.count()) as u32
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        match self.sample_rate.max_pulse_width() {
+            Some(max) if self.pulse_width.us() <= max.us() => (),
+            _ => return Err(SettingsError::PulseWidth),
+        }
+
+        let exact = self.sample_rate.sps() % self.averaging.count() == 0;
+        if !exact || !(MIN_SAMPLE_RATE_HZ..=MAX_SAMPLE_RATE_HZ).contains(&self.sample_rate_hz()) {
+            return Err(SettingsError::SampleRate);
+        }
+        Ok(())
+    }
+}
+
+/// Same syntax as the console `set` commands
+impl fmt::Display for SensorSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "led {} pw {} rate {} avg {} range {}",
+            self.led_amplitude,
+            self.pulse_width.us(),
+            self.sample_rate.sps(),
+            self.averaging.count(),
+            self.adc_range.full_scale_na()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        for pw in PulseWidth::ALL {
+            assert_eq!(PulseWidth::from_us(pw.us()), Some(pw));
+        }
+        for sr in SampleRate::ALL {
+            assert_eq!(SampleRate::from_sps(sr.sps()), Some(sr));
+        }
+        for sa in SampleAveraging::ALL {
+            assert_eq!(SampleAveraging::from_count(sa.count()), Some(sa));
+        }
+        for r in AdcRange::ALL {
+            assert_eq!(AdcRange::from_full_scale_na(r.full_scale_na()), Some(r));
+        }
+        assert_eq!(PulseWidth::from_us(400), None);
+        assert_eq!(SampleAveraging::from_count(3), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let s = SensorSettings::default();
+        assert_eq!(s.validate(), Ok(()));
+        assert_eq!(s.sample_rate_hz(), crate::model::MAX30102_SAMPLE_RATE_HZ);
+
+        let s = SensorSettings {
+            sample_rate: SampleRate::Sps800,
+            averaging: SampleAveraging::Sa32,
+            ..SensorSettings::default()
+        };
+        assert_eq!(s.validate(), Err(SettingsError::PulseWidth));
+        let s = SensorSettings {
+            pulse_width: PulseWidth::Us215,
+            ..s
+        };
+        assert_eq!(s.validate(), Ok(()));
+
+        // 400 / 32 = 12.5Hz
+        let s = SensorSettings {
+            averaging: SampleAveraging::Sa32,
+            ..SensorSettings::default()
+        };
+        assert_eq!(s.validate(), Err(SettingsError::SampleRate));
+        // 100Hz
+        let s = SensorSettings {
+            averaging: SampleAveraging::Sa4,
+            ..SensorSettings::default()
+        };
+        assert_eq!(s.validate(), Err(SettingsError::SampleRate));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            SensorSettings::default().to_string(),
+            "led 200 pw 411 rate 400 avg 16 range 16384"
+        );
+    }
+}
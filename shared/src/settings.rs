@@ -0,0 +1,665 @@
+//! User settings, persisted in flash
+//!
+//! Settings are stored as fixed size records appended to one of two flash
+//! pages. Saving never overwrites a record, when the page fills up the
+//! other one is erased and used next, so each page is erased once every
+//! `SLOTS_PER_PAGE` saves. Loading picks the valid record with the highest
+//! sequence number, a torn or corrupted record just falls back to the one
+//! before it, or to defaults.
+//!
+//! Record, little endian:
+//!
+//! | offset | size | field                           |
+//! |--------|------|---------------------------------|
+//! | 0      | 2    | magic `0xc5a7`                  |
+//! | 2      | 1    | version                         |
+//! | 3      | 1    | payload length                  |
+//! | 4      | 4    | sequence number                 |
+//! | 8      | 118  | payload, padded with 0xff       |
+//! | 126    | 2    | CRC-16 of bytes 0..126          |
+//!
+//! The payload has room to spare for settings still to come.
+
+use core::{fmt, ops::RangeInclusive};
+
+use crate::calibration::{LookupTable, Spo2Calibration, MAX_CALIBRATION_POINTS};
+use crate::crc::crc16;
+use crate::sensor::{AdcRange, PulseWidth, SampleAveraging, SampleRate, SensorSettings};
+use crate::touch::TouchCalibration;
+
+/// Bump when the payload layout changes, records of other versions are ignored
+pub const SETTINGS_VERSION: u8 = 1;
+
+pub const RECORD_LEN: usize = 128;
+const MAGIC: u16 = 0xc5a7;
+const HEADER_LEN: usize = 8;
+const PAYLOAD_LEN: usize = RECORD_LEN - HEADER_LEN - 2;
+const ERASED: u8 = 0xff;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Theme {
+    /// Light text on black
+    Dark,
+    /// Dark text on white
+    Light,
+}
+
+/// Vital sign alarm limits, an alarm goes off outside of them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AlarmLimits {
+    pub hr_low_bpm: u16,
+    pub hr_high_bpm: u16,
+    pub spo2_low: u8,
+}
+
+impl Default for AlarmLimits {
+    /// Common adult monitor defaults
+    fn default() -> Self {
+        AlarmLimits {
+            hr_low_bpm: 50,
+            hr_high_bpm: 120,
+            spo2_low: 90,
+        }
+    }
+}
+
+impl AlarmLimits {
+    /// Heart rate limits that can be set, bpm
+    pub const HEART_RATE_RANGE: RangeInclusive<u16> = 25..=250;
+    /// SpO2 low limits that can be set, %
+    pub const SPO2_RANGE: RangeInclusive<u8> = 50..=99;
+
+    /// All in range, heart rate low below high
+    pub fn is_valid(&self) -> bool {
+        Self::HEART_RATE_RANGE.contains(&self.hr_low_bpm)
+            && Self::HEART_RATE_RANGE.contains(&self.hr_high_bpm)
+            && self.hr_low_bpm < self.hr_high_bpm
+            && Self::SPO2_RANGE.contains(&self.spo2_low)
+    }
+}
+
+/// Same syntax as the console `alarm` commands, both in one line
+impl fmt::Display for AlarmLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hr {} {} spo2 {}",
+            self.hr_low_bpm, self.hr_high_bpm, self.spo2_low
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub sensor: SensorSettings,
+    /// Screen rotation, CCW quarter turns
+    pub rotation: u8,
+    pub alarms: AlarmLimits,
+    pub theme: Theme,
+    pub spo2_calibration: Spo2Calibration,
+    /// `None` until the panel is calibrated
+    pub touch_calibration: Option<TouchCalibration>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            sensor: SensorSettings::default(),
+            rotation: 1,
+            alarms: AlarmLimits::default(),
+            theme: Theme::Dark,
+            spo2_calibration: Spo2Calibration::default(),
+            touch_calibration: None,
+        }
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, x: u8) {
+        self.buf[self.len] = x;
+        self.len += 1;
+    }
+
+    fn u16(&mut self, x: u16) {
+        self.buf[self.len..self.len + 2].copy_from_slice(&x.to_le_bytes());
+        self.len += 2;
+    }
+
+    fn f32(&mut self, x: f32) {
+        self.buf[self.len..self.len + 4].copy_from_slice(&x.to_le_bytes());
+        self.len += 4;
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let x = *self.buf.get(self.pos)?;
+        self.pos += 1;
+        Some(x)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+
+    /// Only finite values
+    fn f32(&mut self) -> Option<f32> {
+        let bytes = self.buf.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).filter(|x| x.is_finite())
+    }
+}
+
+impl Settings {
+    fn encode_payload(&self, out: &mut [u8]) -> usize {
+        let mut w = Writer { buf: out, len: 0 };
+        let s = &self.sensor;
+        w.u8(s.led_amplitude);
+        w.u16(s.pulse_width.us());
+        w.u16(s.sample_rate.sps());
+        w.u8(s.averaging.count() as u8);
+        w.u16(s.adc_range.full_scale_na());
+        w.u8(self.rotation);
+        w.u16(self.alarms.hr_low_bpm);
+        w.u16(self.alarms.hr_high_bpm);
+        w.u8(self.alarms.spo2_low);
+        w.u8(match self.theme {
+            Theme::Dark => 0,
+            Theme::Light => 1,
+        });
+        match self.spo2_calibration {
+            Spo2Calibration::Polynomial {
+                coefficients,
+                ratio_min,
+                ratio_max,
+            } => {
+                w.u8(0);
+                for c in coefficients {
+                    w.f32(c);
+                }
+                w.f32(ratio_min);
+                w.f32(ratio_max);
+            }
+            Spo2Calibration::Lookup(table) => {
+                w.u8(1);
+                w.u8(table.points().len() as u8);
+                for (ratio, spo2) in table.points() {
+                    w.f32(*ratio);
+                    w.f32(*spo2);
+                }
+            }
+        }
+        match self.touch_calibration {
+            Some(calibration) => {
+                w.u8(1);
+                for c in calibration.coefficients {
+                    w.f32(c);
+                }
+            }
+            None => w.u8(0),
+        }
+        w.len
+    }
+
+    /// `None` for anything out of range, the record is treated as corrupt
+    fn decode_payload(version: u8, payload: &[u8]) -> Option<Self> {
+        if version != SETTINGS_VERSION {
+            return None;
+        }
+        let mut r = Reader {
+            buf: payload,
+            pos: 0,
+        };
+        let sensor = SensorSettings {
+            led_amplitude: r.u8()?,
+            pulse_width: PulseWidth::from_us(r.u16()?)?,
+            sample_rate: SampleRate::from_sps(r.u16()?)?,
+            averaging: SampleAveraging::from_count(r.u8()? as u16)?,
+            adc_range: AdcRange::from_full_scale_na(r.u16()?)?,
+        };
+        sensor.validate().ok()?;
+        let rotation = r.u8().filter(|r| *r < 4)?;
+        let alarms = AlarmLimits {
+            hr_low_bpm: r.u16()?,
+            hr_high_bpm: r.u16()?,
+            spo2_low: r.u8()?,
+        };
+        if !alarms.is_valid() {
+            return None;
+        }
+        let theme = match r.u8()? {
+            0 => Theme::Dark,
+            1 => Theme::Light,
+            _ => return None,
+        };
+        let spo2_calibration = Self::decode_calibration(&mut r)?;
+        let touch_calibration = match r.u8()? {
+            0 => None,
+            1 => Some(TouchCalibration::new([
+                r.f32()?,
+                r.f32()?,
+                r.f32()?,
+                r.f32()?,
+                r.f32()?,
+                r.f32()?,
+            ])?),
+            _ => return None,
+        };
+        Some(Settings {
+            sensor,
+            rotation,
+            alarms,
+            theme,
+            spo2_calibration,
+            touch_calibration,
+        })
+    }
+
+    fn decode_calibration(r: &mut Reader) -> Option<Spo2Calibration> {
+        match r.u8()? {
+            0 => Some(Spo2Calibration::Polynomial {
+                coefficients: [r.f32()?, r.f32()?, r.f32()?],
+                ratio_min: r.f32()?,
+                ratio_max: r.f32()?,
+            }),
+            1 => {
+                let len = r.u8()? as usize;
+                let mut points = [(0.0, 0.0); MAX_CALIBRATION_POINTS];
+                for p in points.get_mut(..len)? {
+                    *p = (r.f32()?, r.f32()?);
+                }
+                LookupTable::new(&points[..len]).map(Spo2Calibration::Lookup)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn encode_record(seq: u32, settings: &Settings) -> [u8; RECORD_LEN] {
+    let mut rec = [ERASED; RECORD_LEN];
+    let len = settings.encode_payload(&mut rec[HEADER_LEN..HEADER_LEN + PAYLOAD_LEN]);
+    rec[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+    rec[2] = SETTINGS_VERSION;
+    rec[3] = len as u8;
+    rec[4..8].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc16(&rec[..RECORD_LEN - 2]);
+    rec[RECORD_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+    rec
+}
+
+fn decode_record(rec: &[u8; RECORD_LEN]) -> Option<(u32, Settings)> {
+    let crc = u16::from_le_bytes([rec[RECORD_LEN - 2], rec[RECORD_LEN - 1]]);
+    if u16::from_le_bytes([rec[0], rec[1]]) != MAGIC || crc16(&rec[..RECORD_LEN - 2]) != crc {
+        return None;
+    }
+    let len = rec[3] as usize;
+    let payload = rec[HEADER_LEN..].get(..len.min(PAYLOAD_LEN))?;
+    let seq = u32::from_le_bytes([rec[4], rec[5], rec[6], rec[7]]);
+    Settings::decode_payload(rec[2], payload).map(|s| (seq, s))
+}
+
+/// Two erasable pages of flash set aside for settings,
+/// erased bytes read as 0xff
+pub trait SettingsFlash {
+    type Error;
+
+    /// Bytes per page, a multiple of `RECORD_LEN`
+    const PAGE_SIZE: u32;
+
+    /// `offset` is from the start of the first page
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Programs erased bytes, `offset` and length are even
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Erases page 0 or 1
+    fn erase_page(&mut self, page: u32) -> Result<(), Self::Error>;
+}
+
+pub struct SettingsStore<F> {
+    flash: F,
+    /// Slot for the next record, counting across both pages,
+    /// `None` if there are no valid records at all
+    next_slot: Option<u32>,
+    seq: u32,
+    /// Last loaded or saved
+    current: Option<Settings>,
+}
+
+impl<F: SettingsFlash> SettingsStore<F> {
+    const SLOTS_PER_PAGE: u32 = F::PAGE_SIZE / RECORD_LEN as u32;
+
+    pub fn new(flash: F) -> Self {
+        SettingsStore {
+            flash,
+            next_slot: None,
+            seq: 0,
+            current: None,
+        }
+    }
+
+    /// Latest valid settings, defaults if there are none
+    pub fn load(&mut self) -> Result<Settings, F::Error> {
+        let mut latest: Option<(u32, u32, Settings)> = None;
+        let mut last_used = [None; 2];
+
+        for slot in 0..2 * Self::SLOTS_PER_PAGE {
+            let mut rec = [0; RECORD_LEN];
+            self.flash.read(slot * RECORD_LEN as u32, &mut rec)?;
+            if rec.iter().all(|b| *b == ERASED) {
+                continue;
+            }
+            last_used[(slot / Self::SLOTS_PER_PAGE) as usize] = Some(slot);
+
+            if let Some((seq, settings)) = decode_record(&rec) {
+                if latest.map_or(true, |(latest_seq, ..)| seq > latest_seq) {
+                    latest = Some((seq, slot, settings));
+                }
+            }
+        }
+
+        // new records go after anything written to the latest one's page,
+        // torn writes included
+        let (seq, next_slot, settings) = match latest {
+            Some((seq, slot, settings)) => {
+                let page = (slot / Self::SLOTS_PER_PAGE) as usize;
+                (seq, last_used[page].map(|s| s + 1), settings)
+            }
+            None => (0, None, Settings::default()),
+        };
+        self.seq = seq;
+        self.next_slot = next_slot;
+        self.current = Some(settings);
+        Ok(settings)
+    }
+
+    /// Appends a record, unless `settings` is what's stored already
+    pub fn save(&mut self, settings: &Settings) -> Result<(), F::Error> {
+        if self.current.as_ref() == Some(settings) {
+            return Ok(());
+        }
+
+        let slot = self.next_slot.unwrap_or(0) % (2 * Self::SLOTS_PER_PAGE);
+        if slot % Self::SLOTS_PER_PAGE == 0 {
+            // the old page keeps the previous settings until this one is written
+            self.flash.erase_page(slot / Self::SLOTS_PER_PAGE)?;
+        }
+
+        let seq = self.seq.wrapping_add(1);
+        let rec = encode_record(seq, settings);
+        // a failed write still used up the slot
+        self.next_slot = Some(slot + 1);
+        self.flash.write(slot * RECORD_LEN as u32, &rec)?;
+
+        self.seq = seq;
+        self.current = Some(*settings);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = 1024;
+
+    #[derive(Debug, PartialEq)]
+    enum RamFlashError {
+        NotErased,
+        PowerLoss,
+    }
+
+    /// Two pages in RAM with flash write rules, and a way
+    /// to cut the power halfway through a write
+    struct RamFlash {
+        data: Vec<u8>,
+        erase_count: [usize; 2],
+        /// bytes that still get written before the power goes
+        power_left: Option<usize>,
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            RamFlash {
+                data: vec![ERASED; 2 * PAGE_SIZE],
+                erase_count: [0; 2],
+                power_left: None,
+            }
+        }
+    }
+
+    impl SettingsFlash for &mut RamFlash {
+        type Error = RamFlashError;
+
+        const PAGE_SIZE: u32 = PAGE_SIZE as u32;
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), RamFlashError> {
+            let offset = offset as usize;
+            buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), RamFlashError> {
+            assert!(offset % 2 == 0 && data.len() % 2 == 0);
+            for (i, b) in data.iter().enumerate() {
+                if let Some(left) = self.power_left.as_mut() {
+                    if *left == 0 {
+                        return Err(RamFlashError::PowerLoss);
+                    }
+                    *left -= 1;
+                }
+                let cell = &mut self.data[offset as usize + i];
+                if *cell != ERASED {
+                    return Err(RamFlashError::NotErased);
+                }
+                *cell = *b;
+            }
+            Ok(())
+        }
+
+        fn erase_page(&mut self, page: u32) -> Result<(), RamFlashError> {
+            let start = page as usize * PAGE_SIZE;
+            self.data[start..start + PAGE_SIZE].fill(ERASED);
+            self.erase_count[page as usize] += 1;
+            Ok(())
+        }
+    }
+
+    fn settings(led: u8) -> Settings {
+        Settings {
+            sensor: SensorSettings {
+                led_amplitude: led,
+                ..SensorSettings::default()
+            },
+            ..Settings::default()
+        }
+    }
+
+    fn load(flash: &mut RamFlash) -> Settings {
+        SettingsStore::new(flash).load().unwrap()
+    }
+
+    #[test]
+    fn test_record() {
+        let s = Settings {
+            sensor: SensorSettings {
+                led_amplitude: 7,
+                pulse_width: PulseWidth::Us215,
+                sample_rate: SampleRate::Sps200,
+                averaging: SampleAveraging::Sa8,
+                adc_range: AdcRange::Fs4k,
+            },
+            rotation: 3,
+            alarms: AlarmLimits {
+                hr_low_bpm: 40,
+                hr_high_bpm: 180,
+                spo2_low: 85,
+            },
+            theme: Theme::Light,
+            spo2_calibration: Spo2Calibration::Lookup(
+                LookupTable::new(&[(0.4, 100.0), (1.0, 85.5), (2.0, 60.0)]).unwrap(),
+            ),
+            touch_calibration: Some(TouchCalibration {
+                coefficients: [0.002, -0.068, 250.0, 0.09, 0.004, -30.0],
+            }),
+        };
+        let rec = encode_record(42, &s);
+        assert_eq!(decode_record(&rec), Some((42, s)));
+
+        // every single-bit error is caught
+        for i in 0..RECORD_LEN * 8 {
+            let mut bad = rec;
+            bad[i / 8] ^= 1 << (i % 8);
+            assert_eq!(decode_record(&bad), None, "bit {}", i);
+        }
+
+        let s = Settings {
+            spo2_calibration: Spo2Calibration::Polynomial {
+                coefficients: [101.0, -2.5, -20.0],
+                ratio_min: 0.4,
+                ratio_max: 1.8,
+            },
+            ..Settings::default()
+        };
+        assert_eq!(decode_record(&encode_record(1, &s)), Some((1, s)));
+
+        // biggest payload there is
+        let points: Vec<(f32, f32)> = (0..MAX_CALIBRATION_POINTS)
+            .map(|i| (0.2 * (i + 1) as f32, 100.0 - 5.0 * i as f32))
+            .collect();
+        let s = Settings {
+            spo2_calibration: Spo2Calibration::Lookup(LookupTable::new(&points).unwrap()),
+            touch_calibration: Some(TouchCalibration::default()),
+            ..Settings::default()
+        };
+        assert_eq!(decode_record(&encode_record(2, &s)), Some((2, s)));
+    }
+
+    #[test]
+    fn test_version() {
+        let mut rec = encode_record(1, &Settings::default());
+        rec[2] = SETTINGS_VERSION + 1;
+        let crc = crc16(&rec[..RECORD_LEN - 2]);
+        rec[RECORD_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(decode_record(&rec), None);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let alarms = AlarmLimits {
+            hr_low_bpm: 130,
+            hr_high_bpm: 120,
+            spo2_low: 90,
+        };
+        let rec = encode_record(
+            1,
+            &Settings {
+                alarms,
+                ..Settings::default()
+            },
+        );
+        assert_eq!(decode_record(&rec), None);
+
+        let rec = encode_record(
+            1,
+            &Settings {
+                touch_calibration: Some(TouchCalibration {
+                    coefficients: [0.002, -0.068, f32::NAN, 0.09, 0.004, -30.0],
+                }),
+                ..Settings::default()
+            },
+        );
+        assert_eq!(decode_record(&rec), None);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut flash = RamFlash::new();
+        assert_eq!(load(&mut flash), Settings::default());
+
+        let mut store = SettingsStore::new(&mut flash);
+        store.load().unwrap();
+        store.save(&settings(10)).unwrap();
+        store.save(&settings(20)).unwrap();
+        assert_eq!(load(&mut flash), settings(20));
+
+        // saving the same settings again doesn't wear the flash
+        let mut store = SettingsStore::new(&mut flash);
+        store.load().unwrap();
+        store.save(&settings(20)).unwrap();
+        assert_eq!(flash.data[2 * RECORD_LEN], ERASED);
+    }
+
+    #[test]
+    fn test_wear_leveling() {
+        let slots = PAGE_SIZE / RECORD_LEN;
+        let mut flash = RamFlash::new();
+        for i in 0..10 * slots {
+            // reboot every now and then
+            let mut store = SettingsStore::new(&mut flash);
+            store.load().unwrap();
+            for j in 0..3 {
+                store.save(&settings((3 * i + j) as u8)).unwrap();
+            }
+            assert_eq!(load(&mut flash), settings((3 * i + 2) as u8));
+        }
+        // one erase per page worth of saves, spread over both pages
+        let [a, b] = flash.erase_count;
+        assert!(a + b <= 3 * 10 + 1, "{} {}", a, b);
+        assert!((a as isize - b as isize).abs() <= 1, "{} {}", a, b);
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut flash = RamFlash::new();
+        let mut store = SettingsStore::new(&mut flash);
+        store.load().unwrap();
+        store.save(&settings(10)).unwrap();
+        store.save(&settings(20)).unwrap();
+
+        flash.data[RECORD_LEN + 9] ^= 0x40;
+        assert_eq!(load(&mut flash), settings(10));
+        flash.data[9] ^= 0x40;
+        assert_eq!(load(&mut flash), Settings::default());
+
+        // and saving still works, past the bad records
+        let mut store = SettingsStore::new(&mut flash);
+        store.load().unwrap();
+        store.save(&settings(30)).unwrap();
+        assert_eq!(load(&mut flash), settings(30));
+    }
+
+    #[test]
+    fn test_power_loss() {
+        let slots = PAGE_SIZE / RECORD_LEN;
+        let mut flash = RamFlash::new();
+        let mut store = SettingsStore::new(&mut flash);
+        store.load().unwrap();
+        for i in 0..slots {
+            store.save(&settings(i as u8)).unwrap();
+        }
+
+        // page 0 is full, the next save erases page 1 and dies writing to it
+        flash.power_left = Some(RECORD_LEN / 2);
+        let mut store = SettingsStore::new(&mut flash);
+        store.load().unwrap();
+        assert_eq!(store.save(&settings(100)), Err(RamFlashError::PowerLoss));
+        flash.power_left = None;
+        assert_eq!(load(&mut flash), settings(slots as u8 - 1));
+
+        let mut store = SettingsStore::new(&mut flash);
+        store.load().unwrap();
+        store.save(&settings(101)).unwrap();
+        assert_eq!(load(&mut flash), settings(101));
+    }
+}
@@ -22,170 +22,302 @@ impl Heartbeat {
     }
 }
 
-pub struct HeartbeatItr<'a, const N: usize> {
-    deriv_itr: DerivItr<'a, N>,
-    high: Option<DerivItrItem>,
+/// Fraction of the steepest drop envelope a heartbeat has to exceed,
+/// systolic drops are about as steep beat to beat, noise isn't
+const SLOPE_RATIO: f32 = 0.5;
+
+/// Heartbeat detector, takes one sample at a time.
+///
+/// Heartbeat indices are absolute sample numbers, counted from the
+/// first sample the detector has seen. The high is the highest peak
+/// since the last beat, the low the bottom of the drop after it.
+///
+/// Small amplitude transitions are rejected with an adaptive threshold:
+/// a fraction of the beat amplitude envelope, which slowly decays so that
+/// detection recovers when the signal gets weaker. The steepest drop of
+/// a transition has to be at least half of the steepest drop envelope,
+/// a slow decline between beats isn't a beat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeartbeatDetector {
+    threshold_ratio: f32,
+    envelope_decay: f32,
+    envelope: f32,
+    slope_envelope: f32,
+    /// Steepest drop since `high`
+    steepest: f32,
+
+    /// Absolute index of the next sample
+    idx: usize,
+    last_sample: Option<f32>,
     last_deriv: Option<f32>,
+    high: Option<Deriv>,
 }
 
-impl<'a, const N: usize> HeartbeatItr<'a, N> {
-    pub fn new(data: &'a [f32; N]) -> Self {
-        HeartbeatItr {
-            deriv_itr: DerivItr::new(data),
-            high: None,
+impl HeartbeatDetector {
+    /// `threshold_ratio`: fraction of the amplitude envelope a
+    /// heartbeat has to exceed.
+    /// `envelope_decay`: per-sample amplitude envelope decay factor.
+    pub fn new(threshold_ratio: f32, envelope_decay: f32) -> Self {
+        HeartbeatDetector {
+            threshold_ratio,
+            envelope_decay,
+            envelope: 0.0,
+            slope_envelope: 0.0,
+            steepest: 0.0,
+            idx: 0,
+            last_sample: None,
             last_deriv: None,
+            high: None,
         }
     }
-}
 
-impl<'a, const N: usize> Iterator for HeartbeatItr<'a, N> {
-    type Item = Heartbeat;
+    /// Number of samples seen so far, also the index of the next one
+    pub fn sample_count(&self) -> usize {
+        self.idx
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let d = self.deriv_itr.next()?;
+    /// Current adaptive threshold
+    pub fn threshold(&self) -> f32 {
+        self.envelope * self.threshold_ratio
+    }
 
-            let last_deriv = match self.last_deriv {
-                Some(ld) => ld,
-                None => {
-                    self.last_deriv = Some(d.deriv);
-                    continue;
-                }
-            };
+    pub fn update(&mut self, sample: f32) -> Option<Heartbeat> {
+        let idx = self.idx;
+        self.idx = self.idx.wrapping_add(1);
+        self.envelope *= self.envelope_decay;
+        self.slope_envelope *= self.envelope_decay;
 
-            self.last_deriv = Some(d.deriv);
-
-            if d.deriv >= 0.0 {
-                if last_deriv >= 0.0 {
-                    self.high = Some(d);
-                } else {
-                    match self.high {
-                        Some(h) => {
-                            break Some(Heartbeat {
-                                high_idx: h.idx,
-                                high_value: h.sample,
-                                low_idx: d.idx,
-                                low_value: d.sample,
-                            })
-                        }
-                        None => continue,
-                    }
-                }
+        let last_sample = self.last_sample.replace(sample)?;
+        let d = Deriv {
+            idx: idx.wrapping_sub(1),
+            sample: last_sample,
+            deriv: sample - last_sample,
+        };
+
+        let last_deriv = self.last_deriv.replace(d.deriv)?;
+
+        if d.deriv < 0.0 {
+            // a peak, unless a higher one is still waiting for its low
+            if last_deriv >= 0.0 && !self.high.is_some_and(|h| d.sample < h.sample) {
+                self.high = Some(d);
+                self.steepest = 0.0;
             }
+            self.steepest = self.steepest.max(-d.deriv);
+            return None;
+        }
+
+        if last_deriv >= 0.0 {
+            return None;
+        }
+
+        let h = self.high?;
+        let hb = Heartbeat {
+            high_idx: h.idx,
+            high_value: h.sample,
+            low_idx: d.idx,
+            low_value: d.sample,
+        };
+
+        let hb_val_diff = hb.high_value - hb.low_value;
+        self.envelope = self.envelope.max(hb_val_diff);
+        self.slope_envelope = self.slope_envelope.max(self.steepest);
+
+        // Ignore small amplitude "wiggles" and slow declines, keep the
+        // high point, the real low may be further down.
+        let steep = self.steepest > self.slope_envelope * SLOPE_RATIO;
+        if steep && hb_val_diff > self.threshold() {
+            self.high = None;
+            Some(hb)
+        } else {
+            None
         }
     }
 }
 
 /// Sample, its index and a first derivative
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub struct DerivItrItem {
+struct Deriv {
     idx: usize,
     sample: f32,
     deriv: f32,
 }
 
-pub struct DerivItr<'a, const N: usize> {
-    data: &'a [f32; N],
-    idx: usize,
-}
-
-impl<'a, const N: usize> DerivItr<'a, N> {
-    pub fn new(data: &'a [f32; N]) -> Self {
-        DerivItr { data, idx: 0 }
-    }
-}
-
-impl<'a, const N: usize> Iterator for DerivItr<'a, N> {
-    type Item = DerivItrItem;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.idx >= N - 1 {
-            None
-        } else {
-            let i = self.idx;
-            self.idx += 1;
-
-            let s = self.data[i];
-            let s1 = self.data[i + 1];
-            Some(DerivItrItem {
-                idx: i,
-                sample: s,
-                deriv: s1 - s,
-            })
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        filter::BandPass,
+        synth::{PpgConfig, PpgSynth},
+    };
 
     #[test]
-    fn test_deriv_itr() {
-        let data = [1.0, 1.0, 1.0];
-        let mut ditr = DerivItr::new(&data);
-        assert_eq!(
-            ditr.next(),
-            Some(DerivItrItem {
-                idx: 0,
-                sample: 1.0,
-                deriv: 0.0
-            })
-        );
+    fn test_heartbeat_detector_peaks() {
+        let data = [
+            1.0, 2.0, 3.0, 4.0, 5.0, 4.0, 3.0, 4.0, 5.0, 6.0, 4.0, 2.0, 0.0, 1.0,
+        ];
+
+        let mut det = HeartbeatDetector::new(0.0, 1.0);
+        let hbs: Vec<Heartbeat> = data.iter().filter_map(|s| det.update(*s)).collect();
         assert_eq!(
-            ditr.next(),
-            Some(DerivItrItem {
-                idx: 1,
-                sample: 1.0,
-                deriv: 0.0
-            })
+            hbs,
+            vec![
+                Heartbeat {
+                    high_idx: 4,
+                    high_value: 5.0,
+                    low_idx: 6,
+                    low_value: 3.0
+                },
+                Heartbeat {
+                    high_idx: 9,
+                    high_value: 6.0,
+                    low_idx: 12,
+                    low_value: 0.0
+                }
+            ]
         );
+        assert_eq!(det.sample_count(), data.len());
+    }
+
+    #[test]
+    fn test_heartbeat_detector_absolute_idx() {
+        let data = [0.0, 1.0, 2.0, 3.0, 2.0, 1.0, 0.0, 1.0];
+
+        let mut det = HeartbeatDetector::new(0.5, 1.0);
+        let mut hbs = Vec::new();
+        for _ in 0..3 {
+            for s in data.iter() {
+                hbs.extend(det.update(*s));
+            }
+        }
 
-        let data = [1.0, 2.0, 1.0];
-        let mut ditr = DerivItr::new(&data);
         assert_eq!(
-            ditr.next(),
-            Some(DerivItrItem {
-                idx: 0,
-                sample: 1.0,
-                deriv: 1.0
-            })
+            hbs.iter().map(|hb| hb.high_idx).collect::<Vec<_>>(),
+            vec![3, 11, 19]
         );
         assert_eq!(
-            ditr.next(),
-            Some(DerivItrItem {
-                idx: 1,
-                sample: 2.0,
-                deriv: -1.0
-            })
+            hbs.iter().map(|hb| hb.low_idx).collect::<Vec<_>>(),
+            vec![6, 14, 22]
         );
     }
 
     #[test]
-    fn test_heartbeat_itr() {
+    fn test_heartbeat_detector_threshold() {
+        // a beat to set the envelope, then a large beat with a small
+        // wiggle on the way down: one beat, from the top to the bottom
         let data = [
-            1.0, 2.0, 3.0, 4.0, 5.0, 4.0, 3.0, 4.0, 5.0, 6.0, 4.0, 2.0, 0.0, 1.0,
+            0.0, 5.0, 10.0, 5.0, 0.0, 5.0, 10.0, 9.0, 5.0, 5.5, 6.0, 5.0, 0.0, 5.0,
         ];
 
-        let mut hbitr = HeartbeatItr::new(&data);
+        let mut det = HeartbeatDetector::new(0.5, 1.0);
+        let hbs: Vec<Heartbeat> = data.iter().filter_map(|s| det.update(*s)).collect();
         assert_eq!(
-            hbitr.next(),
-            Some(Heartbeat {
-                high_idx: 3,
-                high_value: 4.0,
-                low_idx: 6,
-                low_value: 3.0
-            })
+            hbs,
+            vec![
+                Heartbeat {
+                    high_idx: 2,
+                    high_value: 10.0,
+                    low_idx: 4,
+                    low_value: 0.0
+                },
+                Heartbeat {
+                    high_idx: 6,
+                    high_value: 10.0,
+                    low_idx: 12,
+                    low_value: 0.0
+                }
+            ]
         );
+    }
 
-        assert_eq!(
-            hbitr.next(),
-            Some(Heartbeat {
-                high_idx: 8,
-                high_value: 5.0,
-                low_idx: 12,
-                low_value: 0.0
-            })
-        );
+    #[test]
+    fn test_heartbeat_detector_envelope_decay() {
+        let beat = |amp: f32| [0.0, amp, 2.0 * amp, amp, 0.0];
+
+        let mut det = HeartbeatDetector::new(0.5, 0.9);
+        let mut hbs = 0;
+        for s in beat(100.0).iter() {
+            hbs += det.update(*s).iter().count();
+        }
+        hbs += det.update(1.0).iter().count();
+        assert_eq!(hbs, 1);
+
+        // much weaker signal is picked up once the envelope decays
+        for _ in 0..20 {
+            for s in beat(1.0).iter() {
+                hbs += det.update(*s).iter().count();
+            }
+        }
+        assert!(hbs > 10, "{}", hbs);
+        assert!(det.threshold() < 1.0);
+    }
+
+    /// Mean beat interval found by the detector in a band-pass filtered
+    /// synthetic signal, after the filter settles. Intervals more than
+    /// 20% off the median (missed or spurious beats) are left out.
+    fn detected_bpm(cfg: PpgConfig) -> f32 {
+        let mut filter = BandPass::new(cfg.sample_rate_hz, 0.5, 4.0);
+        let mut det = HeartbeatDetector::new(0.25, 0.993);
+        let mut beats = Vec::new();
+        for (i, s) in PpgSynth::new(cfg).take(25 * 60).enumerate() {
+            let x = s.sample.ir as f32;
+            if i == 0 {
+                filter.reset_to(x);
+            }
+            if let Some(hb) = det.update(filter.process(x)) {
+                beats.push(hb.low_idx);
+            }
+        }
+
+        let mut intervals: Vec<usize> = beats
+            .iter()
+            .zip(beats.iter().skip(1))
+            .skip(5)
+            .map(|(b0, b1)| b1 - b0)
+            .collect();
+        intervals.sort_unstable();
+        let median = intervals[intervals.len() / 2] as f32;
+        let near: Vec<f32> = intervals
+            .iter()
+            .map(|i| *i as f32)
+            .filter(|i| (i - median).abs() <= 0.2 * median)
+            .collect();
+        let mean = near.iter().sum::<f32>() / near.len() as f32;
+        60.0 * cfg.sample_rate_hz / mean
+    }
+
+    #[test]
+    fn test_heartbeat_detector_heart_rate_range() {
+        for bpm in (40..=260).step_by(20) {
+            let cfg = PpgConfig {
+                heart_rate_bpm: bpm as f32,
+                hrv_ms: 10.0,
+                resp_depth: 0.2,
+                wander: 0.005,
+                noise: 20.0,
+                ..PpgConfig::default()
+            };
+            let detected = detected_bpm(cfg);
+            assert!(
+                (detected - bpm as f32).abs() <= 2.0,
+                "{} bpm, detected {}",
+                bpm,
+                detected
+            );
+        }
+    }
+
+    #[test]
+    fn test_heartbeat_detector_motion() {
+        let cfg = PpgConfig {
+            heart_rate_bpm: 72.0,
+            motion: 0.05,
+            motion_start_s: 20.0,
+            motion_end_s: 30.0,
+            ..PpgConfig::default()
+        };
+
+        // motion burst in the middle doesn't throw the rate off
+        let detected = detected_bpm(cfg);
+        assert!((detected - 72.0).abs() < 4.0, "{}", detected);
     }
 }
@@ -0,0 +1,302 @@
+//! Synthetic PPG signal, host side only (`std` feature)
+//!
+//! Produces raw R/IR sensor samples with a known heart rate and SpO2,
+//! to test the algorithms against ground truth instead of recordings.
+//!
+//! Blood volume goes up with each heartbeat and absorbs more light,
+//! so the raw sensor value drops sharply on systole, then slowly
+//! recovers with a small dicrotic bump.
+
+use crate::fifo::FifoSample;
+use core::f32::consts::PI;
+
+/// Synthetic signal parameters, see [`PpgConfig::default`] for
+/// a clean, steady signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PpgConfig {
+    pub sample_rate_hz: f32,
+
+    pub heart_rate_bpm: f32,
+    /// Standard deviation of beat-to-beat intervals, ms
+    pub hrv_ms: f32,
+
+    /// Target SpO2, %, sets the R channel AC/DC ratio
+    pub spo2: f32,
+
+    /// Raw DC levels
+    pub ir_dc: f32,
+    pub r_dc: f32,
+    /// IR AC amplitude over DC
+    pub perfusion: f32,
+
+    /// Respiration rate and the AC amplitude modulation it causes, 0..1
+    pub resp_rate_bpm: f32,
+    pub resp_depth: f32,
+
+    /// Slow baseline drift, fraction of DC
+    pub wander: f32,
+    pub wander_hz: f32,
+
+    /// Motion artifact amplitude, fraction of DC,
+    /// active between `motion_start_s` and `motion_end_s`
+    pub motion: f32,
+    pub motion_start_s: f32,
+    pub motion_end_s: f32,
+
+    /// Gaussian noise standard deviation, raw counts
+    pub noise: f32,
+
+    /// Same seed, same signal
+    pub seed: u64,
+}
+
+impl Default for PpgConfig {
+    fn default() -> Self {
+        PpgConfig {
+            sample_rate_hz: 25.0,
+            heart_rate_bpm: 60.0,
+            hrv_ms: 0.0,
+            spo2: 97.0,
+            ir_dc: 100_000.0,
+            r_dc: 80_000.0,
+            perfusion: 0.01,
+            resp_rate_bpm: 15.0,
+            resp_depth: 0.0,
+            wander: 0.0,
+            wander_hz: 0.1,
+            motion: 0.0,
+            motion_start_s: 0.0,
+            motion_end_s: 0.0,
+            noise: 0.0,
+            seed: 1,
+        }
+    }
+}
+
+/// Full scale of the 18-bit sensor ADC
+const ADC_MAX: f32 = 262_143.0;
+
+/// Ratio of R and IR AC/DC ratios for a given SpO2, inverse of
+/// the empirical calibration curve `(-45.06 * z + 30.354) * z + 94.845`,
+/// the descending branch (z > 0.34).
+pub fn spo2_to_ratio(spo2: f32) -> f32 {
+    let (a, b, c) = (-45.06, 30.354, 94.845 - spo2.min(99.9));
+    (-b - libm::sqrtf(b * b - 4.0 * a * c)) / (2.0 * a)
+}
+
+/// Single beat shape, `t_s` since the beat onset, peak of 1 shortly after
+/// it: fast systolic rise, then decay over the diastole with a dicrotic
+/// bump. Systole takes about the same time at any rate up to 60bpm.
+fn pulse(t_s: f32, interval_s: f32) -> f32 {
+    let rise_s = 0.15 * interval_s.min(1.0);
+    if t_s < rise_s {
+        let x = t_s / rise_s;
+        return x * x * (3.0 - 2.0 * x);
+    }
+
+    let decay = libm::expf(-(t_s - rise_s) / (0.3 * interval_s));
+    let x = (t_s - 2.5 * rise_s) / (0.5 * rise_s);
+    let dicrotic = 0.15 * libm::expf(-0.5 * x * x);
+    decay + dicrotic
+}
+
+/// xorshift64*, no dependencies and reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn uniform(&mut self) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let x = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        ((x >> 40) as f32 + 0.5) / (1u64 << 24) as f32
+    }
+
+    /// Standard normal, Box-Muller
+    fn gauss(&mut self) -> f32 {
+        let u1 = self.uniform();
+        let u2 = self.uniform();
+        libm::sqrtf(-2.0 * libm::logf(u1)) * libm::cosf(2.0 * PI * u2)
+    }
+}
+
+/// One generated sample and the ground truth behind it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SynthSample {
+    pub sample: FifoSample,
+    /// A beat started at this sample
+    pub beat: bool,
+}
+
+/// Endless stream of synthetic samples
+pub struct PpgSynth {
+    cfg: PpgConfig,
+    rng: Rng,
+    ratio: f32,
+
+    idx: usize,
+    /// position within the current beat, 0..1
+    beat_phase: f32,
+    beat_interval_s: f32,
+    new_beat: bool,
+
+    /// random phases of motion components
+    motion_phases: [f32; 3],
+}
+
+impl PpgSynth {
+    pub fn new(cfg: PpgConfig) -> Self {
+        let mut rng = Rng(cfg.seed.max(1));
+        let motion_phases = [
+            2.0 * PI * rng.uniform(),
+            2.0 * PI * rng.uniform(),
+            2.0 * PI * rng.uniform(),
+        ];
+        let mut synth = PpgSynth {
+            cfg,
+            rng,
+            ratio: spo2_to_ratio(cfg.spo2),
+            idx: 0,
+            beat_phase: 0.0,
+            beat_interval_s: 0.0,
+            new_beat: true,
+            motion_phases,
+        };
+        synth.beat_interval_s = synth.next_interval_s();
+        synth
+    }
+
+    pub fn config(&self) -> &PpgConfig {
+        &self.cfg
+    }
+
+    /// Time of the next sample, s
+    pub fn time_s(&self) -> f32 {
+        self.idx as f32 / self.cfg.sample_rate_hz
+    }
+
+    fn next_interval_s(&mut self) -> f32 {
+        let mean_ms = 60_000.0 / self.cfg.heart_rate_bpm;
+        let ms = mean_ms + self.cfg.hrv_ms * self.rng.gauss();
+        ms.max(mean_ms / 2.0) / 1000.0
+    }
+
+    fn motion(&self, t: f32) -> f32 {
+        if !(self.cfg.motion_start_s..self.cfg.motion_end_s).contains(&t) {
+            return 0.0;
+        }
+        let [p0, p1, p2] = self.motion_phases;
+        self.cfg.motion
+            * (0.5 * libm::sinf(2.0 * PI * 0.7 * t + p0)
+                + 0.3 * libm::sinf(2.0 * PI * 1.9 * t + p1)
+                + 0.2 * libm::sinf(2.0 * PI * 3.1 * t + p2))
+    }
+
+    fn quantize(&mut self, x: f32) -> u32 {
+        let x = x + self.cfg.noise * self.rng.gauss();
+        libm::roundf(x.clamp(0.0, ADC_MAX)) as u32
+    }
+}
+
+impl Iterator for PpgSynth {
+    type Item = SynthSample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let t = self.time_s();
+        let dt = 1.0 / self.cfg.sample_rate_hz;
+
+        let beat = self.new_beat;
+        let resp = libm::sinf(2.0 * PI * self.cfg.resp_rate_bpm / 60.0 * t);
+        let ac = pulse(self.beat_phase * self.beat_interval_s, self.beat_interval_s)
+            * (1.0 + self.cfg.resp_depth * resp);
+
+        // everything that moves the finger affects both channels alike
+        let baseline =
+            1.0 + self.cfg.wander * libm::sinf(2.0 * PI * self.cfg.wander_hz * t) + self.motion(t);
+
+        let ir = self.cfg.ir_dc * (baseline - self.cfg.perfusion * ac);
+        let r = self.cfg.r_dc * (baseline - self.ratio * self.cfg.perfusion * ac);
+        let sample = FifoSample {
+            red: self.quantize(r),
+            ir: self.quantize(ir),
+            green: 0,
+        };
+
+        self.idx += 1;
+        self.new_beat = false;
+        self.beat_phase += dt / self.beat_interval_s;
+        if self.beat_phase >= 1.0 {
+            // the remainder carries over, in the next beat's time scale
+            let next_interval_s = self.next_interval_s();
+            self.beat_phase = (self.beat_phase - 1.0) * self.beat_interval_s / next_interval_s;
+            self.beat_interval_s = next_interval_s;
+            self.new_beat = true;
+        }
+
+        Some(SynthSample { sample, beat })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spo2_to_ratio() {
+        for spo2 in [70.0, 85.0, 95.0, 99.0] {
+            let z = spo2_to_ratio(spo2);
+            assert!(z > 0.33, "{}", z);
+            assert!(((-45.06 * z + 30.354) * z + 94.845 - spo2).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_beat_rate() {
+        for bpm in [40.0, 75.0, 180.0] {
+            let synth = PpgSynth::new(PpgConfig {
+                heart_rate_bpm: bpm,
+                ..PpgConfig::default()
+            });
+            let beats = synth.take(25 * 60).filter(|s| s.beat).count() as f32;
+            assert!((beats - bpm).abs() / bpm < 0.05, "{} {}", bpm, beats);
+        }
+    }
+
+    #[test]
+    fn test_levels() {
+        let cfg = PpgConfig::default();
+        let samples: Vec<FifoSample> = PpgSynth::new(cfg).take(250).map(|s| s.sample).collect();
+
+        let max = samples.iter().map(|s| s.ir).max().unwrap() as f32;
+        let min = samples.iter().map(|s| s.ir).min().unwrap() as f32;
+        assert!(max <= cfg.ir_dc);
+        assert!(((max - min) / cfg.ir_dc - cfg.perfusion).abs() < 0.001);
+
+        // R swings relative to its DC by the SpO2 ratio
+        let r_max = samples.iter().map(|s| s.red).max().unwrap() as f32;
+        let r_min = samples.iter().map(|s| s.red).min().unwrap() as f32;
+        let z = ((r_max - r_min) / cfg.r_dc) / ((max - min) / cfg.ir_dc);
+        assert!((z - spo2_to_ratio(cfg.spo2)).abs() < 0.02, "{}", z);
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let cfg = PpgConfig {
+            hrv_ms: 50.0,
+            noise: 100.0,
+            ..PpgConfig::default()
+        };
+        let a: Vec<SynthSample> = PpgSynth::new(cfg).take(100).collect();
+        let b: Vec<SynthSample> = PpgSynth::new(cfg).take(100).collect();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_clipping() {
+        let samples = PpgSynth::new(PpgConfig {
+            ir_dc: 300_000.0,
+            ..PpgConfig::default()
+        });
+        assert!(samples.take(50).any(|s| s.sample.ir == ADC_MAX as u32));
+    }
+}
@@ -0,0 +1,165 @@
+//! Sample sequence numbers and timestamps
+
+/// Where a sample sits in the sensor stream
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SampleStamp {
+    /// Increments by 1 per sensor sample, lost samples leave gaps.
+    /// Starts at 1, 0 is never assigned to a real sample.
+    pub seq: u32,
+
+    /// Monotonic time, ms
+    pub time_ms: u32,
+}
+
+impl SampleStamp {
+    pub fn zero() -> Self {
+        SampleStamp { seq: 0, time_ms: 0 }
+    }
+}
+
+/// Hands out sample stamps as samples are read from the sensor
+pub struct SampleStamper {
+    next_seq: u32,
+    period_ms: u32,
+}
+
+impl SampleStamper {
+    pub fn new(sample_rate_hz: u32) -> Self {
+        SampleStamper {
+            next_seq: 1,
+            period_ms: 1000 / sample_rate_hz,
+        }
+    }
+
+    /// Sensor was reconfigured, sequence numbers carry on
+    pub fn set_sample_rate(&mut self, sample_rate_hz: u32) {
+        self.period_ms = 1000 / sample_rate_hz;
+    }
+
+    /// Stamps for `num_samples` read at `now_ms`, after `lost` samples were
+    /// dropped by the sensor. Samples are evenly spaced at the sensor
+    /// sample rate, the last one was taken at `now_ms`.
+    pub fn stamp(&mut self, now_ms: u32, lost: usize, num_samples: usize) -> SampleStamps {
+        let first_seq = seq_after(self.next_seq, lost as u32);
+        self.next_seq = seq_after(first_seq, num_samples as u32);
+
+        SampleStamps {
+            seq: first_seq,
+            time_ms: now_ms.wrapping_sub(self.period_ms * (num_samples as u32).saturating_sub(1)),
+            period_ms: self.period_ms,
+            remaining: num_samples,
+        }
+    }
+}
+
+pub struct SampleStamps {
+    seq: u32,
+    time_ms: u32,
+    period_ms: u32,
+    remaining: usize,
+}
+
+impl Iterator for SampleStamps {
+    type Item = SampleStamp;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            None
+        } else {
+            let s = SampleStamp {
+                seq: self.seq,
+                time_ms: self.time_ms,
+            };
+            self.remaining -= 1;
+            self.seq = seq_after(self.seq, 1);
+            self.time_ms = self.time_ms.wrapping_add(self.period_ms);
+            Some(s)
+        }
+    }
+}
+
+/// Sequence number `n` samples after `seq`, 0 is skipped on wrap
+fn seq_after(seq: u32, n: u32) -> u32 {
+    let (next, wrapped) = seq.overflowing_add(n);
+    if wrapped || next == 0 {
+        next.wrapping_add(1)
+    } else {
+        next
+    }
+}
+
+/// Samples missing between two consecutive sequence numbers
+pub fn seq_gap(last_seq: u32, seq: u32) -> u32 {
+    let gap = seq.wrapping_sub(last_seq).wrapping_sub(1);
+    if seq < last_seq {
+        // wrapped past 0, which no sample gets
+        gap.wrapping_sub(1)
+    } else {
+        gap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(seq: u32, time_ms: u32) -> SampleStamp {
+        SampleStamp { seq, time_ms }
+    }
+
+    #[test]
+    fn test_stamps() {
+        let mut st = SampleStamper::new(25);
+        assert_eq!(
+            st.stamp(1000, 0, 1).collect::<Vec<_>>(),
+            vec![stamp(1, 1000)]
+        );
+        assert_eq!(
+            st.stamp(1120, 0, 3).collect::<Vec<_>>(),
+            vec![stamp(2, 1040), stamp(3, 1080), stamp(4, 1120)]
+        );
+        assert_eq!(st.stamp(1130, 0, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_lost_samples() {
+        let mut st = SampleStamper::new(25);
+        let _ = st.stamp(1000, 0, 1);
+        assert_eq!(
+            st.stamp(1200, 3, 2).collect::<Vec<_>>(),
+            vec![stamp(5, 1160), stamp(6, 1200)]
+        );
+
+        assert_eq!(seq_gap(1, 2), 0);
+        assert_eq!(seq_gap(1, 5), 3);
+        assert_eq!(seq_gap(u32::MAX, 1), 0);
+        assert_eq!(seq_gap(u32::MAX - 1, 2), 2);
+    }
+
+    #[test]
+    fn test_seq_wrap() {
+        let mut st = SampleStamper::new(25);
+        st.next_seq = u32::MAX - 1;
+        assert_eq!(
+            st.stamp(1120, 0, 3).collect::<Vec<_>>(),
+            vec![
+                stamp(u32::MAX - 1, 1040),
+                stamp(u32::MAX, 1080),
+                stamp(1, 1120)
+            ]
+        );
+
+        // a jump over lost samples skips 0 too
+        st.next_seq = u32::MAX;
+        assert_eq!(
+            st.stamp(1200, 1, 1).collect::<Vec<_>>(),
+            vec![stamp(1, 1200)]
+        );
+        st.next_seq = u32::MAX;
+        assert_eq!(
+            st.stamp(1240, 2, 1).collect::<Vec<_>>(),
+            vec![stamp(2, 1240)]
+        );
+        assert_eq!(seq_gap(u32::MAX, 2), 1);
+    }
+}
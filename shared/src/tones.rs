@@ -0,0 +1,300 @@
+//! Beeper tones: a tick on every heartbeat and alarm patterns
+//!
+//! Timing only, the firmware asks [`ToneSequencer::tone`] what should be
+//! sounding every few ms and drives the beeper with it. Alarm patterns
+//! follow the IEC 60601-1-8 burst shapes, 10 pulses for high priority,
+//! 3 for medium and 2 for low, so they can be told apart by ear.
+
+/// Pulse tone length
+pub const BEAT_TONE_MS: u32 = 40;
+
+/// Pulse tone pitch at 100% SpO2, it drops a semitone per % below,
+/// like on clinical oximeters, a falling SpO2 can be heard
+pub const BEAT_PITCH_MAX_HZ: f32 = 880.0;
+/// Pitch stops dropping here
+pub const BEAT_PITCH_MIN_SPO2: f32 = 70.0;
+/// Pulse tone without an SpO2 reading
+pub const BEAT_PITCH_UNKNOWN_HZ: u16 = 600;
+
+/// Pulse tone pitch for an SpO2 reading, %
+pub fn beat_pitch_hz(spo2: Option<f32>) -> u16 {
+    match spo2 {
+        Some(spo2) => {
+            let below = 100.0 - spo2.clamp(BEAT_PITCH_MIN_SPO2, 100.0);
+            libm::roundf(BEAT_PITCH_MAX_HZ * libm::exp2f(-below / 12.0)) as u16
+        }
+        None => BEAT_PITCH_UNKNOWN_HZ,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlarmTone {
+    Low,
+    Medium,
+    High,
+}
+
+/// One beep, from the start of the pattern, ms
+struct Pulse {
+    start: u16,
+    duration: u16,
+}
+
+const fn pulse(start: u16, duration: u16) -> Pulse {
+    Pulse { start, duration }
+}
+
+struct Pattern {
+    freq_hz: u16,
+    pulses: &'static [Pulse],
+    /// Pattern repeats this often, ms
+    period_ms: u32,
+}
+
+/// 3 + 2 pulses, twice
+const HIGH: Pattern = Pattern {
+    freq_hz: 988,
+    pulses: &[
+        pulse(0, 100),
+        pulse(150, 100),
+        pulse(300, 100),
+        pulse(600, 100),
+        pulse(750, 100),
+        pulse(1500, 100),
+        pulse(1650, 100),
+        pulse(1800, 100),
+        pulse(2100, 100),
+        pulse(2250, 100),
+    ],
+    period_ms: 6000,
+};
+
+const MEDIUM: Pattern = Pattern {
+    freq_hz: 784,
+    pulses: &[pulse(0, 180), pulse(250, 180), pulse(500, 180)],
+    period_ms: 10_000,
+};
+
+const LOW: Pattern = Pattern {
+    freq_hz: 659,
+    pulses: &[pulse(0, 250), pulse(350, 250)],
+    period_ms: 20_000,
+};
+
+impl AlarmTone {
+    fn pattern(self) -> &'static Pattern {
+        match self {
+            AlarmTone::Low => &LOW,
+            AlarmTone::Medium => &MEDIUM,
+            AlarmTone::High => &HIGH,
+        }
+    }
+}
+
+impl Pattern {
+    /// `t` ms into the pattern
+    fn tone_at(&self, t: u32) -> Option<u16> {
+        let t = t % self.period_ms;
+        self.pulses
+            .iter()
+            .any(|p| (p.start as u32..(p.start + p.duration) as u32).contains(&t))
+            .then_some(self.freq_hz)
+    }
+}
+
+/// What the beeper plays when. Alarms take precedence, pulse
+/// ticks only sound in between alarm beeps.
+pub struct ToneSequencer {
+    pulse_tone: bool,
+    /// start, pitch
+    beat: Option<(u32, u16)>,
+    /// start
+    alarm: Option<(AlarmTone, u32)>,
+}
+
+impl ToneSequencer {
+    pub fn new() -> Self {
+        ToneSequencer {
+            pulse_tone: true,
+            beat: None,
+            alarm: None,
+        }
+    }
+
+    /// Pulse tone on or off, alarms sound regardless
+    pub fn set_pulse_tone(&mut self, on: bool) {
+        self.pulse_tone = on;
+        if !on {
+            self.beat = None;
+        }
+    }
+
+    pub fn pulse_tone(&self) -> bool {
+        self.pulse_tone
+    }
+
+    /// Heartbeat detected, ticks at the pitch for `spo2`
+    pub fn beat(&mut self, now_ms: u32, spo2: Option<f32>) {
+        if self.pulse_tone {
+            self.beat = Some((now_ms, beat_pitch_hz(spo2)));
+        }
+    }
+
+    /// Pattern to repeat, `None` to stop. Keeps going undisturbed
+    /// if it's the one already playing, a new one starts over.
+    pub fn set_alarm(&mut self, now_ms: u32, alarm: Option<AlarmTone>) {
+        if self.alarm.map(|(a, _)| a) != alarm {
+            self.alarm = alarm.map(|a| (a, now_ms));
+        }
+    }
+
+    pub fn alarm(&self) -> Option<AlarmTone> {
+        self.alarm.map(|(a, _)| a)
+    }
+
+    /// Frequency to play at `now_ms`, `None` for silence
+    pub fn tone(&mut self, now_ms: u32) -> Option<u16> {
+        if let Some((alarm, start)) = self.alarm {
+            if let Some(freq) = alarm.pattern().tone_at(now_ms.wrapping_sub(start)) {
+                return Some(freq);
+            }
+        }
+        let (start, freq) = self.beat?;
+        if now_ms.wrapping_sub(start) < BEAT_TONE_MS {
+            Some(freq)
+        } else {
+            self.beat = None;
+            None
+        }
+    }
+}
+
+impl Default for ToneSequencer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (start, end, freq) of each tone between `from` and `to`,
+    /// polled every `step_ms` like the firmware does
+    fn play(seq: &mut ToneSequencer, from: u32, to: u32, step_ms: u32) -> Vec<(u32, u32, u16)> {
+        let mut tones = Vec::new();
+        let mut current: Option<(u32, u16)> = None;
+        let mut t = from;
+        while t != to {
+            let tone = seq.tone(t);
+            if current.map(|(_, f)| f) != tone {
+                if let Some((start, f)) = current {
+                    tones.push((start, t, f));
+                }
+                current = tone.map(|f| (t, f));
+            }
+            t = t.wrapping_add(step_ms);
+        }
+        if let Some((start, f)) = current {
+            tones.push((start, to, f));
+        }
+        tones
+    }
+
+    #[test]
+    fn test_beat_pitch() {
+        assert_eq!(beat_pitch_hz(Some(100.0)), 880);
+        assert_eq!(beat_pitch_hz(Some(88.0)), 440);
+        assert_eq!(beat_pitch_hz(Some(97.0)), 740);
+        assert_eq!(beat_pitch_hz(Some(60.0)), beat_pitch_hz(Some(70.0)));
+        assert_eq!(beat_pitch_hz(None), BEAT_PITCH_UNKNOWN_HZ);
+
+        // every % lower is a lower tone
+        let mut last = u16::MAX;
+        for spo2 in (70..=100).rev() {
+            let pitch = beat_pitch_hz(Some(spo2 as f32));
+            assert!(pitch < last, "{}", spo2);
+            last = pitch;
+        }
+    }
+
+    #[test]
+    fn test_beat() {
+        let mut seq = ToneSequencer::new();
+        assert_eq!(play(&mut seq, 0, 1000, 1), vec![]);
+
+        seq.beat(1000, Some(100.0));
+        assert_eq!(play(&mut seq, 1000, 1800, 1), vec![(1000, 1040, 880)]);
+        seq.beat(1800, Some(88.0));
+        assert_eq!(play(&mut seq, 1800, 2000, 1), vec![(1800, 1840, 440)]);
+        // across the timer wrapping around
+        seq.beat(u32::MAX - 9, None);
+        assert_eq!(
+            play(&mut seq, u32::MAX - 9, 100, 10),
+            vec![(u32::MAX - 9, 30, 600)]
+        );
+
+        seq.set_pulse_tone(false);
+        seq.beat(3000, Some(95.0));
+        assert_eq!(play(&mut seq, 3000, 4000, 10), vec![]);
+    }
+
+    #[test]
+    fn test_alarm_patterns() {
+        for (alarm, pulses, period) in [
+            (AlarmTone::High, 10, 6000),
+            (AlarmTone::Medium, 3, 10_000),
+            (AlarmTone::Low, 2, 20_000),
+        ] {
+            let mut seq = ToneSequencer::new();
+            seq.set_alarm(500, Some(alarm));
+            // 10ms polling still gets every pulse, at full length
+            let tones = play(&mut seq, 500, 500 + 2 * period, 10);
+            assert_eq!(tones.len(), 2 * pulses, "{:?}", alarm);
+            assert_eq!(tones[0].0, 500);
+            assert_eq!(tones[pulses].0, 500 + period);
+            for (start, end, freq) in tones {
+                assert!(end - start >= 100, "{:?}", alarm);
+                assert_eq!(freq, alarm.pattern().freq_hz);
+            }
+        }
+
+        // different pitches too
+        let pitch = |a: AlarmTone| a.pattern().freq_hz;
+        assert!(pitch(AlarmTone::High) > pitch(AlarmTone::Medium));
+        assert!(pitch(AlarmTone::Medium) > pitch(AlarmTone::Low));
+    }
+
+    #[test]
+    fn test_alarm_changes() {
+        let mut seq = ToneSequencer::new();
+        seq.set_alarm(0, Some(AlarmTone::Medium));
+        assert_eq!(seq.alarm(), Some(AlarmTone::Medium));
+
+        // same alarm again doesn't restart the pattern
+        seq.set_alarm(100, Some(AlarmTone::Medium));
+        assert_eq!(play(&mut seq, 100, 1000, 10)[0], (100, 180, 784));
+
+        // escalation starts the new pattern right away
+        seq.set_alarm(1000, Some(AlarmTone::High));
+        assert_eq!(play(&mut seq, 1000, 1150, 10), vec![(1000, 1100, 988)]);
+
+        seq.set_alarm(1150, None);
+        assert_eq!(seq.alarm(), None);
+        assert_eq!(play(&mut seq, 1150, 8000, 10), vec![]);
+    }
+
+    #[test]
+    fn test_beat_during_alarm() {
+        let mut seq = ToneSequencer::new();
+        seq.set_alarm(0, Some(AlarmTone::Low));
+
+        // beat in the middle of an alarm beep is masked
+        seq.beat(100, Some(100.0));
+        assert_eq!(play(&mut seq, 100, 300, 10), vec![(100, 250, 659)]);
+
+        // and heard in between
+        seq.beat(1000, Some(100.0));
+        assert_eq!(play(&mut seq, 1000, 2000, 10), vec![(1000, 1040, 880)]);
+    }
+}
@@ -0,0 +1,676 @@
+//! XPT2046 resistive touch controller
+//!
+//! The controller measures the panel as two voltage dividers, raw 12-bit
+//! X and Y that depend on the panel and on how it's mounted, and how hard
+//! it is pressed. Readings are noisy, worst when the touch starts and
+//! ends, so each point is a burst of them: sorted, the middle ones
+//! averaged, and the whole point dropped if even those are spread out.
+//!
+//! [`TouchCalibration`] takes raw readings to panel pixels, in the
+//! panel's native portrait orientation, [`panel_to_screen`] then follows
+//! the display rotation. Panels differ unit to unit, [`TouchCalibrator`]
+//! fits one from touches on three targets.
+
+use embedded_hal::{blocking::spi::Transfer, digital::v2::OutputPin};
+
+/// Control byte: start bit, channel, 12-bit differential conversion,
+/// powered down in between with the pen interrupt on
+mod command {
+    pub const X: u8 = 0xd0;
+    pub const Y: u8 = 0x90;
+    pub const Z1: u8 = 0xb0;
+    pub const Z2: u8 = 0xc0;
+}
+
+/// Readings per axis that go into one point
+pub const BURST_LEN: usize = 7;
+/// Lighter touches read wrong, see [`pressure`]
+pub const MIN_PRESSURE: u16 = 300;
+/// Middle readings of a burst further apart than this and the point is
+/// dropped, raw units
+pub const MAX_SPREAD: u16 = 48;
+/// Touches this close outside of the screen count as on its edge, px
+pub const EDGE_MARGIN: f32 = 8.0;
+
+/// Calibration targets, fractions of screen width and height. Far apart
+/// and not on a line, so an error in one doesn't skew the fit much.
+pub const CALIBRATION_TARGETS: [(f32, f32); 3] = [(0.15, 0.15), (0.85, 0.5), (0.3, 0.85)];
+/// A calibration touch has to last this long, ms, brushes don't count
+pub const CALIBRATION_PRESS_MS: u32 = 150;
+
+/// Averaged burst, 12-bit
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RawTouch {
+    pub x: u16,
+    pub y: u16,
+    /// Pressure, see [`pressure`]
+    pub z: u16,
+}
+
+/// Screen pixel, in the current rotation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TouchPoint {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TouchError<E> {
+    Spi(E),
+    ChipSelect,
+}
+
+/// 12-bit conversion result from the two bytes clocked out after the
+/// control byte, MSB first with 3 trailing zero bits
+pub fn decode(bytes: [u8; 2]) -> u16 {
+    (u16::from_be_bytes(bytes) >> 3) & 0x0fff
+}
+
+/// Bigger is harder, 0 with nothing touching
+pub fn pressure(z1: u16, z2: u16) -> u16 {
+    if z1 == 0 {
+        return 0;
+    }
+    (z1 + 4095).saturating_sub(z2)
+}
+
+/// Mean of the middle half of `readings`, `None` if they are too far
+/// apart. Sorts `readings`.
+pub fn filter(readings: &mut [u16]) -> Option<u16> {
+    if readings.is_empty() {
+        return None;
+    }
+    readings.sort_unstable();
+    let quarter = readings.len() / 4;
+    let middle = &readings[quarter..readings.len() - quarter];
+    if middle[middle.len() - 1] - middle[0] > MAX_SPREAD {
+        return None;
+    }
+    let sum: u32 = middle.iter().map(|r| *r as u32).sum();
+    let n = middle.len() as u32;
+    Some(((sum + n / 2) / n) as u16)
+}
+
+pub struct Xpt2046<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+}
+
+impl<SPI, CS, E> Xpt2046<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E>,
+    CS: OutputPin,
+{
+    /// SPI mode 0, 2.5MHz at most
+    pub fn new(spi: SPI, mut cs: CS) -> Self {
+        let _ = cs.set_high();
+        Xpt2046 { spi, cs }
+    }
+
+    /// Filtered point, `None` if the panel isn't pressed firmly or the
+    /// readings were all over the place. Pen interrupt line toggles while
+    /// converting, ignore it until this returns.
+    pub fn read(&mut self) -> Result<Option<RawTouch>, TouchError<E>> {
+        self.cs.set_low().map_err(|_| TouchError::ChipSelect)?;
+        let touch = self.read_burst();
+        self.cs.set_high().map_err(|_| TouchError::ChipSelect)?;
+        touch
+    }
+
+    fn read_burst(&mut self) -> Result<Option<RawTouch>, TouchError<E>> {
+        let z = self.read_pressure()?;
+        if z < MIN_PRESSURE {
+            return Ok(None);
+        }
+
+        let mut xs = [0; BURST_LEN];
+        let mut ys = [0; BURST_LEN];
+        for (x, y) in xs.iter_mut().zip(ys.iter_mut()) {
+            *x = self.convert(command::X)?;
+            *y = self.convert(command::Y)?;
+        }
+
+        // lifted halfway through, the readings trail off
+        if self.read_pressure()? < MIN_PRESSURE {
+            return Ok(None);
+        }
+        Ok(filter(&mut xs)
+            .zip(filter(&mut ys))
+            .map(|(x, y)| RawTouch { x, y, z }))
+    }
+
+    fn read_pressure(&mut self) -> Result<u16, TouchError<E>> {
+        let z1 = self.convert(command::Z1)?;
+        let z2 = self.convert(command::Z2)?;
+        Ok(pressure(z1, z2))
+    }
+
+    fn convert(&mut self, command: u8) -> Result<u16, TouchError<E>> {
+        let mut buf = [command, 0, 0];
+        self.spi.transfer(&mut buf).map_err(TouchError::Spi)?;
+        Ok(decode([buf[1], buf[2]]))
+    }
+}
+
+/// Raw readings to panel pixels, native orientation:
+///
+/// ```text
+/// x = c[0] raw_x + c[1] raw_y + c[2]
+/// y = c[3] raw_x + c[4] raw_y + c[5]
+/// ```
+///
+/// so a panel mounted rotated or mirrored maps just as well.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TouchCalibration {
+    pub coefficients: [f32; 6],
+}
+
+impl TouchCalibration {
+    /// `None` unless all coefficients are finite
+    pub fn new(coefficients: [f32; 6]) -> Option<Self> {
+        coefficients
+            .iter()
+            .all(|c| c.is_finite())
+            .then_some(TouchCalibration { coefficients })
+    }
+
+    /// Raw readings at the panel edges, raw X along panel x
+    pub fn from_edges(x: (f32, f32), y: (f32, f32), panel_size: (u16, u16)) -> Self {
+        let sx = panel_size.0 as f32 / (x.1 - x.0);
+        let sy = panel_size.1 as f32 / (y.1 - y.0);
+        TouchCalibration {
+            coefficients: [sx, 0.0, -x.0 * sx, 0.0, sy, -y.0 * sy],
+        }
+    }
+
+    /// Affine transform taking each of the `raw` readings to the panel
+    /// point at the same index, `None` if the readings are about on a
+    /// line, e.g. the same spot touched twice
+    pub fn from_points(raw: [(f32, f32); 3], panel: [(f32, f32); 3]) -> Option<Self> {
+        // relative to the first point, then it's 2x2
+        let d = |i: usize| (raw[i].0 - raw[0].0, raw[i].1 - raw[0].1);
+        let ((x1, y1), (x2, y2)) = (d(1), d(2));
+        let det = x1 * y2 - x2 * y1;
+        // twice the triangle's area against its longest side squared
+        let longest = [x1 * x1 + y1 * y1, x2 * x2 + y2 * y2];
+        if det.abs() <= 0.05 * longest[0].max(longest[1]) {
+            return None;
+        }
+
+        let solve = |p: [f32; 3]| {
+            let (p1, p2) = (p[1] - p[0], p[2] - p[0]);
+            let a = (p1 * y2 - p2 * y1) / det;
+            let b = (x1 * p2 - x2 * p1) / det;
+            (a, b, p[0] - a * raw[0].0 - b * raw[0].1)
+        };
+        let (a, b, c) = solve([panel[0].0, panel[1].0, panel[2].0]);
+        let (d, e, f) = solve([panel[0].1, panel[1].1, panel[2].1]);
+        TouchCalibration::new([a, b, c, d, e, f])
+    }
+
+    pub fn to_panel(&self, raw: RawTouch) -> (f32, f32) {
+        let [a, b, c, d, e, f] = self.coefficients;
+        let (rx, ry) = (raw.x as f32, raw.y as f32);
+        (a * rx + b * ry + c, d * rx + e * ry + f)
+    }
+
+    /// Screen pixel with the display rotated by `quarter_turns` CCW,
+    /// `None` if it's off the screen
+    pub fn to_screen(
+        &self,
+        raw: RawTouch,
+        panel_size: (u16, u16),
+        quarter_turns: u8,
+    ) -> Option<TouchPoint> {
+        let (x, y) = panel_to_screen(self.to_panel(raw), panel_size, quarter_turns);
+        let (width, height) = screen_size(panel_size, quarter_turns);
+        let clamp = |v: f32, size: u16| {
+            let max = size as f32 - 1.0;
+            (-EDGE_MARGIN..=max + EDGE_MARGIN)
+                .contains(&v)
+                .then(|| libm::roundf(v.clamp(0.0, max)) as i32)
+        };
+        Some(TouchPoint {
+            x: clamp(x, width)?,
+            y: clamp(y, height)?,
+        })
+    }
+}
+
+impl Default for TouchCalibration {
+    /// Rough, for a 240x320 panel with raw readings 200..3900 edge to edge
+    fn default() -> Self {
+        Self::from_edges((200.0, 3900.0), (200.0, 3900.0), (240, 320))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CalibrationState {
+    /// Touch here next, screen pixels, `step` of the three
+    Target {
+        point: TouchPoint,
+        step: usize,
+    },
+    Done(TouchCalibration),
+    /// Touches were about on a line, start over
+    Failed,
+}
+
+/// Three point touch calibration: shows where to touch, averages the
+/// readings of each press, fits the transform
+pub struct TouchCalibrator {
+    panel_size: (u16, u16),
+    quarter_turns: u8,
+    raw: [(f32, f32); 3],
+    /// Targets done
+    done: usize,
+    /// Press so far: start, sums of raw x and y, readings
+    press: Option<(u32, f32, f32, u32)>,
+    result: Option<Option<TouchCalibration>>,
+}
+
+impl TouchCalibrator {
+    /// Targets are placed for the display rotated by `quarter_turns` CCW,
+    /// the result doesn't depend on it
+    pub fn new(panel_size: (u16, u16), quarter_turns: u8) -> Self {
+        TouchCalibrator {
+            panel_size,
+            quarter_turns,
+            raw: [(0.0, 0.0); 3],
+            done: 0,
+            press: None,
+            result: None,
+        }
+    }
+
+    /// Target `i` on the screen
+    fn target(&self, i: usize) -> (f32, f32) {
+        let (w, h) = screen_size(self.panel_size, self.quarter_turns);
+        let (fx, fy) = CALIBRATION_TARGETS[i];
+        (libm::roundf(fx * w as f32), libm::roundf(fy * h as f32))
+    }
+
+    pub fn state(&self) -> CalibrationState {
+        match self.result {
+            Some(Some(calibration)) => CalibrationState::Done(calibration),
+            Some(None) => CalibrationState::Failed,
+            None => {
+                let (x, y) = self.target(self.done);
+                CalibrationState::Target {
+                    point: TouchPoint {
+                        x: x as i32,
+                        y: y as i32,
+                    },
+                    step: self.done,
+                }
+            }
+        }
+    }
+
+    /// Latest reading, `None` when not pressed. A press counts when
+    /// it's released.
+    pub fn update(&mut self, now_ms: u32, touch: Option<RawTouch>) -> CalibrationState {
+        if self.result.is_some() {
+            return self.state();
+        }
+        match (touch, self.press.as_mut()) {
+            (Some(t), Some((_, x, y, n))) => {
+                *x += t.x as f32;
+                *y += t.y as f32;
+                *n += 1;
+            }
+            (Some(t), None) => self.press = Some((now_ms, t.x as f32, t.y as f32, 1)),
+            (None, Some(&mut (start, x, y, n))) => {
+                self.press = None;
+                if now_ms.wrapping_sub(start) >= CALIBRATION_PRESS_MS {
+                    self.raw[self.done] = (x / n as f32, y / n as f32);
+                    self.done += 1;
+                }
+            }
+            (None, None) => (),
+        }
+
+        if self.done == self.raw.len() {
+            let panel = [0, 1, 2]
+                .map(|i| screen_to_panel(self.target(i), self.panel_size, self.quarter_turns));
+            self.result = Some(TouchCalibration::from_points(self.raw, panel));
+        }
+        self.state()
+    }
+}
+
+/// Screen size with the display rotated by `quarter_turns`
+pub fn screen_size(panel_size: (u16, u16), quarter_turns: u8) -> (u16, u16) {
+    match quarter_turns % 4 {
+        0 | 2 => panel_size,
+        _ => (panel_size.1, panel_size.0),
+    }
+}
+
+/// Panel to screen pixels, the display's rotation is `quarter_turns` CCW
+pub fn panel_to_screen(p: (f32, f32), panel_size: (u16, u16), quarter_turns: u8) -> (f32, f32) {
+    let w = panel_size.0 as f32 - 1.0;
+    let h = panel_size.1 as f32 - 1.0;
+    let (x, y) = p;
+    match quarter_turns % 4 {
+        0 => (x, y),
+        1 => (y, w - x),
+        2 => (w - x, h - y),
+        _ => (h - y, x),
+    }
+}
+
+/// Inverse of [`panel_to_screen`]
+pub fn screen_to_panel(p: (f32, f32), panel_size: (u16, u16), quarter_turns: u8) -> (f32, f32) {
+    let w = panel_size.0 as f32 - 1.0;
+    let h = panel_size.1 as f32 - 1.0;
+    let (x, y) = p;
+    match quarter_turns % 4 {
+        0 => (x, y),
+        1 => (w - y, x),
+        2 => (w - x, h - y),
+        _ => (y, h - x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use std::collections::VecDeque;
+
+    const PANEL: (u16, u16) = (240, 320);
+
+    /// Touch at (x, y), pressure readings (z1, z2) in turn
+    struct MockPanel {
+        x: u16,
+        y: u16,
+        pressures: VecDeque<(u16, u16)>,
+        /// added to successive (x, y) readings
+        noise: Vec<i16>,
+        readings: usize,
+        commands: Vec<u8>,
+    }
+
+    impl MockPanel {
+        fn new(x: u16, y: u16, pressures: &[(u16, u16)]) -> Self {
+            MockPanel {
+                x,
+                y,
+                pressures: pressures.iter().copied().collect(),
+                noise: vec![0],
+                readings: 0,
+                commands: Vec::new(),
+            }
+        }
+    }
+
+    impl Transfer<u8> for MockPanel {
+        type Error = ();
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            self.commands.push(words[0]);
+            let noise = self.noise[self.readings % self.noise.len()];
+            let value = match words[0] {
+                command::X => (self.x as i16 + noise) as u16,
+                command::Y => (self.y as i16 + noise) as u16,
+                command::Z1 => self.pressures.front().ok_or(())?.0,
+                command::Z2 => self.pressures.pop_front().ok_or(())?.1,
+                _ => return Err(()),
+            };
+            if words[0] == command::Y {
+                self.readings += 1;
+            }
+            words[1..].copy_from_slice(&(value << 3).to_be_bytes());
+            Ok(words)
+        }
+    }
+
+    struct MockPin;
+
+    impl OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode([0x7f, 0xf8]), 4095);
+        assert_eq!(decode([0x00, 0x08]), 1);
+        assert_eq!(decode([0x40, 0x00]), 2048);
+        assert_eq!(pressure(0, 4095), 0);
+        assert_eq!(pressure(400, 3000), 1495);
+    }
+
+    #[test]
+    fn test_filter() {
+        // outliers at either end don't matter
+        assert_eq!(
+            filter(&mut [2000, 4095, 2010, 2004, 0, 2002, 2006]),
+            Some(2004)
+        );
+        assert_eq!(filter(&mut [1000, 1001]), Some(1001));
+        // too spread out to trust
+        assert_eq!(
+            filter(&mut [1000, 1100, 1200, 1300, 1400, 1500, 1600]),
+            None
+        );
+        assert_eq!(filter(&mut []), None);
+    }
+
+    #[test]
+    fn test_read() {
+        let pressed = (500, 3000);
+        let mut panel = MockPanel::new(1000, 3000, &[pressed, pressed]);
+        // outliers here and there
+        panel.noise = vec![0, 500, -500, 3, -3, 1, -1];
+        let mut touch = Xpt2046::new(panel, MockPin);
+        assert_eq!(
+            touch.read(),
+            Ok(Some(RawTouch {
+                x: 1000,
+                y: 3000,
+                z: 1595,
+            }))
+        );
+        let commands = &touch.spi.commands;
+        assert_eq!(commands.len(), 2 + 2 * BURST_LEN + 2);
+        assert_eq!(commands[2..4], [command::X, command::Y]);
+
+        // too light
+        let panel = MockPanel::new(1000, 3000, &[(100, 4000)]);
+        assert_eq!(Xpt2046::new(panel, MockPin).read(), Ok(None));
+
+        // lifted while reading
+        let panel = MockPanel::new(1000, 3000, &[pressed, (0, 4095)]);
+        assert_eq!(Xpt2046::new(panel, MockPin).read(), Ok(None));
+
+        // noisy
+        let mut panel = MockPanel::new(1000, 3000, &[pressed, pressed]);
+        panel.noise = vec![0, 100, -100, 200, -200, 300, -300];
+        assert_eq!(Xpt2046::new(panel, MockPin).read(), Ok(None));
+
+        let panel = MockPanel::new(1000, 3000, &[]);
+        assert_eq!(
+            Xpt2046::new(panel, MockPin).read(),
+            Err(TouchError::Spi(()))
+        );
+    }
+
+    #[test]
+    fn test_calibration() {
+        let cal = TouchCalibration::from_edges((3900.0, 200.0), (300.0, 3500.0), PANEL);
+        let raw = |x, y| RawTouch { x, y, z: 1000 };
+        assert_eq!(cal.to_panel(raw(3900, 300)), (0.0, 0.0));
+        assert_eq!(cal.to_panel(raw(200, 3500)), (240.0, 320.0));
+        let (x, y) = cal.to_panel(raw(2050, 1900));
+        assert!((x - 120.0).abs() < 1e-3 && (y - 160.0).abs() < 1e-3);
+
+        // landscape, 90 degrees CCW
+        assert_eq!(
+            cal.to_screen(raw(3900, 300), PANEL, 1),
+            Some(TouchPoint { x: 0, y: 239 })
+        );
+        // just off the edge, on it
+        assert_eq!(
+            cal.to_screen(raw(3910, 290), PANEL, 0),
+            Some(TouchPoint { x: 0, y: 0 })
+        );
+        // way off
+        assert_eq!(cal.to_screen(raw(4095, 300), PANEL, 0), None);
+    }
+
+    #[test]
+    fn test_from_points() {
+        let raw = |x, y| RawTouch { x, y, z: 1000 };
+        // panel mounted turned and mirrored, slightly skewed too
+        let known = TouchCalibration {
+            coefficients: [0.002, -0.068, 250.0, 0.09, 0.004, -30.0],
+        };
+        let readings = [(3500.0, 400.0), (600.0, 2000.0), (2200.0, 3600.0)];
+        let panel = readings.map(|(x, y)| known.to_panel(raw(x as u16, y as u16)));
+        let fit = TouchCalibration::from_points(readings, panel).unwrap();
+        for (f, k) in fit.coefficients.iter().zip(known.coefficients.iter()) {
+            assert!((f - k).abs() < 1e-3 * k.abs().max(1.0), "{:?}", fit);
+        }
+        // anywhere else, not just at the points
+        for (x, y) in [(0, 0), (4095, 4095), (1234, 3210)] {
+            let (a, b) = (fit.to_panel(raw(x, y)), known.to_panel(raw(x, y)));
+            assert!((a.0 - b.0).abs() < 0.05 && (a.1 - b.1).abs() < 0.05);
+        }
+
+        let edges = TouchCalibration::from_edges((200.0, 3900.0), (300.0, 3700.0), PANEL);
+        let readings = [(200.0, 300.0), (3900.0, 300.0), (200.0, 3700.0)];
+        let panel = [(0.0, 0.0), (240.0, 0.0), (0.0, 320.0)];
+        let fit = TouchCalibration::from_points(readings, panel).unwrap();
+        for (f, e) in fit.coefficients.iter().zip(edges.coefficients.iter()) {
+            assert!((f - e).abs() < 1e-4, "{:?}", fit);
+        }
+
+        // same spot twice, or all in a row
+        let panel = [(0.0, 0.0), (100.0, 100.0), (200.0, 200.0)];
+        let same = [(1000.0, 1000.0), (1000.0, 1000.0), (3000.0, 3000.0)];
+        assert_eq!(TouchCalibration::from_points(same, panel), None);
+        let line = [(1000.0, 1000.0), (2000.0, 2010.0), (3000.0, 3000.0)];
+        assert_eq!(TouchCalibration::from_points(line, panel), None);
+    }
+
+    /// Reading at screen point `p` on a panel that maps like `panel`,
+    /// axis aligned ones only
+    fn reading_at(panel: &TouchCalibration, p: TouchPoint, quarter_turns: u8) -> RawTouch {
+        let [a, _, c, _, e, f] = panel.coefficients;
+        let (px, py) = screen_to_panel((p.x as f32, p.y as f32), PANEL, quarter_turns);
+        RawTouch {
+            x: libm::roundf((px - c) / a) as u16,
+            y: libm::roundf((py - f) / e) as u16,
+            z: 1000,
+        }
+    }
+
+    #[test]
+    fn test_calibrator() {
+        let panel = TouchCalibration::from_edges((3800.0, 250.0), (350.0, 3850.0), PANEL);
+        for quarter_turns in 0..4 {
+            let mut calibrator = TouchCalibrator::new(PANEL, quarter_turns);
+            let mut targets = Vec::new();
+
+            // 200ms on each target
+            let mut t = 0;
+            let mut state = calibrator.state();
+            while let CalibrationState::Target { point, step } = state {
+                assert_eq!(step, targets.len());
+                targets.push(point);
+                for _ in 0..10 {
+                    calibrator.update(t, Some(reading_at(&panel, point, quarter_turns)));
+                    t += 20;
+                }
+                state = calibrator.update(t, None);
+                t += 500;
+            }
+            let fit = match state {
+                CalibrationState::Done(fit) => fit,
+                state => panic!("{:?}", state),
+            };
+
+            // targets come out where they were drawn, whatever the rotation
+            for target in targets {
+                let touch = reading_at(&panel, target, quarter_turns);
+                let p = fit.to_screen(touch, PANEL, quarter_turns).unwrap();
+                assert!(
+                    (p.x - target.x).abs() <= 1 && (p.y - target.y).abs() <= 1,
+                    "{} {:?} {:?}",
+                    quarter_turns,
+                    p,
+                    target
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_calibrator_presses() {
+        let mut calibrator = TouchCalibrator::new(PANEL, 1);
+        let touch = |x, y| Some(RawTouch { x, y, z: 1000 });
+
+        // brushing the panel isn't a press
+        calibrator.update(0, touch(500, 500));
+        calibrator.update(100, touch(500, 500));
+        assert!(matches!(
+            calibrator.update(120, None),
+            CalibrationState::Target { step: 0, .. }
+        ));
+
+        // readings of a press are averaged
+        calibrator.update(1000, touch(500, 500));
+        calibrator.update(1100, touch(520, 480));
+        calibrator.update(1200, touch(510, 490));
+        assert!(matches!(
+            calibrator.update(1200, None),
+            CalibrationState::Target { step: 1, .. }
+        ));
+        assert_eq!(calibrator.raw[0], (510.0, 490.0));
+
+        // the same spot for the rest
+        for t in [2000, 3000] {
+            calibrator.update(t, touch(510, 490));
+            calibrator.update(t + 200, None);
+        }
+        assert_eq!(calibrator.state(), CalibrationState::Failed);
+        // and it stays that way
+        assert_eq!(
+            calibrator.update(4000, touch(1000, 1000)),
+            CalibrationState::Failed
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        // corners go where the LCD puts them, see `Lcd::lcd_window_point`
+        for quarter_turns in 0..4 {
+            let (w, h) = screen_size(PANEL, quarter_turns);
+            let (w, h) = (w as f32 - 1.0, h as f32 - 1.0);
+            for screen in [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h), (10.0, 20.0)] {
+                let panel = screen_to_panel(screen, PANEL, quarter_turns);
+                assert!(
+                    (0.0..240.0).contains(&panel.0) && (0.0..320.0).contains(&panel.1),
+                    "{} {:?}",
+                    quarter_turns,
+                    panel
+                );
+                assert_eq!(panel_to_screen(panel, PANEL, quarter_turns), screen);
+            }
+        }
+        assert_eq!(screen_size(PANEL, 1), (320, 240));
+        // 90 CCW, screen top left is the panel's top right
+        assert_eq!(screen_to_panel((0.0, 0.0), PANEL, 1), (239.0, 0.0));
+        assert_eq!(screen_to_panel((0.0, 0.0), PANEL, 3), (0.0, 319.0));
+        assert_eq!(screen_to_panel((0.0, 0.0), PANEL, 2), (239.0, 319.0));
+    }
+}
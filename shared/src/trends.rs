@@ -0,0 +1,175 @@
+//! Vital sign trends, heart rate and SpO2 averaged over fixed intervals
+//!
+//! Readings come in as often as the firmware loop runs, each interval
+//! keeps the mean of whatever valid readings it got. Intervals without
+//! any (no finger, poor signal) are kept as gaps.
+
+use crate::circ::Circ;
+
+/// Trend points kept, one screen pixel each
+pub const TREND_NUM_POINTS: usize = 270;
+
+/// 45 minutes worth of `TREND_NUM_POINTS`
+pub const TREND_INTERVAL_MS: u32 = 10_000;
+
+/// Interval means, rounded. `None` where there were no readings.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrendPoint {
+    pub heart_rate_bpm: Option<u8>,
+    pub spo2: Option<u8>,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Mean {
+    sum: f32,
+    count: u32,
+}
+
+impl Mean {
+    fn add(&mut self, value: Option<f32>) {
+        if let Some(v) = value {
+            self.sum += v;
+            self.count += 1;
+        }
+    }
+
+    fn take(&mut self) -> Option<u8> {
+        let mean = (self.count > 0).then(|| libm::roundf(self.sum / self.count as f32));
+        *self = Mean::default();
+        mean.map(|m| m.clamp(0.0, u8::MAX as f32) as u8)
+    }
+}
+
+/// Last `N` intervals of readings
+pub struct Trends<const N: usize> {
+    interval_ms: u32,
+    points: Circ<TrendPoint, N>,
+    /// Start of the interval being averaged, `None` before the first reading
+    start_ms: Option<u32>,
+    heart_rate: Mean,
+    spo2: Mean,
+}
+
+impl<const N: usize> Trends<N> {
+    pub fn new(interval_ms: u32) -> Self {
+        Trends {
+            interval_ms,
+            points: Circ::new(TrendPoint::default()),
+            start_ms: None,
+            heart_rate: Mean::default(),
+            spo2: Mean::default(),
+        }
+    }
+
+    pub fn interval_ms(&self) -> u32 {
+        self.interval_ms
+    }
+
+    /// Latest readings, `None` when there's no valid one. A pause longer
+    /// than an interval between calls leaves gaps in the trend.
+    pub fn update(&mut self, now_ms: u32, heart_rate_bpm: Option<f32>, spo2: Option<f32>) {
+        let start_ms = *self.start_ms.get_or_insert(now_ms);
+        let elapsed = now_ms.wrapping_sub(start_ms);
+        if elapsed >= self.interval_ms {
+            let intervals = elapsed / self.interval_ms;
+            self.points.add(TrendPoint {
+                heart_rate_bpm: self.heart_rate.take(),
+                spo2: self.spo2.take(),
+            });
+            // nothing to average over in the skipped ones
+            for _ in 1..intervals.min(N as u32) {
+                self.points.add(TrendPoint::default());
+            }
+            self.start_ms = Some(start_ms.wrapping_add(intervals * self.interval_ms));
+        }
+        self.heart_rate.add(heart_rate_bpm);
+        self.spo2.add(spo2);
+    }
+
+    /// Number of finished intervals, at most `N`
+    pub fn len(&self) -> usize {
+        self.points.total_added().min(N)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Changes whenever a point is added
+    pub fn total_added(&self) -> usize {
+        self.points.total_added()
+    }
+
+    /// Finished intervals, oldest first
+    pub fn points(&self) -> impl Iterator<Item = TrendPoint> + '_ {
+        self.points.iter().skip(N - self.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_means() {
+        let mut trends = Trends::<4>::new(1000);
+        assert!(trends.is_empty());
+
+        trends.update(500, Some(60.0), Some(98.0));
+        trends.update(1000, Some(61.0), None);
+        trends.update(1400, Some(62.0), Some(96.0));
+        assert_eq!(trends.len(), 0);
+
+        trends.update(1500, None, Some(95.0));
+        assert_eq!(
+            trends.points().collect::<Vec<_>>(),
+            vec![TrendPoint {
+                heart_rate_bpm: Some(61),
+                spo2: Some(97),
+            }]
+        );
+
+        // no readings at all, a gap
+        trends.update(2000, None, None);
+        trends.update(2500, Some(70.0), None);
+        assert_eq!(
+            trends.points().collect::<Vec<_>>(),
+            vec![
+                TrendPoint {
+                    heart_rate_bpm: Some(61),
+                    spo2: Some(97),
+                },
+                TrendPoint {
+                    heart_rate_bpm: None,
+                    spo2: Some(95),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut trends = Trends::<4>::new(1000);
+        trends.update(0, Some(60.0), Some(97.0));
+        trends.update(3500, Some(70.0), Some(90.0));
+        let points = trends.points().collect::<Vec<_>>();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].heart_rate_bpm, Some(60));
+        assert_eq!(points[1], TrendPoint::default());
+        assert_eq!(points[2], TrendPoint::default());
+
+        // interval boundaries stay put
+        trends.update(3999, None, None);
+        assert_eq!(trends.len(), 3);
+        trends.update(4000, None, None);
+        assert_eq!(trends.len(), 4);
+        assert_eq!(trends.points().last().unwrap().heart_rate_bpm, Some(70));
+
+        // only the last N are kept
+        let total = trends.total_added();
+        trends.update(100_000, Some(80.0), None);
+        assert_eq!(trends.len(), 4);
+        assert_eq!(trends.total_added(), total + 4);
+        assert!(trends.points().all(|p| p == TrendPoint::default()));
+    }
+}